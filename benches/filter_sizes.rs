@@ -0,0 +1,49 @@
+use std::time::Instant;
+
+use pngme::{
+    encoder::{Encoder, FilterStrategy},
+    filter::FilterType,
+    png_util::read_png,
+};
+
+/*
+ * Compares the encoded size of data/png/rust_crab.png under each filter
+ * strategy. Run with `cargo bench`
+ */
+
+fn main() {
+    let png = read_png("./data/png/rust_crab.png").expect("failed to read rust_crab.png");
+    let pixels = png.decode_pixels().expect("failed to decode rust_crab.png");
+    let original: usize = png.image_data().len();
+
+    let strategies = [
+        ("fixed none", FilterStrategy::Fixed(FilterType::None)),
+        ("fixed sub", FilterStrategy::Fixed(FilterType::Sub)),
+        ("fixed up", FilterStrategy::Fixed(FilterType::Up)),
+        ("fixed average", FilterStrategy::Fixed(FilterType::Average)),
+        ("fixed paeth", FilterStrategy::Fixed(FilterType::Paeth)),
+        ("min sum abs", FilterStrategy::MinSumAbs),
+        ("brute force", FilterStrategy::BruteForce),
+        ("entropy", FilterStrategy::Entropy),
+    ];
+
+    println!(
+        "rust_crab.png: {}x{}, original IDAT {} bytes",
+        pixels.width, pixels.height, original
+    );
+    println!("{:<14} {:>10} {:>10}", "strategy", "IDAT bytes", "time");
+    for (name, strategy) in strategies.iter() {
+        let start = Instant::now();
+        let encoded = Encoder::from_pixels(pixels.clone())
+            .with_filter_strategy(*strategy)
+            .encode()
+            .expect("failed to encode");
+        let elapsed = start.elapsed();
+        println!(
+            "{:<14} {:>10} {:>8.1?}",
+            name,
+            encoded.image_data().len(),
+            elapsed
+        );
+    }
+}
@@ -0,0 +1,961 @@
+use std::{convert::TryFrom, io::Write};
+
+use crate::{
+    chunk::Chunk,
+    chunk_spec::{
+        be_u32, BlendOp, ChunkSpec, Chunk_IDAT, Chunk_IHDR, Chunk_acTL, Chunk_fcTL, Chunk_fdAT,
+        ColorType, DisposeOp,
+    },
+    decoder::{decode_stream, PixelBuffer},
+    encoder::{check_buffer, encode_image_data, Encoder, FilterStrategy, IDAT_CHUNK_SIZE},
+    error::invalid_field,
+    png::Png,
+    PngError, Result,
+};
+
+/*
+ * Animated PNG: acTL announces the animation, each frame is introduced by an
+ * fcTL and carries its pixels in fdAT chunks (or the IDAT, for a first frame
+ * whose fcTL precedes it). Frames are drawn in turn onto an RGBA canvas the
+ * size of the image, so every frame yields a complete picture.
+ * `AnimationEncoder` writes the same layout from a list of frames
+ */
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Frame {
+    pub control: Chunk_fcTL,
+    // The frame's own pixels, as stored in the file
+    pub region: PixelBuffer,
+    // The whole canvas once the frame has been drawn, as 8 bit RGBA
+    pub image: PixelBuffer,
+}
+
+impl Frame {
+    // A still PNG showing the canvas as it looks during this frame
+    pub fn to_png(&self) -> Result<Png> {
+        return Encoder::from_pixels(self.image.clone()).encode();
+    }
+}
+
+// A frame control or data chunk out of step with the chunks around it
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub(crate) struct SequenceError {
+    pub chunk: usize,
+    pub chunk_name: &'static str,
+    pub field: &'static str,
+    pub reason: String,
+}
+
+impl From<SequenceError> for PngError {
+    fn from(e: SequenceError) -> PngError {
+        invalid_field(e.chunk_name, e.field, e.reason)
+    }
+}
+
+// fcTL and fdAT chunks share one sequence, which must count up from 0 in
+// file order, and there must be one fcTL per frame acTL declares. An fcTL
+// ahead of IDAT makes the still image a frame, so it must cover the whole
+// image from offset 0
+pub(crate) fn sequence_errors(png: &Png) -> Vec<SequenceError> {
+    let mut errors: Vec<SequenceError> = Vec::new();
+    let mut expected: u32 = 0;
+    let mut controls: u32 = 0;
+    let mut actl: Option<usize> = None;
+    let mut seen_idat = false;
+    let ihdr = png.ihdr().ok();
+    for (i, chunk) in png.chunks().iter().enumerate() {
+        let chunk_name = match chunk.chunk_type().bytes() {
+            Chunk_acTL::HEADER => {
+                actl = actl.or(Some(i));
+                continue;
+            }
+            Chunk_IDAT::HEADER => {
+                seen_idat = true;
+                continue;
+            }
+            Chunk_fcTL::HEADER => {
+                controls += 1;
+                let control = Chunk_fcTL::decode(chunk).ok();
+                if let (false, Some(ihdr), Some(control)) = (seen_idat, ihdr, control) {
+                    if let Some(reason) = still_frame_mismatch(&control, &ihdr) {
+                        errors.push(SequenceError {
+                            chunk: i,
+                            chunk_name: "fcTL",
+                            field: "width",
+                            reason,
+                        });
+                    }
+                }
+                "fcTL"
+            }
+            Chunk_fdAT::HEADER => "fdAT",
+            _ => continue,
+        };
+        let data = chunk.data();
+        if data.len() < 4 {
+            errors.push(SequenceError {
+                chunk: i,
+                chunk_name,
+                field: "sequence_number",
+                reason: String::from("chunk too short for a sequence number"),
+            });
+            continue;
+        }
+        let sequence_number = be_u32(data, 0);
+        if sequence_number != expected {
+            errors.push(SequenceError {
+                chunk: i,
+                chunk_name,
+                field: "sequence_number",
+                reason: format!("expected {}, found {}", expected, sequence_number),
+            });
+        }
+        expected = sequence_number.wrapping_add(1);
+    }
+    if let Some(i) = actl {
+        let declared = png.chunks()[i].data().get(..4).map(|d| be_u32(d, 0));
+        if declared.is_some_and(|n| n != controls) {
+            errors.push(SequenceError {
+                chunk: i,
+                chunk_name: "acTL",
+                field: "num_frames",
+                reason: format!(
+                    "{} frames declared, {} fcTL chunks found",
+                    declared.unwrap(),
+                    controls
+                ),
+            });
+        }
+    }
+    return errors;
+}
+
+// Each frame's control chunk with its compressed image data
+fn frame_streams(png: &Png) -> Result<Vec<(Chunk_fcTL, Vec<u8>)>> {
+    let mut frames: Vec<(Chunk_fcTL, Vec<u8>)> = Vec::new();
+    // Whether the frame being collected takes its data from IDAT
+    let mut from_idat = false;
+    for chunk in png.chunks() {
+        match chunk.chunk_type().bytes() {
+            Chunk_fcTL::HEADER => {
+                frames.push((Chunk_fcTL::decode(chunk)?, Vec::new()));
+                from_idat = false;
+            }
+            Chunk_IDAT::HEADER => {
+                let count = frames.len();
+                if let Some((_, data)) = frames.last_mut() {
+                    if count > 1 || (!from_idat && !data.is_empty()) {
+                        return Err(invalid_field(
+                            "fcTL",
+                            "sequence_number",
+                            String::from("only the first frame may use IDAT"),
+                        ));
+                    }
+                    data.extend_from_slice(chunk.data());
+                    from_idat = true;
+                }
+            }
+            Chunk_fdAT::HEADER => {
+                let fdat = Chunk_fdAT::decode(chunk)?;
+                match frames.last_mut() {
+                    Some((_, data)) if !from_idat => data.extend_from_slice(&fdat.data),
+                    _ => {
+                        return Err(invalid_field(
+                            "fdAT",
+                            "sequence_number",
+                            String::from("frame data without a frame control chunk"),
+                        ))
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    return Ok(frames);
+}
+
+// Draw `src`, a `width` pixel wide RGBA region, onto the canvas at (x0, y0)
+fn blend(canvas: &mut PixelBuffer, src: &[u8], control: &Chunk_fcTL) {
+    let stride = canvas.stride();
+    let width = control.width as usize;
+    for y in 0..control.height as usize {
+        for x in 0..width {
+            let s = &src[(y * width + x) * 4..(y * width + x + 1) * 4];
+            let at = (control.y_offset as usize + y) * stride + (control.x_offset as usize + x) * 4;
+            let d = &mut canvas.data[at..at + 4];
+            match control.blend_op {
+                BlendOp::Source => d.copy_from_slice(s),
+                BlendOp::Over => {
+                    let (sa, da) = (s[3] as u32, d[3] as u32);
+                    // Alpha of the result, scaled by 255
+                    let a = sa * 255 + da * (255 - sa);
+                    if a == 0 {
+                        d.copy_from_slice(&[0; 4]);
+                        continue;
+                    }
+                    for c in 0..3 {
+                        d[c] = ((s[c] as u32 * sa * 255 + d[c] as u32 * da * (255 - sa)) / a) as u8;
+                    }
+                    d[3] = (a / 255) as u8;
+                }
+            }
+        }
+    }
+}
+
+// Why a frame cannot stand for the still image, as the frame ahead of IDAT does
+fn still_frame_mismatch(control: &Chunk_fcTL, ihdr: &Chunk_IHDR) -> Option<String> {
+    if (control.x_offset, control.y_offset) == (0, 0)
+        && (control.width, control.height) == (ihdr.width, ihdr.height)
+    {
+        return None;
+    }
+    return Some(format!(
+        "the frame before IDAT must be {}x{} at (0, 0), found {}x{} at ({}, {})",
+        ihdr.width, ihdr.height, control.width, control.height, control.x_offset, control.y_offset
+    ));
+}
+
+// Frame `n` must lie within the canvas
+fn check_region(n: usize, control: &Chunk_fcTL, ihdr: &Chunk_IHDR) -> Result<()> {
+    let fits =
+        |offset: u32, len: u32, max: u32| offset.checked_add(len).is_some_and(|end| end <= max);
+    if !fits(control.x_offset, control.width, ihdr.width)
+        || !fits(control.y_offset, control.height, ihdr.height)
+    {
+        return Err(invalid_field(
+            "fcTL",
+            "width",
+            format!(
+                "frame {} extends past the {}x{} canvas",
+                n, ihdr.width, ihdr.height
+            ),
+        ));
+    }
+    return Ok(());
+}
+
+// Copy of the canvas rows and columns a frame covers
+fn save_region(canvas: &PixelBuffer, control: &Chunk_fcTL) -> Vec<u8> {
+    let stride = canvas.stride();
+    let (x0, w) = (control.x_offset as usize * 4, control.width as usize * 4);
+    return (0..control.height as usize)
+        .flat_map(|y| {
+            let at = (control.y_offset as usize + y) * stride + x0;
+            canvas.data[at..at + w].iter().copied()
+        })
+        .collect();
+}
+
+impl Png {
+    // The acTL chunk, if this is an animated PNG
+    pub fn animation_control(&self) -> Result<Option<Chunk_acTL>> {
+        return self.get::<Chunk_acTL>();
+    }
+
+    // Every frame of the animation, drawn in order onto a transparent canvas
+    pub fn frames(&self) -> Result<Vec<Frame>> {
+        self.animation_control()?
+            .ok_or_else(|| PngError::ChunkNotFound(String::from("acTL")))?;
+        if let Some(e) = sequence_errors(self).into_iter().next() {
+            return Err(e.into());
+        }
+        let ihdr = self.ihdr()?;
+        let palette = self.palette()?;
+        let trns = self.transparency()?;
+        let streams = frame_streams(self)?;
+
+        let size = (ihdr.width as usize)
+            .checked_mul(ihdr.height as usize)
+            .and_then(|n| n.checked_mul(4))
+            .ok_or_else(|| {
+                PngError::InvalidImageData(format!(
+                    "{}x{} canvas is too large",
+                    ihdr.width, ihdr.height
+                ))
+            })?;
+        let mut canvas = PixelBuffer {
+            width: ihdr.width,
+            height: ihdr.height,
+            color_type: ColorType::RGBA,
+            bit_depth: 8,
+            data: vec![0; size],
+        };
+        let mut frames: Vec<Frame> = Vec::with_capacity(streams.len());
+        for (n, (control, data)) in streams.into_iter().enumerate() {
+            check_region(n, &control, &ihdr)?;
+            let header = Chunk_IHDR {
+                width: control.width,
+                height: control.height,
+                ..ihdr
+            };
+            let region = decode_stream(&data, &header)?;
+            let previous = match control.dispose_op {
+                DisposeOp::Previous => Some(save_region(&canvas, &control)),
+                _ => None,
+            };
+            let rgba = region.to_rgba8(palette.as_ref(), trns.as_ref())?;
+            blend(&mut canvas, &rgba.data, &control);
+            let image = canvas.clone();
+
+            // Clearing the region is the same as drawing transparent black over it
+            let mut clear = control;
+            clear.blend_op = BlendOp::Source;
+            match (control.dispose_op, previous) {
+                (DisposeOp::None, _) => {}
+                // With nothing earlier to return to, the first frame reverts
+                // to the blank canvas
+                (DisposeOp::Previous, Some(saved)) if n > 0 => blend(&mut canvas, &saved, &clear),
+                _ => {
+                    let blank = vec![0; control.width as usize * control.height as usize * 4];
+                    blend(&mut canvas, &blank, &clear);
+                }
+            }
+            frames.push(Frame {
+                control,
+                region,
+                image,
+            });
+        }
+        return Ok(frames);
+    }
+}
+
+// One frame of an animation to be written, with how and for how long it shows
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct AnimationFrame {
+    pub image: PixelBuffer,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    // Display time of delay_num / delay_den seconds
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+}
+
+impl AnimationFrame {
+    // A frame drawn at the top left corner, replacing what is beneath it
+    pub fn new(image: PixelBuffer, delay_num: u16, delay_den: u16) -> AnimationFrame {
+        return AnimationFrame {
+            image,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num,
+            delay_den,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        };
+    }
+
+    pub fn with_offset(mut self, x_offset: u32, y_offset: u32) -> AnimationFrame {
+        self.x_offset = x_offset;
+        self.y_offset = y_offset;
+        return self;
+    }
+
+    pub fn with_dispose(mut self, dispose_op: DisposeOp) -> AnimationFrame {
+        self.dispose_op = dispose_op;
+        return self;
+    }
+
+    pub fn with_blend(mut self, blend_op: BlendOp) -> AnimationFrame {
+        self.blend_op = blend_op;
+        return self;
+    }
+
+    fn control(&self, sequence_number: u32) -> Chunk_fcTL {
+        return Chunk_fcTL {
+            sequence_number,
+            width: self.image.width,
+            height: self.image.height,
+            x_offset: self.x_offset,
+            y_offset: self.y_offset,
+            delay_num: self.delay_num,
+            delay_den: self.delay_den,
+            dispose_op: self.dispose_op,
+            blend_op: self.blend_op,
+        };
+    }
+}
+
+// Builds an animated PNG. The first frame is also the still image that
+// viewers without APNG support show, so it sets the canvas size, colour type
+// and bit depth for every frame
+#[derive(Debug, Clone)]
+pub struct AnimationEncoder {
+    frames: Vec<AnimationFrame>,
+    num_plays: u32,
+    palette: Option<Vec<[u8; 3]>>,
+}
+
+impl AnimationEncoder {
+    pub fn new(frames: Vec<AnimationFrame>) -> AnimationEncoder {
+        return AnimationEncoder {
+            frames,
+            num_plays: 0,
+            palette: None,
+        };
+    }
+
+    // Times to play the animation before stopping on the last frame; 0, the
+    // default, loops forever
+    pub fn with_plays(mut self, num_plays: u32) -> AnimationEncoder {
+        self.num_plays = num_plays;
+        return self;
+    }
+
+    // Shared by every frame; required for colour type 3
+    pub fn with_palette(mut self, palette: Vec<[u8; 3]>) -> AnimationEncoder {
+        self.palette = Some(palette);
+        return self;
+    }
+
+    pub fn encode(&self) -> Result<Png> {
+        let first = self.frames.first().ok_or_else(|| {
+            invalid_field(
+                "acTL",
+                "num_frames",
+                "an animation needs at least one frame".to_string(),
+            )
+        })?;
+        if first.x_offset != 0 || first.y_offset != 0 {
+            return Err(invalid_field(
+                "fcTL",
+                "x_offset",
+                "the first frame must cover the whole canvas".to_string(),
+            ));
+        }
+        let mut encoder = Encoder::from_pixels(first.image.clone());
+        if let Some(palette) = &self.palette {
+            encoder = encoder.with_palette(palette.clone());
+        }
+        // IHDR, any PLTE, the first frame as IDAT, and IEND
+        let mut png = encoder.encode()?;
+        let ihdr = png.ihdr()?;
+        let num_frames = u32::try_from(self.frames.len())
+            .map_err(|_| invalid_field("acTL", "num_frames", "too many frames".to_string()))?;
+        let actl = Chunk_acTL {
+            num_frames,
+            num_plays: self.num_plays,
+        };
+        let first_idat = png
+            .chunks()
+            .iter()
+            .position(|c| c.chunk_type().bytes() == Chunk_IDAT::HEADER)
+            .unwrap();
+        png.chunks_mut().splice(
+            first_idat..first_idat,
+            vec![actl.encode(), first.control(0).encode()],
+        );
+
+        let mut sequence_number: u32 = 1;
+        for (n, frame) in self.frames.iter().enumerate().skip(1) {
+            let image = &frame.image;
+            if (image.color_type, image.bit_depth) != (ihdr.color_type, ihdr.bit_depth) {
+                return Err(PngError::InvalidImageData(format!(
+                    "frame {} is {:?} at {} bits, the animation {:?} at {} bits",
+                    n, image.color_type, image.bit_depth, ihdr.color_type, ihdr.bit_depth
+                )));
+            }
+            check_buffer(image)?;
+            let control = frame.control(sequence_number);
+            check_region(n, &control, &ihdr)?;
+            png.append_chunk(control.encode());
+            sequence_number += 1;
+
+            let strategy = FilterStrategy::default_for(image.color_type, image.bit_depth);
+            let compressed = encode_image_data(image, ihdr.interlace_method, strategy);
+            for part in compressed.chunks(IDAT_CHUNK_SIZE) {
+                let mut data: Vec<u8> = Vec::with_capacity(4 + part.len());
+                data.extend_from_slice(&sequence_number.to_be_bytes());
+                data.extend_from_slice(part);
+                png.append_chunk(Chunk::new(Chunk_fdAT::chunk_type(), data));
+                sequence_number += 1;
+            }
+        }
+        return Ok(png);
+    }
+
+    // Encode the animation and stream it to `w`
+    pub fn write_to<W: Write>(&self, w: W) -> Result<W> {
+        return self.encode()?.write_to(w);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunk_spec::{Chunk_IEND, CompressionMethod, FilterMethod, InterlaceMethod},
+        png_util::read_png,
+        validate::Severity,
+    };
+
+    fn solid(width: u32, height: u32, pixel: [u8; 4]) -> PixelBuffer {
+        PixelBuffer {
+            width,
+            height,
+            color_type: ColorType::RGBA,
+            bit_depth: 8,
+            data: pixel.repeat((width * height) as usize),
+        }
+    }
+
+    fn control(
+        image: &PixelBuffer,
+        x: u32,
+        y: u32,
+        dispose: DisposeOp,
+        blend: BlendOp,
+    ) -> Chunk_fcTL {
+        Chunk_fcTL {
+            sequence_number: 0,
+            width: image.width,
+            height: image.height,
+            x_offset: x,
+            y_offset: y,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: dispose,
+            blend_op: blend,
+        }
+    }
+
+    // A 4x4 RGBA animation whose first frame is the IDAT image
+    fn animation(frames: &[(Chunk_fcTL, PixelBuffer)]) -> Png {
+        let ihdr = Chunk_IHDR {
+            width: 4,
+            height: 4,
+            bit_depth: 8,
+            color_type: ColorType::RGBA,
+            compression_method: CompressionMethod::DeflateInflate,
+            filter_method: FilterMethod::Adaptive,
+            interlace_method: InterlaceMethod::None,
+        };
+        let mut chunks = vec![
+            ihdr.encode(),
+            Chunk_acTL {
+                num_frames: frames.len() as u32,
+                num_plays: 0,
+            }
+            .encode(),
+        ];
+        let mut sequence_number = 0;
+        for (n, (control, image)) in frames.iter().enumerate() {
+            let mut control = *control;
+            control.sequence_number = sequence_number;
+            chunks.push(control.encode());
+            sequence_number += 1;
+            let data = encode_image_data(image, InterlaceMethod::None, FilterStrategy::MinSumAbs);
+            if n == 0 {
+                chunks.push(Chunk_IDAT { data }.encode());
+            } else {
+                chunks.push(
+                    Chunk_fdAT {
+                        sequence_number,
+                        data,
+                    }
+                    .encode(),
+                );
+                sequence_number += 1;
+            }
+        }
+        chunks.push(Chunk_IEND {}.encode());
+        Png::from_chunks(chunks)
+    }
+
+    fn pixel(image: &PixelBuffer, x: usize, y: usize) -> [u8; 4] {
+        let at = y * image.stride() + x * 4;
+        [
+            image.data[at],
+            image.data[at + 1],
+            image.data[at + 2],
+            image.data[at + 3],
+        ]
+    }
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const CLEAR: [u8; 4] = [0, 0, 0, 0];
+
+    #[test]
+    fn test_dispose_ops() {
+        let red = solid(4, 4, RED);
+        let green = solid(2, 2, GREEN);
+        let blue = solid(1, 1, BLUE);
+        let dot = solid(1, 1, CLEAR);
+        let png = animation(&[
+            (
+                control(&red, 0, 0, DisposeOp::None, BlendOp::Source),
+                red.clone(),
+            ),
+            (
+                control(&green, 0, 0, DisposeOp::Previous, BlendOp::Source),
+                green,
+            ),
+            (
+                control(&blue, 3, 3, DisposeOp::Background, BlendOp::Source),
+                blue,
+            ),
+            (control(&dot, 1, 1, DisposeOp::None, BlendOp::Over), dot),
+        ]);
+        let frames = png.frames().unwrap();
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[0].image, red);
+        assert_eq!(pixel(&frames[1].image, 0, 0), GREEN);
+        assert_eq!(pixel(&frames[1].image, 3, 3), RED);
+        // The green square is gone again, the blue dot is drawn
+        assert_eq!(pixel(&frames[2].image, 0, 0), RED);
+        assert_eq!(pixel(&frames[2].image, 3, 3), BLUE);
+        // ...and then cleared to transparent black
+        assert_eq!(pixel(&frames[3].image, 3, 3), CLEAR);
+        assert_eq!(pixel(&frames[3].image, 1, 1), RED);
+        assert_eq!(frames[3].region, solid(1, 1, CLEAR));
+    }
+
+    #[test]
+    fn test_blend_ops() {
+        let red = solid(4, 4, RED);
+        let tint = solid(1, 1, [0, 0, 255, 128]);
+        let png = animation(&[
+            (
+                control(&red, 0, 0, DisposeOp::None, BlendOp::Source),
+                red.clone(),
+            ),
+            (
+                control(&tint, 0, 0, DisposeOp::None, BlendOp::Over),
+                tint.clone(),
+            ),
+            (control(&tint, 1, 0, DisposeOp::None, BlendOp::Source), tint),
+        ]);
+        let frames = png.frames().unwrap();
+        assert_eq!(pixel(&frames[1].image, 0, 0), [127, 0, 128, 255]);
+        assert_eq!(pixel(&frames[2].image, 1, 0), [0, 0, 255, 128]);
+        assert_eq!(pixel(&frames[2].image, 0, 0), [127, 0, 128, 255]);
+    }
+
+    #[test]
+    fn test_first_frame_dispose_previous_clears() {
+        let red = solid(4, 4, RED);
+        let green = solid(1, 1, GREEN);
+        let png = animation(&[
+            (
+                control(&red, 0, 0, DisposeOp::Previous, BlendOp::Source),
+                red,
+            ),
+            (control(&green, 0, 0, DisposeOp::None, BlendOp::Over), green),
+        ]);
+        let frames = png.frames().unwrap();
+        assert_eq!(pixel(&frames[1].image, 0, 0), GREEN);
+        assert_eq!(pixel(&frames[1].image, 1, 0), CLEAR);
+    }
+
+    #[test]
+    fn test_default_image_outside_animation() {
+        let red = solid(4, 4, RED);
+        let blue = solid(4, 4, BLUE);
+        let mut png = animation(&[
+            (control(&red, 0, 0, DisposeOp::None, BlendOp::Source), red),
+            (
+                control(&blue, 0, 0, DisposeOp::None, BlendOp::Source),
+                blue.clone(),
+            ),
+        ]);
+        // Drop frame 0's fcTL so the IDAT image is no longer a frame, and
+        // renumber what is left
+        png.chunks_mut().remove(2);
+        png.chunks_mut()[1] = Chunk_acTL {
+            num_frames: 1,
+            num_plays: 0,
+        }
+        .encode();
+        let mut fctl = Chunk_fcTL::decode(&png.chunks()[3]).unwrap();
+        fctl.sequence_number = 0;
+        png.chunks_mut()[3] = fctl.encode();
+        let mut fdat = Chunk_fdAT::decode(&png.chunks()[4]).unwrap();
+        fdat.sequence_number = 1;
+        png.chunks_mut()[4] = fdat.encode();
+
+        let frames = png.frames().unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].image, blue);
+    }
+
+    #[test]
+    fn test_sequence_errors() {
+        let red = solid(4, 4, RED);
+        let blue = solid(1, 1, BLUE);
+        let mut png = animation(&[
+            (control(&red, 0, 0, DisposeOp::None, BlendOp::Source), red),
+            (control(&blue, 0, 0, DisposeOp::None, BlendOp::Source), blue),
+        ]);
+        assert!(png.validate().is_empty());
+        // Swap the second fcTL's number with its fdAT's
+        png.chunks_mut().swap(4, 5);
+        assert!(matches!(
+            png.frames(),
+            Err(PngError::InvalidField {
+                field: "sequence_number",
+                ..
+            })
+        ));
+        let errors: Vec<(Severity, Option<usize>)> = png
+            .validate()
+            .iter()
+            .map(|d| (d.severity, d.chunk))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![(Severity::Error, Some(4)), (Severity::Error, Some(5))]
+        );
+
+        png.chunks_mut().swap(4, 5);
+        png.chunks_mut()[1] = Chunk_acTL {
+            num_frames: 3,
+            num_plays: 0,
+        }
+        .encode();
+        assert!(matches!(
+            png.frames(),
+            Err(PngError::InvalidField {
+                chunk: "acTL",
+                field: "num_frames",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_idat_frame_must_cover_image() {
+        let red = solid(4, 4, RED);
+        let blue = solid(4, 4, BLUE);
+        let mut png = animation(&[
+            (control(&red, 0, 0, DisposeOp::None, BlendOp::Source), red),
+            (control(&blue, 0, 0, DisposeOp::None, BlendOp::Source), blue),
+        ]);
+        let mut first = Chunk_fcTL::decode(&png.chunks()[2]).unwrap();
+        first.width = 3;
+        png.chunks_mut()[2] = first.encode();
+        assert!(matches!(
+            png.frames(),
+            Err(PngError::InvalidField {
+                chunk: "fcTL",
+                field: "width",
+                ..
+            })
+        ));
+        let errors: Vec<Option<usize>> = png.validate().iter().map(|d| d.chunk).collect();
+        assert_eq!(errors, vec![Some(2)]);
+
+        // Only the frame ahead of IDAT is held to the image size
+        first.width = 4;
+        first.x_offset = 1;
+        png.chunks_mut()[2] = first.encode();
+        assert!(png.frames().is_err());
+        first.x_offset = 0;
+        png.chunks_mut()[2] = first.encode();
+        assert_eq!(png.frames().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_frame_outside_canvas() {
+        let red = solid(4, 4, RED);
+        let blue = solid(2, 2, BLUE);
+        let png = animation(&[
+            (control(&red, 0, 0, DisposeOp::None, BlendOp::Source), red),
+            (control(&blue, 3, 0, DisposeOp::None, BlendOp::Source), blue),
+        ]);
+        assert!(png.frames().is_err());
+    }
+
+    #[test]
+    fn test_frame_to_png() {
+        let red = solid(4, 4, RED);
+        let green = solid(2, 2, GREEN);
+        let png = animation(&[
+            (control(&red, 0, 0, DisposeOp::None, BlendOp::Source), red),
+            (
+                control(&green, 1, 1, DisposeOp::None, BlendOp::Source),
+                green,
+            ),
+        ]);
+        let frame = &png.frames().unwrap()[1];
+        let still = frame.to_png().unwrap();
+        assert!(still.animation_control().unwrap().is_none());
+        assert_eq!(still.decode_pixels().unwrap(), frame.image);
+    }
+
+    #[test]
+    fn test_frames_from_file() {
+        let png = read_png("./data/png/animated.png").unwrap();
+        assert!(png.validate().is_empty());
+        assert_eq!(
+            png.animation_control().unwrap(),
+            Some(Chunk_acTL {
+                num_frames: 3,
+                num_plays: 0
+            })
+        );
+        let frames = png.frames().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].image, solid(8, 8, RED));
+        assert_eq!(frames[1].control.delay(), 0.25);
+        assert_eq!(pixel(&frames[1].image, 2, 2), [127, 0, 128, 255]);
+        assert_eq!(pixel(&frames[1].image, 6, 6), RED);
+        assert_eq!(pixel(&frames[2].image, 0, 0), GREEN);
+        assert_eq!(pixel(&frames[2].image, 3, 3), [127, 0, 128, 255]);
+    }
+
+    fn noise(width: u32, height: u32) -> PixelBuffer {
+        let mut state: u32 = 12345;
+        let data = (0..width * height * 4)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        PixelBuffer {
+            width,
+            height,
+            color_type: ColorType::RGBA,
+            bit_depth: 8,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_encoder_round_trip() {
+        let red = solid(4, 4, RED);
+        let tint = solid(2, 2, [0, 0, 255, 128]);
+        let green = solid(1, 1, GREEN);
+        let frames = vec![
+            AnimationFrame::new(red.clone(), 1, 10),
+            AnimationFrame::new(tint.clone(), 1, 10)
+                .with_offset(1, 1)
+                .with_dispose(DisposeOp::Previous)
+                .with_blend(BlendOp::Over),
+            AnimationFrame::new(green.clone(), 1, 10)
+                .with_offset(3, 3)
+                .with_dispose(DisposeOp::Background),
+        ];
+        let bytes = AnimationEncoder::new(frames.clone())
+            .with_plays(2)
+            .write_to(Vec::new())
+            .unwrap();
+        let png = Png::try_from(bytes.as_slice()).unwrap();
+        assert!(png.validate().is_empty());
+        assert_eq!(
+            png.animation_control().unwrap(),
+            Some(Chunk_acTL {
+                num_frames: 3,
+                num_plays: 2
+            })
+        );
+        // The still image is the first frame
+        assert_eq!(png.decode_pixels().unwrap(), red);
+
+        let expected = animation(&[
+            (control(&red, 0, 0, DisposeOp::None, BlendOp::Source), red),
+            (
+                control(&tint, 1, 1, DisposeOp::Previous, BlendOp::Over),
+                tint,
+            ),
+            (
+                control(&green, 3, 3, DisposeOp::Background, BlendOp::Source),
+                green,
+            ),
+        ]);
+        assert_eq!(png.frames().unwrap(), expected.frames().unwrap());
+        for (frame, written) in png.frames().unwrap().iter().zip(frames.iter()) {
+            assert_eq!(
+                frame.control,
+                written.control(frame.control.sequence_number)
+            );
+            assert_eq!(frame.region, written.image);
+        }
+    }
+
+    #[test]
+    fn test_encoder_sequence_numbers() {
+        // Noise does not compress, so the second frame spans several fdATs
+        let frames = vec![
+            AnimationFrame::new(solid(64, 64, RED), 1, 2),
+            AnimationFrame::new(noise(64, 64), 1, 2).with_blend(BlendOp::Over),
+        ];
+        let png = AnimationEncoder::new(frames).encode().unwrap();
+        let sequence: Vec<u32> = png
+            .chunks()
+            .iter()
+            .filter(|c| [Chunk_fcTL::HEADER, Chunk_fdAT::HEADER].contains(&c.chunk_type().bytes()))
+            .map(|c| be_u32(c.data(), 0))
+            .collect();
+        assert!(sequence.len() > 3);
+        assert_eq!(sequence, (0..sequence.len() as u32).collect::<Vec<u32>>());
+        assert!(png.validate().is_empty());
+        assert_eq!(png.frames().unwrap()[1].region, noise(64, 64));
+    }
+
+    #[test]
+    fn test_encoder_palette_animation() {
+        let indices = |data: Vec<u8>| PixelBuffer {
+            width: 2,
+            height: 2,
+            color_type: ColorType::PLT,
+            bit_depth: 8,
+            data,
+        };
+        let frames = vec![
+            AnimationFrame::new(indices(vec![0, 0, 0, 0]), 1, 1),
+            AnimationFrame::new(indices(vec![1, 1, 1, 1]), 1, 1),
+        ];
+        let png = AnimationEncoder::new(frames.clone())
+            .with_palette(vec![[255, 0, 0], [0, 255, 0]])
+            .encode()
+            .unwrap();
+        let frames = png.frames().unwrap();
+        assert_eq!(frames[0].image, solid(2, 2, RED));
+        assert_eq!(frames[1].image, solid(2, 2, GREEN));
+
+        let missing = AnimationEncoder::new(vec![AnimationFrame::new(indices(vec![0; 4]), 1, 1)]);
+        assert!(missing.encode().is_err());
+    }
+
+    #[test]
+    fn test_encoder_errors() {
+        let red = solid(4, 4, RED);
+        assert!(AnimationEncoder::new(Vec::new()).encode().is_err());
+        let offset = vec![AnimationFrame::new(red.clone(), 1, 1).with_offset(1, 0)];
+        assert!(AnimationEncoder::new(offset).encode().is_err());
+        let outside = vec![
+            AnimationFrame::new(red.clone(), 1, 1),
+            AnimationFrame::new(solid(2, 2, RED), 1, 1).with_offset(3, 3),
+        ];
+        assert!(matches!(
+            AnimationEncoder::new(outside).encode(),
+            Err(PngError::InvalidField { chunk: "fcTL", .. })
+        ));
+        let grey = PixelBuffer {
+            width: 1,
+            height: 1,
+            color_type: ColorType::GRY,
+            bit_depth: 8,
+            data: vec![0],
+        };
+        let mixed = vec![
+            AnimationFrame::new(red, 1, 1),
+            AnimationFrame::new(grey, 1, 1),
+        ];
+        assert!(matches!(
+            AnimationEncoder::new(mixed).encode(),
+            Err(PngError::InvalidImageData(_))
+        ));
+    }
+
+    #[test]
+    fn test_still_image_has_no_frames() {
+        let png = read_png("./data/png/rust_crab.png").unwrap();
+        assert!(matches!(png.frames(), Err(PngError::ChunkNotFound(_))));
+    }
+}
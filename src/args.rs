@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+/*
+ * Command line arguments for the pngme binary
+ */
+
+#[derive(Debug, Parser)]
+#[command(name = "pngme", version, about = "Hide secret messages in PNG files")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: PngMeArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PngMeArgs {
+    /// Encode a message into a chunk of the given type
+    Encode(EncodeArgs),
+    /// Decode the message stored in the first chunk of the given type
+    Decode(DecodeArgs),
+    /// Remove the first chunk of the given type
+    Remove(RemoveArgs),
+    /// Print every chunk in the file
+    Print(PrintArgs),
+    /// Recompute wrong chunk CRCs, recovering what it can of a damaged file
+    Repair(RepairArgs),
+    /// Hide a message in the low bits of the pixels
+    Embed(EmbedArgs),
+    /// Recover a message hidden with `embed`
+    Extract(ExtractArgs),
+    /// Write each frame of an animated PNG to its own file
+    Frames(FramesArgs),
+    /// List the Exif tags of the eXIf chunk, or remove some of them
+    Exif(ExifArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct EncodeArgs {
+    pub file_path: PathBuf,
+    pub chunk_type: String,
+    pub message: String,
+    /// Where to write the result; defaults to overwriting `file_path`
+    pub output: Option<PathBuf>,
+    /// Keyword to store the message under when the chunk type is tEXt, zTXt or iTXt
+    #[arg(short, long, default_value = "Comment")]
+    pub keyword: String,
+    /// Encrypt the message with a passphrase taken from PNGME_PASSPHRASE, or else read from stdin
+    #[arg(short, long)]
+    pub passphrase: bool,
+    /// Spread the message over this many chunks of the chunk type, at most one per byte
+    #[arg(long)]
+    pub parts: Option<u32>,
+}
+
+#[derive(Debug, Args)]
+pub struct DecodeArgs {
+    pub file_path: PathBuf,
+    pub chunk_type: String,
+    /// Only match text chunks with this keyword
+    #[arg(short, long)]
+    pub keyword: Option<String>,
+    /// Decrypt the message with a passphrase taken from PNGME_PASSPHRASE, or else read from stdin
+    #[arg(short, long)]
+    pub passphrase: bool,
+    /// Reassemble a message spread over every chunk of the chunk type
+    #[arg(long)]
+    pub parts: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RemoveArgs {
+    pub file_path: PathBuf,
+    pub chunk_type: String,
+}
+
+#[derive(Debug, Args)]
+pub struct PrintArgs {
+    pub file_path: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct RepairArgs {
+    pub file_path: PathBuf,
+    /// Where to write the result; defaults to overwriting `file_path`
+    pub output: Option<PathBuf>,
+    /// Only report the problems, writing nothing
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct EmbedArgs {
+    pub file_path: PathBuf,
+    pub message: String,
+    /// Where to write the result; defaults to overwriting `file_path`
+    pub output: Option<PathBuf>,
+    /// Low bits of each sample to replace, from 1 to 8
+    #[arg(short, long, default_value_t = 1)]
+    pub bits: u8,
+    /// Allow palette images by doubling the palette, hiding 1 bit per pixel
+    #[arg(long)]
+    pub palette_safe: bool,
+    /// Encrypt the message with a passphrase taken from PNGME_PASSPHRASE, or else read from stdin
+    #[arg(short, long)]
+    pub passphrase: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ExtractArgs {
+    pub file_path: PathBuf,
+    /// Low bits of each sample the message was hidden in
+    #[arg(short, long, default_value_t = 1)]
+    pub bits: u8,
+    /// Decrypt the message with a passphrase taken from PNGME_PASSPHRASE, or else read from stdin
+    #[arg(short, long)]
+    pub passphrase: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct FramesArgs {
+    pub file_path: PathBuf,
+    /// Directory to write frame_000.png, frame_001.png, ... into
+    pub output_dir: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct ExifArgs {
+    pub file_path: PathBuf,
+    /// Where to write the result of --remove; defaults to overwriting `file_path`
+    pub output: Option<PathBuf>,
+    /// Tag to remove, by name (e.g. GPSLatitude, BodySerialNumber); may be repeated
+    #[arg(short, long)]
+    pub remove: Vec<String>,
+    /// Remove the thumbnail as well
+    #[arg(long)]
+    pub remove_thumbnail: bool,
+}
@@ -5,9 +5,9 @@ use std::{
 
 use crc::Crc;
 
-use crate::{chunk_type::ChunkType, throw_string_error, Error, Result};
+use crate::{chunk_type::ChunkType, PngError, Result};
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Clone)]
 pub struct Chunk {
     clength: u32,
     ctype: ChunkType,
@@ -16,28 +16,37 @@ pub struct Chunk {
 }
 
 impl TryFrom<&[u8]> for Chunk {
-    type Error = Error;
+    type Error = PngError;
 
     fn try_from(value: &[u8]) -> Result<Self> {
-        if value.len() < 4 {
-            return Err(throw_string_error("Insufficient data to read size"));
+        if value.len() < 12 {
+            return Err(PngError::TruncatedChunk { offset: 0 });
         }
-        let clength: u32 = u32::from_be_bytes(value[0..4].try_into()?);
-
-        if value.len() != usize::try_from(clength).unwrap() + 12 {
-            return Err(throw_string_error("Malsized chunk"));
+        let clength: u32 = u32::from_be_bytes(value[0..4].try_into().unwrap());
+
+        let expected = clength as usize + 12;
+        if value.len() != expected {
+            return Err(PngError::LengthMismatch {
+                expected,
+                actual: value.len(),
+            });
         }
 
         // Slice the data
-        let raw_type: [u8; 4] = value[4..8].try_into()?;
-        let raw_crc: [u8; 4] = value[value.len() - 4..value.len()].try_into()?;
+        let raw_type: [u8; 4] = value[4..8].try_into().unwrap();
+        let raw_crc: [u8; 4] = value[value.len() - 4..value.len()].try_into().unwrap();
         let cdata: Vec<u8> = value[8..value.len() - 4].to_vec();
 
         // Process the data
         let ctype: ChunkType = ChunkType::try_from(raw_type)?;
         let ccrc: u32 = u32::from_be_bytes(raw_crc);
-        if ccrc != Chunk::CHUNK_CRC.checksum(&value[4..value.len() - 4]) {
-            return Err(throw_string_error("Chunk does not match checksum"));
+        let computed = Chunk::CHUNK_CRC.checksum(&value[4..value.len() - 4]);
+        if ccrc != computed {
+            return Err(PngError::CrcMismatch {
+                chunk_type: ctype,
+                expected: computed,
+                actual: ccrc,
+            });
         }
 
         return Ok(Chunk {
@@ -79,6 +88,15 @@ impl Chunk {
             ccrc: Chunk::CHUNK_CRC.checksum(&tocrc),
         };
     }
+    // Assemble a chunk whose CRC has already been checked by the caller
+    pub(crate) fn from_parts(ctype: ChunkType, cdata: Vec<u8>, ccrc: u32) -> Chunk {
+        return Chunk {
+            clength: u32::try_from(cdata.len()).unwrap(),
+            ctype,
+            cdata,
+            ccrc,
+        };
+    }
     pub fn length(&self) -> u32 {
         return self.clength;
     }
@@ -91,11 +109,19 @@ impl Chunk {
     pub fn crc(&self) -> u32 {
         return self.ccrc;
     }
+    // Whether the stored CRC disagrees with the type and data, as it may for a
+    // chunk kept by the lenient parser
+    pub fn is_corrupt(&self) -> bool {
+        return self.ccrc != self.computed_crc();
+    }
+    pub(crate) fn computed_crc(&self) -> u32 {
+        let mut digest = Chunk::CHUNK_CRC.digest();
+        digest.update(&self.ctype.bytes());
+        digest.update(&self.cdata);
+        return digest.finalize();
+    }
     pub fn data_as_string(&self) -> Result<String> {
-        match String::from_utf8(self.cdata.clone()) {
-            Ok(s) => Ok(s),
-            Err(_e) => panic!(),
-        }
+        return Ok(String::from_utf8(self.cdata.clone())?);
     }
     pub fn as_bytes(&self) -> Vec<u8> {
         return self
@@ -216,7 +242,52 @@ mod tests {
 
         let chunk = Chunk::try_from(chunk_data.as_ref());
 
-        assert!(chunk.is_err());
+        assert!(matches!(
+            chunk,
+            Err(PngError::CrcMismatch {
+                expected: 2882656334,
+                actual: 2882656333,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_chunk_length_mismatch() {
+        let mut chunk_data = testing_chunk().as_bytes();
+        chunk_data.push(0);
+
+        let chunk = Chunk::try_from(chunk_data.as_ref());
+
+        assert!(matches!(
+            chunk,
+            Err(PngError::LengthMismatch {
+                expected: 54,
+                actual: 55
+            })
+        ));
+    }
+
+    #[test]
+    fn test_is_corrupt() {
+        let chunk = testing_chunk();
+        assert!(!chunk.is_corrupt());
+        let damaged = Chunk::from_parts(
+            chunk.chunk_type().clone(),
+            chunk.data().to_vec(),
+            chunk.crc() ^ 1,
+        );
+        assert!(damaged.is_corrupt());
+    }
+
+    #[test]
+    fn test_invalid_utf8_string() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let chunk = Chunk::new(chunk_type, vec![0x66, 0x6f, 0xff]);
+        assert!(matches!(
+            chunk.data_as_string(),
+            Err(PngError::InvalidUtf8(_))
+        ));
     }
 
     #[test]
@@ -1,19 +1,46 @@
-trait ChunkSpec {
+#![allow(non_camel_case_types)]
+
+use std::convert::{TryFrom, TryInto};
+
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    error::invalid_field,
+    exif::Exif,
+    text::{check_keyword, compress, decompress, latin1_encode, split_keyword},
+    Result,
+};
+
+/*
+ * A chunk layout defined by the PNG spec, which can be decoded from and
+ * encoded back into a raw `Chunk`
+ */
+pub trait ChunkSpec: Sized {
     const HEADER: [u8; 4];
+
+    fn decode(chunk: &Chunk) -> Result<Self>;
+    fn encode(&self) -> Chunk;
+
+    fn chunk_type() -> ChunkType {
+        ChunkType::try_from(Self::HEADER).unwrap()
+    }
 }
 
-struct Chunk_IHDR {
-    width: u32,    /* 0 < width <= 2^31 */
-    height: u32,   /* 0 < width <= 2^31 */
-    bit_depth: u8, /*1, 2, 4, 8, 16 */
-    color_type: ColorType,
-    compression_method: CompressionMethod,
-    filter_method: FilterMethod,
-    iterlace_method: u8,
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Chunk_IHDR {
+    pub width: u32,    /* 0 < width <= 2^31 - 1 */
+    pub height: u32,   /* 0 < height <= 2^31 - 1 */
+    pub bit_depth: u8, /*1, 2, 4, 8, 16 */
+    pub color_type: ColorType,
+    pub compression_method: CompressionMethod,
+    pub filter_method: FilterMethod,
+    pub interlace_method: InterlaceMethod,
 }
 
 #[repr(u8)]
-enum ColorType {
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum ColorType {
     GRY = 0,
     RGB = 2,
     PLT = 3,
@@ -22,35 +49,94 @@ enum ColorType {
 }
 
 #[repr(u8)]
-enum CompressionMethod {
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum CompressionMethod {
     DeflateInflate = 0,
 }
 
 #[repr(u8)]
-enum FilterMethod {
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum FilterMethod {
     Adaptive = 0,
 }
 
 #[repr(u8)]
-enum InterlaceMethod {
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum InterlaceMethod {
     None = 0,
     Adam7 = 1,
 }
 
+impl TryFrom<u8> for ColorType {
+    type Error = u8;
+    fn try_from(value: u8) -> std::result::Result<Self, u8> {
+        match value {
+            0 => Ok(ColorType::GRY),
+            2 => Ok(ColorType::RGB),
+            3 => Ok(ColorType::PLT),
+            4 => Ok(ColorType::GRYA),
+            6 => Ok(ColorType::RGBA),
+            other => Err(other),
+        }
+    }
+}
+
+impl TryFrom<u8> for CompressionMethod {
+    type Error = u8;
+    fn try_from(value: u8) -> std::result::Result<Self, u8> {
+        match value {
+            0 => Ok(CompressionMethod::DeflateInflate),
+            other => Err(other),
+        }
+    }
+}
+
+impl TryFrom<u8> for FilterMethod {
+    type Error = u8;
+    fn try_from(value: u8) -> std::result::Result<Self, u8> {
+        match value {
+            0 => Ok(FilterMethod::Adaptive),
+            other => Err(other),
+        }
+    }
+}
+
+impl TryFrom<u8> for InterlaceMethod {
+    type Error = u8;
+    fn try_from(value: u8) -> std::result::Result<Self, u8> {
+        match value {
+            0 => Ok(InterlaceMethod::None),
+            1 => Ok(InterlaceMethod::Adam7),
+            other => Err(other),
+        }
+    }
+}
+
 impl ColorType {
-    fn pallate_used(self) -> bool {
+    pub fn pallate_used(self) -> bool {
         ((self as u8) & 0x1) == 0x1
     }
 
-    fn color_used(self) -> bool {
+    pub fn color_used(self) -> bool {
         ((self as u8) & 0x2) == 0x2
     }
 
-    fn alpha_used(self) -> bool {
+    pub fn alpha_used(self) -> bool {
         ((self as u8) & 0x4) == 0x4
     }
 
-    fn allowed_bit_depth(self, depth: u8) -> bool {
+    // Samples per pixel; palette indices count as one
+    pub fn channels(self) -> usize {
+        match self {
+            ColorType::GRY => 1,
+            ColorType::RGB => 3,
+            ColorType::PLT => 1,
+            ColorType::GRYA => 2,
+            ColorType::RGBA => 4,
+        }
+    }
+
+    pub fn allowed_bit_depth(self, depth: u8) -> bool {
         match self {
             ColorType::GRY => {
                 (depth == 0x1)
@@ -68,25 +154,1906 @@ impl ColorType {
 }
 
 impl Chunk_IHDR {
-    fn sample_depth(self) -> u8 {
+    pub const LENGTH: usize = 13;
+    pub const MAX_DIMENSION: u32 = 0x7fff_ffff;
+
+    pub fn sample_depth(self) -> u8 {
         match self.color_type {
             ColorType::PLT => 8,
-            other => self.bit_depth,
+            _ => self.bit_depth,
         }
     }
-}
 
-struct Chunk_IDAT {}
-struct Chunk_IEND {}
+    // Check the constraints the PNG spec places on header fields
+    pub fn validate(&self) -> Result<()> {
+        let bad = |field, reason| invalid_field("IHDR", field, reason);
+        if self.width == 0 || self.width > Chunk_IHDR::MAX_DIMENSION {
+            return Err(bad(
+                "width",
+                format!("{} is outside 1..=2^31-1", self.width),
+            ));
+        }
+        if self.height == 0 || self.height > Chunk_IHDR::MAX_DIMENSION {
+            return Err(bad(
+                "height",
+                format!("{} is outside 1..=2^31-1", self.height),
+            ));
+        }
+        if !self.color_type.allowed_bit_depth(self.bit_depth) {
+            return Err(bad(
+                "bit_depth",
+                format!(
+                    "{} is not allowed for colour type {:?}",
+                    self.bit_depth, self.color_type
+                ),
+            ));
+        }
+        return Ok(());
+    }
+}
 
 impl ChunkSpec for Chunk_IHDR {
-    const HEADER: [u8; 4] = [73, 68, 65, 82];
+    const HEADER: [u8; 4] = [73, 72, 68, 82];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_IHDR> {
+        let bad = |field, reason| invalid_field("IHDR", field, reason);
+        let data = fixed_data::<Chunk_IHDR>(chunk, "IHDR", Chunk_IHDR::LENGTH)?;
+
+        let width = u32::from_be_bytes(data[0..4].try_into().unwrap());
+        let height = u32::from_be_bytes(data[4..8].try_into().unwrap());
+        let bit_depth = data[8];
+        let color_type = ColorType::try_from(data[9])
+            .map_err(|v| bad("color_type", format!("unknown colour type {}", v)))?;
+        let compression_method = CompressionMethod::try_from(data[10]).map_err(|v| {
+            bad(
+                "compression_method",
+                format!("unknown compression method {}", v),
+            )
+        })?;
+        let filter_method = FilterMethod::try_from(data[11])
+            .map_err(|v| bad("filter_method", format!("unknown filter method {}", v)))?;
+        let interlace_method = InterlaceMethod::try_from(data[12]).map_err(|v| {
+            bad(
+                "interlace_method",
+                format!("unknown interlace method {}", v),
+            )
+        })?;
+
+        let ihdr = Chunk_IHDR {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            compression_method,
+            filter_method,
+            interlace_method,
+        };
+        ihdr.validate()?;
+        return Ok(ihdr);
+    }
+
+    fn encode(&self) -> Chunk {
+        let mut data: Vec<u8> = Vec::with_capacity(Chunk_IHDR::LENGTH);
+        data.extend_from_slice(&self.width.to_be_bytes());
+        data.extend_from_slice(&self.height.to_be_bytes());
+        data.push(self.bit_depth);
+        data.push(self.color_type as u8);
+        data.push(self.compression_method as u8);
+        data.push(self.filter_method as u8);
+        data.push(self.interlace_method as u8);
+        return Chunk::new(Chunk_IHDR::chunk_type(), data);
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Chunk_PLTE {
+    pub entries: Vec<[u8; 3]>,
+}
+
+impl ChunkSpec for Chunk_PLTE {
+    const HEADER: [u8; 4] = [80, 76, 84, 69];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_PLTE> {
+        check_type::<Chunk_PLTE>(chunk, "PLTE")?;
+        let data = chunk.data();
+        if data.is_empty() || !data.len().is_multiple_of(3) || data.len() > 3 * 256 {
+            return Err(invalid_field(
+                "PLTE",
+                "length",
+                format!("{} is not a multiple of 3 in 3..=768", data.len()),
+            ));
+        }
+        let entries = data.chunks(3).map(|e| [e[0], e[1], e[2]]).collect();
+        return Ok(Chunk_PLTE { entries });
+    }
+
+    fn encode(&self) -> Chunk {
+        let data = self.entries.iter().flatten().copied().collect();
+        return Chunk::new(Chunk_PLTE::chunk_type(), data);
+    }
+}
+
+impl Chunk_PLTE {
+    // Whether this palette suits the image `ihdr` describes: none for
+    // greyscale, and no more entries than an index of its bit depth can reach
+    pub fn check(&self, ihdr: &Chunk_IHDR) -> Result<()> {
+        if !ihdr.color_type.color_used() {
+            return Err(invalid_field(
+                "PLTE",
+                "entries",
+                format!(
+                    "a palette is not allowed for colour type {:?}",
+                    ihdr.color_type
+                ),
+            ));
+        }
+        let max = match ihdr.color_type {
+            ColorType::PLT => 1usize << ihdr.bit_depth.min(8),
+            _ => 256,
+        };
+        if self.entries.is_empty() || self.entries.len() > max {
+            return Err(invalid_field(
+                "PLTE",
+                "entries",
+                format!("{} entries is outside 1..={}", self.entries.len(), max),
+            ));
+        }
+        return Ok(());
+    }
+}
+
+/* Transparency without an alpha channel */
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Transparency {
+    // Alpha of the first palette entries; later entries are opaque
+    Palette(Vec<u8>),
+    // The single sample value, or colour, that is fully transparent
+    Grey(u16),
+    Rgb(u16, u16, u16),
+}
+
+// tRNS has no ChunkSpec impl: a 2 or 6 byte chunk reads differently for a
+// palette image than for a greyscale or truecolour one, so decoding needs the
+// image header
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Chunk_tRNS {
+    pub transparency: Transparency,
+}
+
+impl Chunk_tRNS {
+    pub const HEADER: [u8; 4] = [116, 82, 78, 83];
+
+    pub fn chunk_type() -> ChunkType {
+        ChunkType::try_from(Chunk_tRNS::HEADER).unwrap()
+    }
+
+    // Decode `chunk` for the image `ihdr` describes. Palette alphas are
+    // checked against `palette` when given
+    pub fn decode(
+        chunk: &Chunk,
+        ihdr: &Chunk_IHDR,
+        palette: Option<&Chunk_PLTE>,
+    ) -> Result<Chunk_tRNS> {
+        if chunk.chunk_type().bytes() != Chunk_tRNS::HEADER {
+            return Err(invalid_field(
+                "tRNS",
+                "type",
+                format!("expected tRNS, found {}", chunk.chunk_type()),
+            ));
+        }
+        let data = chunk.data();
+        let bad_length = |expected: &str| {
+            invalid_field(
+                "tRNS",
+                "length",
+                format!(
+                    "expected {} for colour type {:?}, found {}",
+                    expected,
+                    ihdr.color_type,
+                    data.len()
+                ),
+            )
+        };
+        let u16_at = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+        // A key beyond the bit depth could never match a pixel
+        let key = |at: usize| -> Result<u16> {
+            let v = u16_at(at);
+            if ihdr.bit_depth < 16 && v >> ihdr.bit_depth != 0 {
+                return Err(invalid_field(
+                    "tRNS",
+                    "key",
+                    format!("{} does not fit in {} bits", v, ihdr.bit_depth),
+                ));
+            }
+            return Ok(v);
+        };
+        let transparency = match ihdr.color_type {
+            ColorType::PLT => {
+                let max = palette.map_or(256, |p| p.entries.len());
+                if data.is_empty() || data.len() > max {
+                    return Err(bad_length(&format!("1..={} bytes", max)));
+                }
+                Transparency::Palette(data.to_vec())
+            }
+            ColorType::GRY if data.len() == 2 => Transparency::Grey(key(0)?),
+            ColorType::GRY => return Err(bad_length("2 bytes")),
+            ColorType::RGB if data.len() == 6 => Transparency::Rgb(key(0)?, key(2)?, key(4)?),
+            ColorType::RGB => return Err(bad_length("6 bytes")),
+            ColorType::GRYA | ColorType::RGBA => {
+                return Err(invalid_field(
+                    "tRNS",
+                    "type",
+                    format!("not allowed for colour type {:?}", ihdr.color_type),
+                ))
+            }
+        };
+        return Ok(Chunk_tRNS { transparency });
+    }
+
+    pub fn encode(&self) -> Chunk {
+        let data = match &self.transparency {
+            Transparency::Palette(alpha) => alpha.clone(),
+            Transparency::Grey(g) => g.to_be_bytes().to_vec(),
+            Transparency::Rgb(r, g, b) => [r, g, b].iter().flat_map(|v| v.to_be_bytes()).collect(),
+        };
+        return Chunk::new(Chunk_tRNS::chunk_type(), data);
+    }
+
+    // Alpha of palette entry `index`
+    pub fn palette_alpha(&self, index: usize) -> u8 {
+        return match &self.transparency {
+            Transparency::Palette(alpha) => alpha.get(index).copied().unwrap_or(255),
+            _ => 255,
+        };
+    }
+}
+
+/* Approximate usage frequency of each palette entry */
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Chunk_hIST {
+    pub frequencies: Vec<u16>,
+}
+
+impl Chunk_hIST {
+    // There must be exactly one frequency per palette entry
+    pub fn check(&self, palette: &Chunk_PLTE) -> Result<()> {
+        if self.frequencies.len() != palette.entries.len() {
+            return Err(invalid_field(
+                "hIST",
+                "frequencies",
+                format!(
+                    "{} frequencies for {} palette entries",
+                    self.frequencies.len(),
+                    palette.entries.len()
+                ),
+            ));
+        }
+        return Ok(());
+    }
+}
+
+impl ChunkSpec for Chunk_hIST {
+    const HEADER: [u8; 4] = [104, 73, 83, 84];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_hIST> {
+        check_type::<Chunk_hIST>(chunk, "hIST")?;
+        let data = chunk.data();
+        if data.is_empty() || !data.len().is_multiple_of(2) || data.len() > 2 * 256 {
+            return Err(invalid_field(
+                "hIST",
+                "length",
+                format!("{} is not a multiple of 2 in 2..=512", data.len()),
+            ));
+        }
+        let frequencies = data
+            .chunks(2)
+            .map(|f| u16::from_be_bytes([f[0], f[1]]))
+            .collect();
+        return Ok(Chunk_hIST { frequencies });
+    }
+
+    fn encode(&self) -> Chunk {
+        let data = self
+            .frequencies
+            .iter()
+            .flat_map(|f| f.to_be_bytes())
+            .collect();
+        return Chunk::new(Chunk_hIST::chunk_type(), data);
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Chunk_IDAT {
+    pub data: Vec<u8>,
 }
 
 impl ChunkSpec for Chunk_IDAT {
     const HEADER: [u8; 4] = [73, 68, 65, 84];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_IDAT> {
+        check_type::<Chunk_IDAT>(chunk, "IDAT")?;
+        return Ok(Chunk_IDAT {
+            data: chunk.data().to_vec(),
+        });
+    }
+
+    fn encode(&self) -> Chunk {
+        return Chunk::new(Chunk_IDAT::chunk_type(), self.data.clone());
+    }
 }
 
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Chunk_IEND {}
+
 impl ChunkSpec for Chunk_IEND {
-    const HEADER: [u8; 4] = [73, 68, 65, 68];
+    const HEADER: [u8; 4] = [73, 69, 78, 68];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_IEND> {
+        fixed_data::<Chunk_IEND>(chunk, "IEND", 0)?;
+        return Ok(Chunk_IEND {});
+    }
+
+    fn encode(&self) -> Chunk {
+        return Chunk::new(Chunk_IEND::chunk_type(), Vec::new());
+    }
+}
+
+/* Image gamma, scaled by 100000 */
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Chunk_gAMA {
+    pub gamma: u32,
+}
+
+impl Chunk_gAMA {
+    // Stored as the gamma times 100000, so 1 / 2.2 is 45455. The scaled value
+    // must be a positive 32 bit number, as `decode` requires
+    pub fn from_value(gamma: f64) -> Result<Chunk_gAMA> {
+        let scaled = (gamma * 100000.0).round();
+        if !(scaled >= 1.0 && scaled <= u32::MAX as f64) {
+            return Err(invalid_field(
+                "gAMA",
+                "gamma",
+                format!("{} is not a positive gamma that fits the chunk", gamma),
+            ));
+        }
+        return Ok(Chunk_gAMA {
+            gamma: scaled as u32,
+        });
+    }
+
+    pub fn value(&self) -> f64 {
+        return self.gamma as f64 / 100000.0;
+    }
+}
+
+impl ChunkSpec for Chunk_gAMA {
+    const HEADER: [u8; 4] = [103, 65, 77, 65];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_gAMA> {
+        let data = fixed_data::<Chunk_gAMA>(chunk, "gAMA", 4)?;
+        let gamma = be_u32(data, 0);
+        if gamma == 0 {
+            return Err(invalid_field(
+                "gAMA",
+                "gamma",
+                "a gamma of 0 is meaningless".to_string(),
+            ));
+        }
+        return Ok(Chunk_gAMA { gamma });
+    }
+
+    fn encode(&self) -> Chunk {
+        return Chunk::new(Chunk_gAMA::chunk_type(), self.gamma.to_be_bytes().to_vec());
+    }
+}
+
+/* Chromaticities of the white point and primaries, each scaled by 100000 */
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Chunk_cHRM {
+    pub white_x: u32,
+    pub white_y: u32,
+    pub red_x: u32,
+    pub red_y: u32,
+    pub green_x: u32,
+    pub green_y: u32,
+    pub blue_x: u32,
+    pub blue_y: u32,
+}
+
+impl Chunk_cHRM {
+    // CIE x, y of the white point
+    pub fn white_point(&self) -> (f64, f64) {
+        return (scaled(self.white_x), scaled(self.white_y));
+    }
+
+    // CIE x, y of the red, green and blue primaries
+    pub fn primaries(&self) -> [(f64, f64); 3] {
+        return [
+            (scaled(self.red_x), scaled(self.red_y)),
+            (scaled(self.green_x), scaled(self.green_y)),
+            (scaled(self.blue_x), scaled(self.blue_y)),
+        ];
+    }
+}
+
+fn scaled(v: u32) -> f64 {
+    v as f64 / 100000.0
+}
+
+impl ChunkSpec for Chunk_cHRM {
+    const HEADER: [u8; 4] = [99, 72, 82, 77];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_cHRM> {
+        let data = fixed_data::<Chunk_cHRM>(chunk, "cHRM", 32)?;
+        return Ok(Chunk_cHRM {
+            white_x: be_u32(data, 0),
+            white_y: be_u32(data, 4),
+            red_x: be_u32(data, 8),
+            red_y: be_u32(data, 12),
+            green_x: be_u32(data, 16),
+            green_y: be_u32(data, 20),
+            blue_x: be_u32(data, 24),
+            blue_y: be_u32(data, 28),
+        });
+    }
+
+    fn encode(&self) -> Chunk {
+        let data = [
+            self.white_x,
+            self.white_y,
+            self.red_x,
+            self.red_y,
+            self.green_x,
+            self.green_y,
+            self.blue_x,
+            self.blue_y,
+        ]
+        .iter()
+        .flat_map(|v| v.to_be_bytes())
+        .collect();
+        return Chunk::new(Chunk_cHRM::chunk_type(), data);
+    }
+}
+
+#[repr(u8)]
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum RenderingIntent {
+    Perceptual = 0,
+    RelativeColorimetric = 1,
+    Saturation = 2,
+    AbsoluteColorimetric = 3,
+}
+
+impl TryFrom<u8> for RenderingIntent {
+    type Error = u8;
+    fn try_from(value: u8) -> std::result::Result<Self, u8> {
+        match value {
+            0 => Ok(RenderingIntent::Perceptual),
+            1 => Ok(RenderingIntent::RelativeColorimetric),
+            2 => Ok(RenderingIntent::Saturation),
+            3 => Ok(RenderingIntent::AbsoluteColorimetric),
+            other => Err(other),
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Chunk_sRGB {
+    pub intent: RenderingIntent,
+}
+
+impl ChunkSpec for Chunk_sRGB {
+    const HEADER: [u8; 4] = [115, 82, 71, 66];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_sRGB> {
+        let data = fixed_data::<Chunk_sRGB>(chunk, "sRGB", 1)?;
+        let intent = RenderingIntent::try_from(data[0]).map_err(|v| {
+            invalid_field("sRGB", "intent", format!("unknown rendering intent {}", v))
+        })?;
+        return Ok(Chunk_sRGB { intent });
+    }
+
+    fn encode(&self) -> Chunk {
+        return Chunk::new(Chunk_sRGB::chunk_type(), vec![self.intent as u8]);
+    }
+}
+
+#[repr(u8)]
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum PhysUnit {
+    Unknown = 0,
+    Metre = 1,
+}
+
+impl TryFrom<u8> for PhysUnit {
+    type Error = u8;
+    fn try_from(value: u8) -> std::result::Result<Self, u8> {
+        match value {
+            0 => Ok(PhysUnit::Unknown),
+            1 => Ok(PhysUnit::Metre),
+            other => Err(other),
+        }
+    }
+}
+
+/* Intended pixel size or aspect ratio */
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Chunk_pHYs {
+    pub pixels_per_unit_x: u32,
+    pub pixels_per_unit_y: u32,
+    pub unit: PhysUnit,
+}
+
+impl ChunkSpec for Chunk_pHYs {
+    const HEADER: [u8; 4] = [112, 72, 89, 115];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_pHYs> {
+        let data = fixed_data::<Chunk_pHYs>(chunk, "pHYs", 9)?;
+        let unit = PhysUnit::try_from(data[8])
+            .map_err(|v| invalid_field("pHYs", "unit", format!("unknown unit specifier {}", v)))?;
+        return Ok(Chunk_pHYs {
+            pixels_per_unit_x: be_u32(data, 0),
+            pixels_per_unit_y: be_u32(data, 4),
+            unit,
+        });
+    }
+
+    fn encode(&self) -> Chunk {
+        let mut data: Vec<u8> = Vec::with_capacity(9);
+        data.extend_from_slice(&self.pixels_per_unit_x.to_be_bytes());
+        data.extend_from_slice(&self.pixels_per_unit_y.to_be_bytes());
+        data.push(self.unit as u8);
+        return Chunk::new(Chunk_pHYs::chunk_type(), data);
+    }
+}
+
+/* Bits of each channel that were significant in the original image */
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum SignificantBits {
+    Grey(u8),
+    GreyAlpha(u8, u8),
+    // Also the layout for palette images, describing the palette colours
+    Rgb(u8, u8, u8),
+    Rgba(u8, u8, u8, u8),
+}
+
+impl SignificantBits {
+    fn values(&self) -> Vec<u8> {
+        match *self {
+            SignificantBits::Grey(g) => vec![g],
+            SignificantBits::GreyAlpha(g, a) => vec![g, a],
+            SignificantBits::Rgb(r, g, b) => vec![r, g, b],
+            SignificantBits::Rgba(r, g, b, a) => vec![r, g, b, a],
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Chunk_sBIT {
+    pub bits: SignificantBits,
+}
+
+impl Chunk_sBIT {
+    // The layout must be the one the colour type dictates, and no channel may
+    // claim more bits than its samples hold (8 for palette colours)
+    pub fn check(&self, ihdr: &Chunk_IHDR) -> Result<()> {
+        let fits = match self.bits {
+            SignificantBits::Grey(_) => ihdr.color_type == ColorType::GRY,
+            SignificantBits::GreyAlpha(..) => ihdr.color_type == ColorType::GRYA,
+            SignificantBits::Rgb(..) => {
+                ihdr.color_type == ColorType::RGB || ihdr.color_type == ColorType::PLT
+            }
+            SignificantBits::Rgba(..) => ihdr.color_type == ColorType::RGBA,
+        };
+        if !fits {
+            return Err(invalid_field(
+                "sBIT",
+                "bits",
+                format!(
+                    "{:?} does not fit colour type {:?}",
+                    self.bits, ihdr.color_type
+                ),
+            ));
+        }
+        let depth = if ihdr.color_type == ColorType::PLT {
+            8
+        } else {
+            ihdr.bit_depth
+        };
+        if let Some(v) = self.bits.values().into_iter().find(|&v| v > depth) {
+            return Err(invalid_field(
+                "sBIT",
+                "bits",
+                format!("{} significant bits in {} bit samples", v, depth),
+            ));
+        }
+        return Ok(());
+    }
+}
+
+impl ChunkSpec for Chunk_sBIT {
+    const HEADER: [u8; 4] = [115, 66, 73, 84];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_sBIT> {
+        check_type::<Chunk_sBIT>(chunk, "sBIT")?;
+        let data = chunk.data();
+        if data.contains(&0) {
+            return Err(invalid_field(
+                "sBIT",
+                "bits",
+                "a channel has 0 significant bits".to_string(),
+            ));
+        }
+        let bits = match *data {
+            [g] => SignificantBits::Grey(g),
+            [g, a] => SignificantBits::GreyAlpha(g, a),
+            [r, g, b] => SignificantBits::Rgb(r, g, b),
+            [r, g, b, a] => SignificantBits::Rgba(r, g, b, a),
+            _ => {
+                return Err(invalid_field(
+                    "sBIT",
+                    "length",
+                    format!("expected 1 to 4 bytes, found {}", data.len()),
+                ))
+            }
+        };
+        return Ok(Chunk_sBIT { bits });
+    }
+
+    fn encode(&self) -> Chunk {
+        return Chunk::new(Chunk_sBIT::chunk_type(), self.bits.values());
+    }
+}
+
+/* Time of last modification, in UTC */
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Chunk_tIME {
+    pub year: u16,
+    pub month: u8,  /* 1-12 */
+    pub day: u8,    /* 1-31 */
+    pub hour: u8,   /* 0-23 */
+    pub minute: u8, /* 0-59 */
+    pub second: u8, /* 0-60, to allow for leap seconds */
+}
+
+impl ChunkSpec for Chunk_tIME {
+    const HEADER: [u8; 4] = [116, 73, 77, 69];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_tIME> {
+        let data = fixed_data::<Chunk_tIME>(chunk, "tIME", 7)?;
+        let field = |name, value: u8, lo: u8, hi: u8| {
+            if value < lo || value > hi {
+                return Err(invalid_field(
+                    "tIME",
+                    name,
+                    format!("{} is outside {}..={}", value, lo, hi),
+                ));
+            }
+            return Ok(value);
+        };
+        return Ok(Chunk_tIME {
+            year: u16::from_be_bytes([data[0], data[1]]),
+            month: field("month", data[2], 1, 12)?,
+            day: field("day", data[3], 1, 31)?,
+            hour: field("hour", data[4], 0, 23)?,
+            minute: field("minute", data[5], 0, 59)?,
+            second: field("second", data[6], 0, 60)?,
+        });
+    }
+
+    fn encode(&self) -> Chunk {
+        let mut data: Vec<u8> = self.year.to_be_bytes().to_vec();
+        data.extend_from_slice(&[self.month, self.day, self.hour, self.minute, self.second]);
+        return Chunk::new(Chunk_tIME::chunk_type(), data);
+    }
+}
+
+/* Embedded ICC profile, stored deflated after its name */
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Chunk_iCCP {
+    name: String,
+    profile: Vec<u8>,
+}
+
+impl Chunk_iCCP {
+    // The name follows the keyword rules of the text chunks
+    pub fn new(name: &str, profile: Vec<u8>) -> Result<Chunk_iCCP> {
+        check_keyword("iCCP", name)?;
+        return Ok(Chunk_iCCP {
+            name: name.to_string(),
+            profile,
+        });
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn profile(&self) -> &[u8] {
+        &self.profile
+    }
+}
+
+impl ChunkSpec for Chunk_iCCP {
+    const HEADER: [u8; 4] = [105, 67, 67, 80];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_iCCP> {
+        check_type::<Chunk_iCCP>(chunk, "iCCP")?;
+        let (name, rest) = split_keyword("iCCP", chunk.data())?;
+        match rest.first() {
+            Some(&m) if m == CompressionMethod::DeflateInflate as u8 => {}
+            m => {
+                return Err(invalid_field(
+                    "iCCP",
+                    "compression_method",
+                    format!("{:?} is not 0", m),
+                ))
+            }
+        }
+        let profile = decompress("iCCP", "profile", &rest[1..])?;
+        return Ok(Chunk_iCCP { name, profile });
+    }
+
+    fn encode(&self) -> Chunk {
+        // The name was checked to be Latin-1 when this was built
+        let mut data = latin1_encode(&self.name).unwrap();
+        data.push(0);
+        data.push(CompressionMethod::DeflateInflate as u8);
+        data.extend(compress(&self.profile));
+        return Chunk::new(Chunk_iCCP::chunk_type(), data);
+    }
+}
+
+/* A named palette suggested for displays with few colours */
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct SuggestedColor {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub alpha: u16,
+    // Relative to the other entries; 0 when unknown
+    pub frequency: u16,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Chunk_sPLT {
+    name: String,
+    sample_depth: u8,
+    entries: Vec<SuggestedColor>,
+}
+
+impl Chunk_sPLT {
+    // The name follows the keyword rules of the text chunks, and with a sample
+    // depth of 8 every sample must fit in a byte
+    pub fn new(name: &str, sample_depth: u8, entries: Vec<SuggestedColor>) -> Result<Chunk_sPLT> {
+        check_keyword("sPLT", name)?;
+        if sample_depth != 8 && sample_depth != 16 {
+            return Err(invalid_field(
+                "sPLT",
+                "sample_depth",
+                format!("{} is not 8 or 16", sample_depth),
+            ));
+        }
+        let too_deep =
+            |c: &SuggestedColor| [c.red, c.green, c.blue, c.alpha].iter().any(|&v| v > 255);
+        if sample_depth == 8 && entries.iter().any(too_deep) {
+            return Err(invalid_field(
+                "sPLT",
+                "entries",
+                "a sample does not fit in 8 bits".to_string(),
+            ));
+        }
+        return Ok(Chunk_sPLT {
+            name: name.to_string(),
+            sample_depth,
+            entries,
+        });
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn sample_depth(&self) -> u8 {
+        self.sample_depth
+    }
+
+    pub fn entries(&self) -> &[SuggestedColor] {
+        &self.entries
+    }
+}
+
+impl ChunkSpec for Chunk_sPLT {
+    const HEADER: [u8; 4] = [115, 80, 76, 84];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_sPLT> {
+        check_type::<Chunk_sPLT>(chunk, "sPLT")?;
+        let (name, rest) = split_keyword("sPLT", chunk.data())?;
+        let (sample_depth, rest) = match rest.split_first() {
+            Some((&depth, rest)) => (depth, rest),
+            None => return Err(invalid_field("sPLT", "sample_depth", "missing".to_string())),
+        };
+        // Four samples of the sample depth, then a 16 bit frequency
+        let size = if sample_depth == 16 { 10 } else { 6 };
+        if !rest.len().is_multiple_of(size) {
+            return Err(invalid_field(
+                "sPLT",
+                "length",
+                format!(
+                    "{} bytes of entries are not a multiple of {}",
+                    rest.len(),
+                    size
+                ),
+            ));
+        }
+        let entries = rest
+            .chunks(size)
+            .map(|e| {
+                let u16_at = |at: usize| u16::from_be_bytes([e[at], e[at + 1]]);
+                if size == 6 {
+                    SuggestedColor {
+                        red: e[0] as u16,
+                        green: e[1] as u16,
+                        blue: e[2] as u16,
+                        alpha: e[3] as u16,
+                        frequency: u16_at(4),
+                    }
+                } else {
+                    SuggestedColor {
+                        red: u16_at(0),
+                        green: u16_at(2),
+                        blue: u16_at(4),
+                        alpha: u16_at(6),
+                        frequency: u16_at(8),
+                    }
+                }
+            })
+            .collect();
+        return Chunk_sPLT::new(&name, sample_depth, entries);
+    }
+
+    fn encode(&self) -> Chunk {
+        // The name was checked to be Latin-1 when this was built
+        let mut data = latin1_encode(&self.name).unwrap();
+        data.push(0);
+        data.push(self.sample_depth);
+        for c in self.entries.iter() {
+            for &v in [c.red, c.green, c.blue, c.alpha].iter() {
+                if self.sample_depth == 8 {
+                    data.push(v as u8);
+                } else {
+                    data.extend_from_slice(&v.to_be_bytes());
+                }
+            }
+            data.extend_from_slice(&c.frequency.to_be_bytes());
+        }
+        return Chunk::new(Chunk_sPLT::chunk_type(), data);
+    }
+}
+
+/* Coding-independent code points (ITU-T H.273) naming the colour space */
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Chunk_cICP {
+    pub colour_primaries: u8,
+    pub transfer_function: u8,
+    // Always 0: PNG stores RGB, never YCbCr
+    pub matrix_coefficients: u8,
+    pub video_full_range: bool,
+}
+
+impl ChunkSpec for Chunk_cICP {
+    const HEADER: [u8; 4] = [99, 73, 67, 80];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_cICP> {
+        let data = fixed_data::<Chunk_cICP>(chunk, "cICP", 4)?;
+        if data[2] != 0 {
+            return Err(invalid_field(
+                "cICP",
+                "matrix_coefficients",
+                format!("{} is not 0 (RGB)", data[2]),
+            ));
+        }
+        let video_full_range = match data[3] {
+            0 => false,
+            1 => true,
+            v => {
+                return Err(invalid_field(
+                    "cICP",
+                    "video_full_range",
+                    format!("{} is not 0 or 1", v),
+                ))
+            }
+        };
+        return Ok(Chunk_cICP {
+            colour_primaries: data[0],
+            transfer_function: data[1],
+            matrix_coefficients: data[2],
+            video_full_range,
+        });
+    }
+
+    fn encode(&self) -> Chunk {
+        let data = vec![
+            self.colour_primaries,
+            self.transfer_function,
+            self.matrix_coefficients,
+            self.video_full_range as u8,
+        ];
+        return Chunk::new(Chunk_cICP::chunk_type(), data);
+    }
+}
+
+/*
+ * Mastering display colour volume (SMPTE ST 2086): chromaticities scaled by
+ * 50000, luminances in units of 0.0001 cd/m2
+ */
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Chunk_mDCV {
+    pub red_x: u16,
+    pub red_y: u16,
+    pub green_x: u16,
+    pub green_y: u16,
+    pub blue_x: u16,
+    pub blue_y: u16,
+    pub white_x: u16,
+    pub white_y: u16,
+    pub max_luminance: u32,
+    pub min_luminance: u32,
+}
+
+impl Chunk_mDCV {
+    // CIE x, y of the white point
+    pub fn white_point(&self) -> (f64, f64) {
+        return (self.white_x as f64 / 50000.0, self.white_y as f64 / 50000.0);
+    }
+
+    // CIE x, y of the red, green and blue primaries
+    pub fn primaries(&self) -> [(f64, f64); 3] {
+        let xy = |x: u16, y: u16| (x as f64 / 50000.0, y as f64 / 50000.0);
+        return [
+            xy(self.red_x, self.red_y),
+            xy(self.green_x, self.green_y),
+            xy(self.blue_x, self.blue_y),
+        ];
+    }
+
+    // Minimum and maximum display luminance in cd/m2
+    pub fn luminance(&self) -> (f64, f64) {
+        return (
+            self.min_luminance as f64 / 10000.0,
+            self.max_luminance as f64 / 10000.0,
+        );
+    }
+}
+
+impl ChunkSpec for Chunk_mDCV {
+    const HEADER: [u8; 4] = [109, 68, 67, 86];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_mDCV> {
+        let data = fixed_data::<Chunk_mDCV>(chunk, "mDCV", 24)?;
+        let u16_at = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+        let mdcv = Chunk_mDCV {
+            red_x: u16_at(0),
+            red_y: u16_at(2),
+            green_x: u16_at(4),
+            green_y: u16_at(6),
+            blue_x: u16_at(8),
+            blue_y: u16_at(10),
+            white_x: u16_at(12),
+            white_y: u16_at(14),
+            max_luminance: be_u32(data, 16),
+            min_luminance: be_u32(data, 20),
+        };
+        if mdcv.min_luminance >= mdcv.max_luminance {
+            return Err(invalid_field(
+                "mDCV",
+                "min_luminance",
+                format!(
+                    "{} is not below the maximum {}",
+                    mdcv.min_luminance, mdcv.max_luminance
+                ),
+            ));
+        }
+        return Ok(mdcv);
+    }
+
+    fn encode(&self) -> Chunk {
+        let mut data: Vec<u8> = [
+            self.red_x,
+            self.red_y,
+            self.green_x,
+            self.green_y,
+            self.blue_x,
+            self.blue_y,
+            self.white_x,
+            self.white_y,
+        ]
+        .iter()
+        .flat_map(|v| v.to_be_bytes())
+        .collect();
+        data.extend_from_slice(&self.max_luminance.to_be_bytes());
+        data.extend_from_slice(&self.min_luminance.to_be_bytes());
+        return Chunk::new(Chunk_mDCV::chunk_type(), data);
+    }
+}
+
+/* Content light level: brightest pixel and brightest frame average, in units of 0.0001 cd/m2 */
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Chunk_cLLI {
+    pub max_content_light_level: u32,
+    pub max_frame_average_light_level: u32,
+}
+
+impl ChunkSpec for Chunk_cLLI {
+    const HEADER: [u8; 4] = [99, 76, 76, 73];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_cLLI> {
+        let data = fixed_data::<Chunk_cLLI>(chunk, "cLLI", 8)?;
+        return Ok(Chunk_cLLI {
+            max_content_light_level: be_u32(data, 0),
+            max_frame_average_light_level: be_u32(data, 4),
+        });
+    }
+
+    fn encode(&self) -> Chunk {
+        let mut data: Vec<u8> = Vec::with_capacity(8);
+        data.extend_from_slice(&self.max_content_light_level.to_be_bytes());
+        data.extend_from_slice(&self.max_frame_average_light_level.to_be_bytes());
+        return Chunk::new(Chunk_cLLI::chunk_type(), data);
+    }
+}
+
+/* Exif metadata, as the TIFF structure `Exif` models */
+#[derive(PartialEq, Debug, Clone)]
+pub struct Chunk_eXIf {
+    pub exif: Exif,
+}
+
+impl ChunkSpec for Chunk_eXIf {
+    const HEADER: [u8; 4] = [101, 88, 73, 102];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_eXIf> {
+        check_type::<Chunk_eXIf>(chunk, "eXIf")?;
+        let exif = Exif::parse(chunk.data())?;
+        return Ok(Chunk_eXIf { exif });
+    }
+
+    fn encode(&self) -> Chunk {
+        return Chunk::new(Chunk_eXIf::chunk_type(), self.exif.to_bytes());
+    }
+}
+
+/* Default background colour, in the form the image's colour type dictates */
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Background {
+    Grey(u16),
+    Rgb(u16, u16, u16),
+    PaletteIndex(u8),
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Chunk_bKGD {
+    pub color: Background,
+}
+
+impl Chunk_bKGD {
+    // Whether this background is laid out as `color_type` requires
+    pub fn matches(&self, color_type: ColorType) -> bool {
+        match self.color {
+            Background::Grey(_) => !color_type.color_used(),
+            Background::Rgb(..) => color_type.color_used() && !color_type.pallate_used(),
+            Background::PaletteIndex(_) => color_type.pallate_used(),
+        }
+    }
+}
+
+impl ChunkSpec for Chunk_bKGD {
+    const HEADER: [u8; 4] = [98, 75, 71, 68];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_bKGD> {
+        check_type::<Chunk_bKGD>(chunk, "bKGD")?;
+        let data = chunk.data();
+        let u16_at = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+        let color = match data.len() {
+            1 => Background::PaletteIndex(data[0]),
+            2 => Background::Grey(u16_at(0)),
+            6 => Background::Rgb(u16_at(0), u16_at(2), u16_at(4)),
+            n => {
+                return Err(invalid_field(
+                    "bKGD",
+                    "length",
+                    format!("expected 1, 2 or 6 bytes, found {}", n),
+                ))
+            }
+        };
+        return Ok(Chunk_bKGD { color });
+    }
+
+    fn encode(&self) -> Chunk {
+        let data = match self.color {
+            Background::PaletteIndex(i) => vec![i],
+            Background::Grey(g) => g.to_be_bytes().to_vec(),
+            Background::Rgb(r, g, b) => [r, g, b].iter().flat_map(|v| v.to_be_bytes()).collect(),
+        };
+        return Chunk::new(Chunk_bKGD::chunk_type(), data);
+    }
+}
+
+/* APNG animation control: frame count, and plays before stopping (0 loops forever) */
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Chunk_acTL {
+    pub num_frames: u32,
+    pub num_plays: u32,
+}
+
+impl ChunkSpec for Chunk_acTL {
+    const HEADER: [u8; 4] = [97, 99, 84, 76];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_acTL> {
+        let data = fixed_data::<Chunk_acTL>(chunk, "acTL", 8)?;
+        let num_frames = be_u32(data, 0);
+        if num_frames == 0 {
+            return Err(invalid_field(
+                "acTL",
+                "num_frames",
+                "an animation needs at least one frame".to_string(),
+            ));
+        }
+        return Ok(Chunk_acTL {
+            num_frames,
+            num_plays: be_u32(data, 4),
+        });
+    }
+
+    fn encode(&self) -> Chunk {
+        let mut data: Vec<u8> = Vec::with_capacity(8);
+        data.extend_from_slice(&self.num_frames.to_be_bytes());
+        data.extend_from_slice(&self.num_plays.to_be_bytes());
+        return Chunk::new(Chunk_acTL::chunk_type(), data);
+    }
+}
+
+// What becomes of a frame's region before the next frame is rendered
+#[repr(u8)]
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum DisposeOp {
+    None = 0,
+    Background = 1,
+    Previous = 2,
+}
+
+// How a frame's pixels are combined with the canvas beneath them
+#[repr(u8)]
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum BlendOp {
+    Source = 0,
+    Over = 1,
+}
+
+impl TryFrom<u8> for DisposeOp {
+    type Error = u8;
+    fn try_from(value: u8) -> std::result::Result<Self, u8> {
+        match value {
+            0 => Ok(DisposeOp::None),
+            1 => Ok(DisposeOp::Background),
+            2 => Ok(DisposeOp::Previous),
+            other => Err(other),
+        }
+    }
+}
+
+impl TryFrom<u8> for BlendOp {
+    type Error = u8;
+    fn try_from(value: u8) -> std::result::Result<Self, u8> {
+        match value {
+            0 => Ok(BlendOp::Source),
+            1 => Ok(BlendOp::Over),
+            other => Err(other),
+        }
+    }
+}
+
+/* APNG frame control: the region a frame covers, how long it shows, and how it is drawn */
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Chunk_fcTL {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub delay_num: u16,
+    pub delay_den: u16, /* 0 means 1/100 s */
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+}
+
+impl Chunk_fcTL {
+    // Display time of the frame in seconds
+    pub fn delay(&self) -> f64 {
+        let den = if self.delay_den == 0 {
+            100
+        } else {
+            self.delay_den
+        };
+        return self.delay_num as f64 / den as f64;
+    }
+}
+
+impl ChunkSpec for Chunk_fcTL {
+    const HEADER: [u8; 4] = [102, 99, 84, 76];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_fcTL> {
+        let data = fixed_data::<Chunk_fcTL>(chunk, "fcTL", 26)?;
+        let u16_at = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+        let width = be_u32(data, 4);
+        let height = be_u32(data, 8);
+        if width == 0 || height == 0 {
+            return Err(invalid_field(
+                "fcTL",
+                if width == 0 { "width" } else { "height" },
+                "frame dimensions must be non-zero".to_string(),
+            ));
+        }
+        let dispose_op = DisposeOp::try_from(data[24]).map_err(|v| {
+            invalid_field("fcTL", "dispose_op", format!("unknown dispose op {}", v))
+        })?;
+        let blend_op = BlendOp::try_from(data[25])
+            .map_err(|v| invalid_field("fcTL", "blend_op", format!("unknown blend op {}", v)))?;
+        return Ok(Chunk_fcTL {
+            sequence_number: be_u32(data, 0),
+            width,
+            height,
+            x_offset: be_u32(data, 12),
+            y_offset: be_u32(data, 16),
+            delay_num: u16_at(20),
+            delay_den: u16_at(22),
+            dispose_op,
+            blend_op,
+        });
+    }
+
+    fn encode(&self) -> Chunk {
+        let mut data: Vec<u8> = Vec::with_capacity(26);
+        for v in [
+            self.sequence_number,
+            self.width,
+            self.height,
+            self.x_offset,
+            self.y_offset,
+        ]
+        .iter()
+        {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        data.extend_from_slice(&self.delay_num.to_be_bytes());
+        data.extend_from_slice(&self.delay_den.to_be_bytes());
+        data.push(self.dispose_op as u8);
+        data.push(self.blend_op as u8);
+        return Chunk::new(Chunk_fcTL::chunk_type(), data);
+    }
+}
+
+/* APNG frame data: a sequence number followed by what would be IDAT contents */
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Chunk_fdAT {
+    pub sequence_number: u32,
+    pub data: Vec<u8>,
+}
+
+impl ChunkSpec for Chunk_fdAT {
+    const HEADER: [u8; 4] = [102, 100, 65, 84];
+
+    fn decode(chunk: &Chunk) -> Result<Chunk_fdAT> {
+        check_type::<Chunk_fdAT>(chunk, "fdAT")?;
+        let data = chunk.data();
+        if data.len() < 4 {
+            return Err(invalid_field(
+                "fdAT",
+                "length",
+                format!("expected at least 4 bytes, found {}", data.len()),
+            ));
+        }
+        return Ok(Chunk_fdAT {
+            sequence_number: be_u32(data, 0),
+            data: data[4..].to_vec(),
+        });
+    }
+
+    fn encode(&self) -> Chunk {
+        let mut data: Vec<u8> = Vec::with_capacity(4 + self.data.len());
+        data.extend_from_slice(&self.sequence_number.to_be_bytes());
+        data.extend_from_slice(&self.data);
+        return Chunk::new(Chunk_fdAT::chunk_type(), data);
+    }
+}
+
+pub(crate) fn be_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+// Fail unless `chunk` carries the type `T` decodes
+pub(crate) fn check_type<T: ChunkSpec>(chunk: &Chunk, name: &'static str) -> Result<()> {
+    if chunk.chunk_type().bytes() != T::HEADER {
+        return Err(invalid_field(
+            name,
+            "type",
+            format!("expected {}, found {}", name, chunk.chunk_type()),
+        ));
+    }
+    return Ok(());
+}
+
+// The data of a chunk of type `T` whose layout has a fixed size
+fn fixed_data<'a, T: ChunkSpec>(
+    chunk: &'a Chunk,
+    name: &'static str,
+    len: usize,
+) -> Result<&'a [u8]> {
+    check_type::<T>(chunk, name)?;
+    if chunk.data().len() != len {
+        return Err(invalid_field(
+            name,
+            "length",
+            format!("expected {} bytes, found {}", len, chunk.data().len()),
+        ));
+    }
+    return Ok(chunk.data());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{png::Png, png_util::read_png, PngError};
+
+    fn testing_ihdr() -> Chunk_IHDR {
+        Chunk_IHDR {
+            width: 50,
+            height: 40,
+            bit_depth: 8,
+            color_type: ColorType::RGBA,
+            compression_method: CompressionMethod::DeflateInflate,
+            filter_method: FilterMethod::Adaptive,
+            interlace_method: InterlaceMethod::None,
+        }
+    }
+
+    fn with_byte(index: usize, value: u8) -> Chunk {
+        let mut data = testing_ihdr().encode().data().to_vec();
+        data[index] = value;
+        Chunk::new(Chunk_IHDR::chunk_type(), data)
+    }
+
+    fn invalid_field_name<T: std::fmt::Debug>(res: Result<T>) -> &'static str {
+        match res {
+            Err(PngError::InvalidField { field, .. }) => field,
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ihdr_from_image_file() {
+        let png = read_png("./data/png/rust_crab.png").unwrap();
+        let ihdr = png.ihdr().unwrap();
+        assert_eq!(ihdr.width, 1168);
+        assert_eq!(ihdr.height, 687);
+        assert_eq!(ihdr.bit_depth, 8);
+        assert_eq!(ihdr.color_type, ColorType::RGBA);
+        assert_eq!(ihdr.interlace_method, InterlaceMethod::None);
+    }
+
+    #[test]
+    fn test_ihdr_round_trip() {
+        let ihdr = testing_ihdr();
+        assert_eq!(Chunk_IHDR::decode(&ihdr.encode()).unwrap(), ihdr);
+    }
+
+    #[test]
+    fn test_ihdr_missing() {
+        let png = Png::from_chunks(Vec::new());
+        assert!(matches!(png.ihdr(), Err(PngError::ChunkNotFound(_))));
+    }
+
+    #[test]
+    fn test_ihdr_invalid_fields() {
+        let zero_width = with_byte(3, 0);
+        assert_eq!(invalid_field_name(Chunk_IHDR::decode(&zero_width)), "width");
+
+        let huge_height = with_byte(4, 0x80);
+        assert_eq!(
+            invalid_field_name(Chunk_IHDR::decode(&huge_height)),
+            "height"
+        );
+
+        let bad_depth = with_byte(8, 4);
+        assert_eq!(
+            invalid_field_name(Chunk_IHDR::decode(&bad_depth)),
+            "bit_depth"
+        );
+
+        let bad_colour = with_byte(9, 5);
+        assert_eq!(
+            invalid_field_name(Chunk_IHDR::decode(&bad_colour)),
+            "color_type"
+        );
+
+        let bad_compression = with_byte(10, 1);
+        assert_eq!(
+            invalid_field_name(Chunk_IHDR::decode(&bad_compression)),
+            "compression_method"
+        );
+
+        let bad_filter = with_byte(11, 1);
+        assert_eq!(
+            invalid_field_name(Chunk_IHDR::decode(&bad_filter)),
+            "filter_method"
+        );
+
+        let bad_interlace = with_byte(12, 2);
+        assert_eq!(
+            invalid_field_name(Chunk_IHDR::decode(&bad_interlace)),
+            "interlace_method"
+        );
+    }
+
+    #[test]
+    fn test_ihdr_wrong_length() {
+        let chunk = Chunk::new(
+            ChunkType::try_from(Chunk_IHDR::HEADER).unwrap(),
+            vec![0; 12],
+        );
+        assert_eq!(invalid_field_name(Chunk_IHDR::decode(&chunk)), "length");
+    }
+
+    fn round_trip<T: ChunkSpec + PartialEq + std::fmt::Debug>(value: T) {
+        let chunk = value.encode();
+        assert_eq!(chunk.chunk_type().bytes(), T::HEADER);
+        assert_eq!(T::decode(&chunk).unwrap(), value);
+    }
+
+    #[test]
+    fn test_standard_chunk_round_trips() {
+        round_trip(Chunk_PLTE {
+            entries: vec![[0, 0, 0], [255, 128, 1]],
+        });
+        round_trip(Chunk_IDAT {
+            data: vec![1, 2, 3],
+        });
+        round_trip(Chunk_IEND {});
+        round_trip(Chunk_gAMA { gamma: 45455 });
+        round_trip(Chunk_cHRM {
+            white_x: 31270,
+            white_y: 32900,
+            red_x: 64000,
+            red_y: 33000,
+            green_x: 30000,
+            green_y: 60000,
+            blue_x: 15000,
+            blue_y: 6000,
+        });
+        round_trip(Chunk_sRGB {
+            intent: RenderingIntent::Saturation,
+        });
+        round_trip(Chunk_pHYs {
+            pixels_per_unit_x: 3778,
+            pixels_per_unit_y: 3779,
+            unit: PhysUnit::Metre,
+        });
+        round_trip(Chunk_tIME {
+            year: 2021,
+            month: 6,
+            day: 30,
+            hour: 23,
+            minute: 59,
+            second: 60,
+        });
+        round_trip(Chunk_iCCP::new("Display P3", vec![7; 300]).unwrap());
+        round_trip(Chunk_sBIT {
+            bits: SignificantBits::Grey(3),
+        });
+        round_trip(Chunk_sBIT {
+            bits: SignificantBits::Rgba(5, 6, 5, 1),
+        });
+        let color = SuggestedColor {
+            red: 255,
+            green: 128,
+            blue: 0,
+            alpha: 255,
+            frequency: 1000,
+        };
+        round_trip(Chunk_sPLT::new("Web safe", 8, vec![color; 3]).unwrap());
+        let deep = SuggestedColor {
+            red: 65535,
+            ..color
+        };
+        round_trip(Chunk_sPLT::new("Deep", 16, vec![color, deep]).unwrap());
+        round_trip(Chunk_sPLT::new("Empty", 16, Vec::new()).unwrap());
+        round_trip(Chunk_bKGD {
+            color: Background::Grey(1000),
+        });
+        round_trip(Chunk_bKGD {
+            color: Background::Rgb(255, 0, 65535),
+        });
+        round_trip(Chunk_bKGD {
+            color: Background::PaletteIndex(3),
+        });
+        round_trip(Chunk_cICP {
+            colour_primaries: 9,
+            transfer_function: 16,
+            matrix_coefficients: 0,
+            video_full_range: true,
+        });
+        round_trip(Chunk_mDCV {
+            red_x: 35400,
+            red_y: 14600,
+            green_x: 8500,
+            green_y: 39850,
+            blue_x: 6550,
+            blue_y: 2300,
+            white_x: 15635,
+            white_y: 16450,
+            max_luminance: 10_000_000,
+            min_luminance: 50,
+        });
+        round_trip(Chunk_cLLI {
+            max_content_light_level: 4_000_000,
+            max_frame_average_light_level: 1_000_000,
+        });
+        round_trip(Chunk_hIST {
+            frequencies: vec![0, 1, 65535],
+        });
+        round_trip(Chunk_acTL {
+            num_frames: 3,
+            num_plays: 0,
+        });
+        round_trip(Chunk_fcTL {
+            sequence_number: 5,
+            width: 10,
+            height: 20,
+            x_offset: 1,
+            y_offset: 2,
+            delay_num: 1,
+            delay_den: 30,
+            dispose_op: DisposeOp::Previous,
+            blend_op: BlendOp::Over,
+        });
+        round_trip(Chunk_fdAT {
+            sequence_number: 6,
+            data: vec![8, 9],
+        });
+    }
+
+    #[test]
+    fn test_background_matches_color_type() {
+        let grey = Chunk_bKGD {
+            color: Background::Grey(0),
+        };
+        assert!(grey.matches(ColorType::GRYA));
+        assert!(!grey.matches(ColorType::RGB));
+        let index = Chunk_bKGD {
+            color: Background::PaletteIndex(0),
+        };
+        assert!(index.matches(ColorType::PLT));
+        assert!(!index.matches(ColorType::RGBA));
+    }
+
+    #[test]
+    fn test_invalid_iccp() {
+        let chunk = Chunk::new(Chunk_iCCP::chunk_type(), b"sRGB\0\0garbage".to_vec());
+        assert!(matches!(
+            Chunk_iCCP::decode(&chunk),
+            Err(PngError::InvalidField {
+                field: "profile",
+                ..
+            })
+        ));
+        let chunk = Chunk::new(Chunk_iCCP::chunk_type(), b"sRGB\0".to_vec());
+        assert!(matches!(
+            Chunk_iCCP::decode(&chunk),
+            Err(PngError::InvalidField {
+                field: "compression_method",
+                ..
+            })
+        ));
+        for name in ["\u{100}", "", " sRGB", "sRGB ", "Display  P3"].iter() {
+            assert!(matches!(
+                Chunk_iCCP::new(name, vec![1]),
+                Err(PngError::InvalidField {
+                    chunk: "iCCP",
+                    field: "keyword",
+                    ..
+                })
+            ));
+        }
+        assert!(Chunk_iCCP::new(&"a".repeat(80), vec![1]).is_err());
+    }
+
+    #[test]
+    fn test_significant_bits_check() {
+        let sbit = |bits| Chunk_sBIT { bits };
+        let mut ihdr = testing_ihdr();
+        assert!(sbit(SignificantBits::Rgba(8, 8, 8, 1)).check(&ihdr).is_ok());
+        assert!(sbit(SignificantBits::Rgb(8, 8, 8)).check(&ihdr).is_err());
+        assert!(sbit(SignificantBits::Rgba(8, 9, 8, 8))
+            .check(&ihdr)
+            .is_err());
+        // Palette colours are always 8 bit, whatever the index depth
+        ihdr.color_type = ColorType::PLT;
+        ihdr.bit_depth = 2;
+        assert!(sbit(SignificantBits::Rgb(8, 8, 8)).check(&ihdr).is_ok());
+        ihdr.color_type = ColorType::GRY;
+        assert!(sbit(SignificantBits::Grey(3)).check(&ihdr).is_err());
+
+        let raw = |data: &[u8]| Chunk::new(Chunk_sBIT::chunk_type(), data.to_vec());
+        assert_eq!(invalid_field_name(Chunk_sBIT::decode(&raw(&[]))), "length");
+        assert_eq!(
+            invalid_field_name(Chunk_sBIT::decode(&raw(&[1; 5]))),
+            "length"
+        );
+        assert_eq!(
+            invalid_field_name(Chunk_sBIT::decode(&raw(&[8, 0]))),
+            "bits"
+        );
+    }
+
+    #[test]
+    fn test_invalid_suggested_palette() {
+        let raw = |data: &[u8]| Chunk::new(Chunk_sPLT::chunk_type(), data.to_vec());
+        assert_eq!(
+            invalid_field_name(Chunk_sPLT::decode(&raw(b"Web\0"))),
+            "sample_depth"
+        );
+        assert_eq!(
+            invalid_field_name(Chunk_sPLT::decode(&raw(b"Web\0\x04"))),
+            "sample_depth"
+        );
+        assert_eq!(
+            invalid_field_name(Chunk_sPLT::decode(&raw(b"Web\0\x08\x01\x02\x03\x04\x00"))),
+            "length"
+        );
+        assert_eq!(
+            invalid_field_name(Chunk_sPLT::decode(&raw(b" Web\0\x08"))),
+            "keyword"
+        );
+        let color = SuggestedColor {
+            red: 256,
+            green: 0,
+            blue: 0,
+            alpha: 0,
+            frequency: 0,
+        };
+        assert!(Chunk_sPLT::new("Web", 8, vec![color]).is_err());
+        assert!(Chunk_sPLT::new("Web", 16, vec![color]).is_ok());
+    }
+
+    #[test]
+    fn test_iend_header_spells_iend() {
+        assert_eq!(&Chunk_IEND::HEADER, b"IEND");
+    }
+
+    #[test]
+    fn test_decode_wrong_type() {
+        let chunk = Chunk_gAMA { gamma: 1 }.encode();
+        assert!(matches!(
+            Chunk_IHDR::decode(&chunk),
+            Err(PngError::InvalidField { field: "type", .. })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_palette_length() {
+        let chunk = Chunk::new(Chunk_PLTE::chunk_type(), vec![0; 4]);
+        assert!(matches!(
+            Chunk_PLTE::decode(&chunk),
+            Err(PngError::InvalidField { chunk: "PLTE", .. })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_time() {
+        let mut data = Chunk_tIME {
+            year: 2021,
+            month: 6,
+            day: 30,
+            hour: 12,
+            minute: 0,
+            second: 0,
+        }
+        .encode()
+        .data()
+        .to_vec();
+        data[2] = 13;
+        let chunk = Chunk::new(Chunk_tIME::chunk_type(), data);
+        assert!(matches!(
+            Chunk_tIME::decode(&chunk),
+            Err(PngError::InvalidField { field: "month", .. })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_frame_control() {
+        let control = Chunk_fcTL {
+            sequence_number: 0,
+            width: 4,
+            height: 4,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 0,
+            delay_den: 0,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        };
+        assert_eq!(control.delay(), 0.0);
+        let mut data = control.encode().data().to_vec();
+        data[24] = 3;
+        let chunk = Chunk::new(Chunk_fcTL::chunk_type(), data);
+        assert!(matches!(
+            Chunk_fcTL::decode(&chunk),
+            Err(PngError::InvalidField {
+                field: "dispose_op",
+                ..
+            })
+        ));
+        let mut data = control.encode().data().to_vec();
+        data[4..8].copy_from_slice(&[0; 4]);
+        let chunk = Chunk::new(Chunk_fcTL::chunk_type(), data);
+        assert!(matches!(
+            Chunk_fcTL::decode(&chunk),
+            Err(PngError::InvalidField { field: "width", .. })
+        ));
+        let chunk = Chunk::new(Chunk_fdAT::chunk_type(), vec![0, 0]);
+        assert!(Chunk_fdAT::decode(&chunk).is_err());
+    }
+
+    #[test]
+    fn test_palette_check() {
+        let mut ihdr = testing_ihdr();
+        ihdr.color_type = ColorType::PLT;
+        ihdr.bit_depth = 2;
+        let palette = Chunk_PLTE {
+            entries: vec![[0; 3]; 4],
+        };
+        assert!(palette.check(&ihdr).is_ok());
+        let too_many = Chunk_PLTE {
+            entries: vec![[0; 3]; 5],
+        };
+        assert!(matches!(
+            too_many.check(&ihdr),
+            Err(PngError::InvalidField {
+                field: "entries",
+                ..
+            })
+        ));
+        ihdr.color_type = ColorType::RGB;
+        ihdr.bit_depth = 8;
+        assert!(too_many.check(&ihdr).is_ok());
+        ihdr.color_type = ColorType::GRYA;
+        assert!(palette.check(&ihdr).is_err());
+    }
+
+    #[test]
+    fn test_transparency_per_color_type() {
+        let trns = |data: &[u8]| Chunk::new(Chunk_tRNS::chunk_type(), data.to_vec());
+        let mut ihdr = testing_ihdr();
+        ihdr.color_type = ColorType::PLT;
+        let palette = Chunk_PLTE {
+            entries: vec![[0; 3]; 2],
+        };
+        // Two bytes are two palette alphas here, a grey key below
+        let t = Chunk_tRNS::decode(&trns(&[0, 128]), &ihdr, Some(&palette)).unwrap();
+        assert_eq!(t.transparency, Transparency::Palette(vec![0, 128]));
+        assert_eq!(t.palette_alpha(1), 128);
+        assert_eq!(t.palette_alpha(5), 255);
+        assert_eq!(t.encode(), trns(&[0, 128]));
+        assert!(Chunk_tRNS::decode(&trns(&[0, 0, 0]), &ihdr, Some(&palette)).is_err());
+        assert!(Chunk_tRNS::decode(&trns(&[0, 0, 0]), &ihdr, None).is_ok());
+
+        ihdr.color_type = ColorType::GRY;
+        let t = Chunk_tRNS::decode(&trns(&[0, 128]), &ihdr, None).unwrap();
+        assert_eq!(t.transparency, Transparency::Grey(128));
+        assert!(Chunk_tRNS::decode(&trns(&[1, 0]), &ihdr, None).is_err());
+        ihdr.bit_depth = 16;
+        assert!(Chunk_tRNS::decode(&trns(&[1, 0]), &ihdr, None).is_ok());
+
+        ihdr.color_type = ColorType::RGB;
+        let t = Chunk_tRNS::decode(&trns(&[0, 1, 0, 2, 0, 3]), &ihdr, None).unwrap();
+        assert_eq!(t.transparency, Transparency::Rgb(1, 2, 3));
+        assert_eq!(t.encode(), trns(&[0, 1, 0, 2, 0, 3]));
+        assert!(Chunk_tRNS::decode(&trns(&[0, 1]), &ihdr, None).is_err());
+
+        ihdr.color_type = ColorType::RGBA;
+        assert!(matches!(
+            Chunk_tRNS::decode(&trns(&[0, 1]), &ihdr, None),
+            Err(PngError::InvalidField { field: "type", .. })
+        ));
+    }
+
+    #[test]
+    fn test_histogram_check() {
+        let hist = Chunk_hIST {
+            frequencies: vec![1, 2],
+        };
+        assert!(hist
+            .check(&Chunk_PLTE {
+                entries: vec![[0; 3]; 2]
+            })
+            .is_ok());
+        assert!(hist
+            .check(&Chunk_PLTE {
+                entries: vec![[0; 3]; 3]
+            })
+            .is_err());
+        let odd = Chunk::new(Chunk_hIST::chunk_type(), vec![0; 3]);
+        assert!(Chunk_hIST::decode(&odd).is_err());
+    }
+
+    #[test]
+    fn test_colour_space_values() {
+        let gama = Chunk_gAMA::from_value(1.0 / 2.2).unwrap();
+        assert_eq!(gama.gamma, 45455);
+        assert!((gama.value() - 0.45455).abs() < 1e-9);
+        for &bad in [0.0, -0.45, 1e-6, f64::NAN, f64::INFINITY, 1e10].iter() {
+            assert_eq!(invalid_field_name(Chunk_gAMA::from_value(bad)), "gamma");
+        }
+        let zero = Chunk::new(Chunk_gAMA::chunk_type(), vec![0; 4]);
+        assert!(Chunk_gAMA::decode(&zero).is_err());
+
+        let chrm = Chunk_cHRM {
+            white_x: 31270,
+            white_y: 32900,
+            red_x: 64000,
+            red_y: 33000,
+            green_x: 30000,
+            green_y: 60000,
+            blue_x: 15000,
+            blue_y: 6000,
+        };
+        assert_eq!(chrm.white_point(), (0.3127, 0.329));
+        assert_eq!(chrm.primaries(), [(0.64, 0.33), (0.3, 0.6), (0.15, 0.06)]);
+
+        let mdcv = Chunk_mDCV {
+            red_x: 35400,
+            red_y: 14600,
+            green_x: 8500,
+            green_y: 39850,
+            blue_x: 6550,
+            blue_y: 2300,
+            white_x: 15635,
+            white_y: 16450,
+            max_luminance: 10_000_000,
+            min_luminance: 50,
+        };
+        assert_eq!(mdcv.white_point(), (0.3127, 0.329));
+        assert_eq!(mdcv.primaries()[0], (0.708, 0.292));
+        assert_eq!(mdcv.luminance(), (0.005, 1000.0));
+    }
+
+    #[test]
+    fn test_invalid_hdr_chunks() {
+        let cicp = |data: [u8; 4]| {
+            Chunk_cICP::decode(&Chunk::new(Chunk_cICP::chunk_type(), data.to_vec()))
+        };
+        assert!(cicp([1, 13, 0, 1]).is_ok());
+        assert!(matches!(
+            cicp([1, 13, 1, 1]),
+            Err(PngError::InvalidField {
+                field: "matrix_coefficients",
+                ..
+            })
+        ));
+        assert!(matches!(
+            cicp([1, 13, 0, 2]),
+            Err(PngError::InvalidField {
+                field: "video_full_range",
+                ..
+            })
+        ));
+
+        let mut data = vec![0; 24];
+        data[16..20].copy_from_slice(&5u32.to_be_bytes());
+        data[20..24].copy_from_slice(&5u32.to_be_bytes());
+        let chunk = Chunk::new(Chunk_mDCV::chunk_type(), data);
+        assert!(Chunk_mDCV::decode(&chunk).is_err());
+        let chunk = Chunk::new(Chunk_cLLI::chunk_type(), vec![0; 7]);
+        assert!(Chunk_cLLI::decode(&chunk).is_err());
+    }
 }
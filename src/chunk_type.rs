@@ -4,7 +4,7 @@ use std::{
     str::FromStr,
 };
 
-use crate::{throw_string_error, Error, Result};
+use crate::{PngError, Result};
 
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub struct ChunkType {
@@ -12,29 +12,26 @@ pub struct ChunkType {
 }
 
 impl TryFrom<[u8; 4]> for ChunkType {
-    type Error = Error;
+    type Error = PngError;
     fn try_from(value: [u8; 4]) -> Result<Self> {
         let ret = ChunkType { ctype: value };
         if ret.is_valid() {
             return Ok(ret);
         } else {
-            return Err(throw_string_error("Invalid chunk type"));
+            return Err(PngError::InvalidChunkType(value.to_vec()));
         }
     }
 }
 
 impl FromStr for ChunkType {
-    type Err = Error;
+    type Err = PngError;
     fn from_str(s: &str) -> Result<Self> {
+        let invalid = || PngError::InvalidChunkType(s.as_bytes().to_vec());
         if !s.chars().all(|c| c.is_ascii_alphabetic()) {
-            return Err(throw_string_error("Character value out of range"));
+            return Err(invalid());
         }
-        if s.len() != 4 {
-            return Err(throw_string_error("String length incorrect size"));
-        }
-        return Ok(ChunkType {
-            ctype: s.as_bytes().try_into()?,
-        });
+        let ctype: [u8; 4] = s.as_bytes().try_into().map_err(|_| invalid())?;
+        return Ok(ChunkType { ctype });
     }
 }
 
@@ -71,7 +68,7 @@ impl ChunkType {
 fn bytes_alphabetic(value: [u8; 4]) -> bool {
     return value
         .iter()
-        .all(|&b| ((b >= 65) && (b <= 90)) || (b >= 97) && (b <= 122));
+        .all(|&b| (65..=90).contains(&b) || (97..=122).contains(&b));
 }
 
 #[cfg(test)]
@@ -158,6 +155,15 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    pub fn test_invalid_chunk_type_error() {
+        let chunk = ChunkType::from_str("RuStY");
+        assert!(matches!(chunk, Err(PngError::InvalidChunkType(b)) if b == b"RuStY"));
+
+        let chunk = ChunkType::try_from([82, 117, 115, 116]);
+        assert!(matches!(chunk, Err(PngError::InvalidChunkType(_))));
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
@@ -0,0 +1,270 @@
+use std::{
+    env,
+    io::{self, BufRead, IsTerminal, Write},
+    str::FromStr,
+};
+
+use pngme::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    crypto::{decrypt, encrypt},
+    exif::{tag_by_name, tag_name, Ifd},
+    png_util::{read_png, read_png_with, write_png},
+    recovery::{ParseOptions, Recovery, Strictness},
+    split::split_payload,
+    stego::LsbOptions,
+    text::text_chunk,
+    PngError, Result,
+};
+
+use crate::args::{
+    DecodeArgs, EmbedArgs, EncodeArgs, ExifArgs, ExtractArgs, FramesArgs, PrintArgs, RemoveArgs,
+    RepairArgs,
+};
+
+/*
+ * Implementations of the pngme subcommands
+ */
+
+// Where --passphrase looks for the passphrase before falling back to stdin
+const PASSPHRASE_VAR: &str = "PNGME_PASSPHRASE";
+
+// The passphrase for --passphrase, from PNGME_PASSPHRASE or else the first
+// line of stdin. Unlike an argument, neither shows up in `ps` or shell history
+fn read_passphrase() -> Result<String> {
+    let passphrase = match env::var_os(PASSPHRASE_VAR) {
+        Some(value) => value
+            .into_string()
+            .map_err(|_| PngError::Unsupported(format!("{} is not valid UTF-8", PASSPHRASE_VAR)))?,
+        None => {
+            let stdin = io::stdin();
+            if stdin.is_terminal() {
+                eprint!("Passphrase: ");
+                io::stderr().flush()?;
+            }
+            let mut line = String::new();
+            stdin.lock().read_line(&mut line)?;
+            line.trim_end_matches(&['\r', '\n'][..]).to_string()
+        }
+    };
+    if passphrase.is_empty() {
+        return Err(PngError::Unsupported(format!(
+            "an empty passphrase; set {} or pass it on stdin",
+            PASSPHRASE_VAR
+        )));
+    }
+    Ok(passphrase)
+}
+
+// Standard text chunks are chosen between by size and content, so any of
+// their names selects all of them
+fn is_text_type(chunk_type: &str) -> bool {
+    chunk_type == "tEXt" || chunk_type == "zTXt" || chunk_type == "iTXt"
+}
+
+pub fn encode(args: &EncodeArgs) -> Result<()> {
+    let mut png = read_png(&args.file_path)?;
+    let chunks = if is_text_type(&args.chunk_type) {
+        if args.passphrase || args.parts.is_some() {
+            return Err(PngError::Unsupported(
+                "encrypted or split messages need a private chunk type, not a text chunk"
+                    .to_string(),
+            ));
+        }
+        vec![text_chunk(&args.keyword, &args.message)?]
+    } else {
+        let chunk_type = ChunkType::from_str(&args.chunk_type)?;
+        let payload = if args.passphrase {
+            encrypt(&read_passphrase()?, args.message.as_bytes())?
+        } else {
+            args.message.as_bytes().to_vec()
+        };
+        match args.parts {
+            Some(parts) => split_payload(&chunk_type, &payload, parts)?,
+            None => vec![Chunk::new(chunk_type, payload)],
+        }
+    };
+    for chunk in chunks {
+        png.append_chunk(chunk);
+    }
+    let output = args.output.as_ref().unwrap_or(&args.file_path);
+    write_png(output, &png)?;
+    Ok(())
+}
+
+fn decode_text(args: &DecodeArgs) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    for entry in png.text_entries() {
+        let (keyword, text) = entry?;
+        if args.keyword.as_ref().is_none_or(|k| *k == keyword) {
+            println!("{}", text);
+            return Ok(());
+        }
+    }
+    let wanted = match &args.keyword {
+        Some(k) => format!("{} with keyword {}", args.chunk_type, k),
+        None => args.chunk_type.clone(),
+    };
+    Err(PngError::ChunkNotFound(wanted))
+}
+
+pub fn decode(args: &DecodeArgs) -> Result<()> {
+    if is_text_type(&args.chunk_type) {
+        return decode_text(args);
+    }
+    let png = read_png(&args.file_path)?;
+    let payload = if args.parts {
+        png.reassemble_payload(&args.chunk_type)?
+    } else {
+        match png.chunk_by_type(&args.chunk_type) {
+            Some(chunk) => chunk.data().to_vec(),
+            None => return Err(PngError::ChunkNotFound(args.chunk_type.clone())),
+        }
+    };
+    let message = if args.passphrase {
+        decrypt(&read_passphrase()?, &payload)?
+    } else {
+        payload
+    };
+    println!("{}", String::from_utf8(message)?);
+    Ok(())
+}
+
+pub fn remove(args: &RemoveArgs) -> Result<()> {
+    let mut png = read_png(&args.file_path)?;
+    let removed = png.remove_chunk(&args.chunk_type)?;
+    write_png(&args.file_path, &png)?;
+    println!("Removed {} chunk", removed.chunk_type());
+    Ok(())
+}
+
+pub fn print(args: &PrintArgs) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    print!("{}", png);
+    Ok(())
+}
+
+pub fn repair(args: &RepairArgs) -> Result<()> {
+    let options = ParseOptions {
+        strictness: Strictness::Lenient,
+    };
+    let (mut png, report) = read_png_with(&args.file_path, &options)?;
+    // Bad CRCs are listed below, once per chunk, along with the fix
+    for recovery in report.recoveries.iter() {
+        if !matches!(recovery, Recovery::CorruptCrc { .. }) {
+            println!("{}", recovery);
+        }
+    }
+    let fixes = if args.dry_run {
+        png.crc_mismatches()
+    } else {
+        png.repair_crcs()
+    };
+    for fix in fixes.iter() {
+        println!("{}", fix);
+    }
+    if fixes.is_empty() && report.is_clean() {
+        println!("No problems found");
+    }
+    if !args.dry_run {
+        let output = args.output.as_ref().unwrap_or(&args.file_path);
+        write_png(output, &png)?;
+        println!("Repaired {} of {} chunks", fixes.len(), png.chunks().len());
+    }
+    Ok(())
+}
+
+pub fn embed(args: &EmbedArgs) -> Result<()> {
+    let mut png = read_png(&args.file_path)?;
+    let payload = if args.passphrase {
+        encrypt(&read_passphrase()?, args.message.as_bytes())?
+    } else {
+        args.message.as_bytes().to_vec()
+    };
+    let options = LsbOptions {
+        bits_per_channel: args.bits,
+        palette_safe: args.palette_safe,
+        ..LsbOptions::default()
+    };
+    png.embed_lsb(&payload, &options)?;
+    let output = args.output.as_ref().unwrap_or(&args.file_path);
+    write_png(output, &png)?;
+    Ok(())
+}
+
+pub fn extract(args: &ExtractArgs) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    let options = LsbOptions {
+        bits_per_channel: args.bits,
+        ..LsbOptions::default()
+    };
+    let payload = png.extract_lsb(&options)?;
+    let message = if args.passphrase {
+        decrypt(&read_passphrase()?, &payload)?
+    } else {
+        payload
+    };
+    println!("{}", String::from_utf8(message)?);
+    Ok(())
+}
+
+pub fn frames(args: &FramesArgs) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    let frames = png.frames()?;
+    std::fs::create_dir_all(&args.output_dir)?;
+    for (n, frame) in frames.iter().enumerate() {
+        let path = args.output_dir.join(format!("frame_{:03}.png", n));
+        write_png(&path, &frame.to_png()?)?;
+        println!(
+            "{}: {}x{} at ({}, {}), {} s",
+            path.display(),
+            frame.control.width,
+            frame.control.height,
+            frame.control.x_offset,
+            frame.control.y_offset,
+            frame.control.delay()
+        );
+    }
+    Ok(())
+}
+
+pub fn exif(args: &ExifArgs) -> Result<()> {
+    let mut png = read_png(&args.file_path)?;
+    let mut exif = png
+        .metadata()
+        .exif()?
+        .ok_or_else(|| PngError::ChunkNotFound("eXIf".to_string()))?;
+    if args.remove.is_empty() && !args.remove_thumbnail {
+        for &ifd in Ifd::ALL.iter() {
+            for tag in exif.tags(ifd) {
+                let name = tag_name(ifd, tag.id)
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("{:#06x}", tag.id));
+                println!("{:?} {}: {}", ifd, name, tag.value);
+            }
+        }
+        if let Some((lat, lon)) = exif.gps_position() {
+            println!("GPS position: {:.6}, {:.6}", lat, lon);
+        }
+        if let Some(thumbnail) = exif.thumbnail() {
+            println!("Thumbnail: {} bytes", thumbnail.len());
+        }
+        return Ok(());
+    }
+
+    for name in args.remove.iter() {
+        let (ifd, id) = tag_by_name(name)
+            .ok_or_else(|| PngError::Unsupported(format!("unknown Exif tag {}", name)))?;
+        match exif.remove(ifd, id) {
+            Some(_) => println!("Removed {}", name),
+            None => println!("{} not present", name),
+        }
+    }
+    if args.remove_thumbnail {
+        exif.remove_thumbnail();
+        println!("Removed thumbnail");
+    }
+    png.set_exif(&exif);
+    let output = args.output.as_ref().unwrap_or(&args.file_path);
+    write_png(output, &png)
+}
@@ -0,0 +1,141 @@
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+
+use crate::{chunk::Chunk, chunk_type::ChunkType, PngError, Result};
+
+/*
+ * Passphrase encryption for hidden messages. The payload is
+ *
+ *   version (1) | salt (16) | nonce (12) | ChaCha20-Poly1305 ciphertext and tag
+ *
+ * with the key derived from the passphrase and salt by Argon2id
+ */
+
+pub const VERSION: u8 = 1;
+pub const SALT_LENGTH: usize = 16;
+pub const NONCE_LENGTH: usize = 12;
+pub const HEADER_LENGTH: usize = 1 + SALT_LENGTH + NONCE_LENGTH;
+pub const TAG_LENGTH: usize = 16;
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Key {
+    let mut key = Key::default();
+    // Only fails for out of range parameters or salt lengths, neither possible here
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .unwrap();
+    return key;
+}
+
+fn random_bytes(buf: &mut [u8]) -> Result<()> {
+    getrandom::getrandom(buf).map_err(|e| PngError::Io(e.into()))?;
+    return Ok(());
+}
+
+// Encrypt `plaintext` under `passphrase` with a fresh salt and nonce
+pub fn encrypt(passphrase: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let mut header = [0u8; HEADER_LENGTH];
+    header[0] = VERSION;
+    random_bytes(&mut header[1..])?;
+    let salt = &header[1..1 + SALT_LENGTH];
+    let nonce = Nonce::from_slice(&header[1 + SALT_LENGTH..]);
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, salt));
+    // Encryption can only fail for inputs far beyond a chunk's size
+    let ciphertext = cipher.encrypt(nonce, plaintext).unwrap();
+    let mut payload = header.to_vec();
+    payload.extend(ciphertext);
+    return Ok(payload);
+}
+
+// Check and strip the encryption of a payload made by `encrypt`
+pub fn decrypt(passphrase: &str, payload: &[u8]) -> Result<Vec<u8>> {
+    if payload.len() < HEADER_LENGTH + TAG_LENGTH {
+        return Err(PngError::InvalidPayload(format!(
+            "{} bytes is too short for an encrypted message",
+            payload.len()
+        )));
+    }
+    if payload[0] != VERSION {
+        return Err(PngError::InvalidPayload(format!(
+            "unknown encryption version {}",
+            payload[0]
+        )));
+    }
+    let salt = &payload[1..1 + SALT_LENGTH];
+    let nonce = Nonce::from_slice(&payload[1 + SALT_LENGTH..HEADER_LENGTH]);
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, salt));
+    return cipher
+        .decrypt(nonce, &payload[HEADER_LENGTH..])
+        .map_err(|_| PngError::AuthenticationFailed);
+}
+
+pub fn encrypted_chunk(chunk_type: ChunkType, passphrase: &str, message: &[u8]) -> Result<Chunk> {
+    return Ok(Chunk::new(chunk_type, encrypt(passphrase, message)?));
+}
+
+pub fn decrypt_chunk(chunk: &Chunk, passphrase: &str) -> Result<Vec<u8>> {
+    return decrypt(passphrase, chunk.data());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_round_trip() {
+        let payload = encrypt("hunter2", b"meet at noon").unwrap();
+        assert_eq!(payload.len(), HEADER_LENGTH + 12 + TAG_LENGTH);
+        assert_eq!(payload[0], VERSION);
+        assert!(!payload.windows(4).any(|w| w == b"meet"));
+        assert_eq!(decrypt("hunter2", &payload).unwrap(), b"meet at noon");
+    }
+
+    #[test]
+    fn test_fresh_salt_and_nonce() {
+        let a = encrypt("pass", b"same").unwrap();
+        let b = encrypt("pass", b"same").unwrap();
+        assert_ne!(a[1..HEADER_LENGTH], b[1..HEADER_LENGTH]);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_wrong_passphrase_or_tampering() {
+        let mut payload = encrypt("right", b"secret").unwrap();
+        assert!(matches!(
+            decrypt("wrong", &payload),
+            Err(PngError::AuthenticationFailed)
+        ));
+        let last = payload.len() - 1;
+        payload[last] ^= 1;
+        assert!(matches!(
+            decrypt("right", &payload),
+            Err(PngError::AuthenticationFailed)
+        ));
+    }
+
+    #[test]
+    fn test_malformed_payload() {
+        assert!(matches!(
+            decrypt("pass", b"short"),
+            Err(PngError::InvalidPayload(_))
+        ));
+        let mut payload = encrypt("pass", b"").unwrap();
+        payload[0] = 9;
+        assert!(matches!(
+            decrypt("pass", &payload),
+            Err(PngError::InvalidPayload(_))
+        ));
+    }
+
+    #[test]
+    fn test_chunk_round_trip() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let chunk = encrypted_chunk(chunk_type, "pass", b"in a chunk").unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "ruSt");
+        assert_eq!(decrypt_chunk(&chunk, "pass").unwrap(), b"in a chunk");
+    }
+}
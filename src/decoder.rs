@@ -0,0 +1,605 @@
+use std::{convert::TryFrom, io::Read};
+
+use flate2::read::ZlibDecoder;
+
+use crate::{
+    chunk_spec::{
+        ChunkSpec, Chunk_IDAT, Chunk_IHDR, Chunk_PLTE, Chunk_tRNS, ColorType, InterlaceMethod,
+        Transparency,
+    },
+    error::invalid_field,
+    filter::{unfilter_scanline, FilterType},
+    interlace::{deinterlace, ADAM7},
+    png::Png,
+    PngError, Result,
+};
+
+/*
+ * Turns the IDAT stream back into raw pixel samples: inflate the joined
+ * chunk data, then reverse the per-scanline filters
+ */
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct PixelBuffer {
+    pub width: u32,
+    pub height: u32,
+    pub color_type: ColorType,
+    pub bit_depth: u8,
+    // Scanlines without filter bytes, packed exactly as in the PNG: samples
+    // below 8 bits share bytes, 16 bit samples are big endian
+    pub data: Vec<u8>,
+}
+
+impl PixelBuffer {
+    pub fn stride(&self) -> usize {
+        row_bytes(self.width, self.color_type, self.bit_depth)
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        filter_bpp(self.color_type, self.bit_depth)
+    }
+
+    pub fn row(&self, y: u32) -> &[u8] {
+        let stride = self.stride();
+        &self.data[y as usize * stride..(y as usize + 1) * stride]
+    }
+
+    // Widen to 8 bit RGBA: palette indices are looked up in `palette`, 16 bit
+    // samples keep their high byte and smaller ones are scaled up. Alpha comes
+    // from the alpha channel, or else from `trns`
+    pub fn to_rgba8(
+        &self,
+        palette: Option<&Chunk_PLTE>,
+        trns: Option<&Chunk_tRNS>,
+    ) -> Result<PixelBuffer> {
+        let channels = self.color_type.channels();
+        let depth = self.bit_depth;
+        let scale = |v: u16| -> u8 {
+            match depth {
+                16 => (v >> 8) as u8,
+                8 => v as u8,
+                bits => (v as u32 * 255 / ((1u32 << bits) - 1)) as u8,
+            }
+        };
+        let transparency = trns.map(|t| &t.transparency);
+        let mut data: Vec<u8> = Vec::with_capacity(self.width as usize * self.height as usize * 4);
+        for y in 0..self.height {
+            let row = self.row(y);
+            for x in 0..self.width as usize {
+                let s = |c: usize| sample(row, x * channels + c, depth);
+                let pixel = match self.color_type {
+                    ColorType::GRY => {
+                        let g = s(0);
+                        let a = if transparency == Some(&Transparency::Grey(g)) {
+                            0
+                        } else {
+                            255
+                        };
+                        [scale(g), scale(g), scale(g), a]
+                    }
+                    ColorType::GRYA => [scale(s(0)), scale(s(0)), scale(s(0)), scale(s(1))],
+                    ColorType::RGB => {
+                        let (r, g, b) = (s(0), s(1), s(2));
+                        let a = if transparency == Some(&Transparency::Rgb(r, g, b)) {
+                            0
+                        } else {
+                            255
+                        };
+                        [scale(r), scale(g), scale(b), a]
+                    }
+                    ColorType::RGBA => [scale(s(0)), scale(s(1)), scale(s(2)), scale(s(3))],
+                    ColorType::PLT => {
+                        let index = s(0) as usize;
+                        let entry =
+                            palette.and_then(|p| p.entries.get(index)).ok_or_else(|| {
+                                invalid_field(
+                                    "PLTE",
+                                    "entries",
+                                    format!("no palette entry for index {}", index),
+                                )
+                            })?;
+                        let a = trns.map_or(255, |t| t.palette_alpha(index));
+                        [entry[0], entry[1], entry[2], a]
+                    }
+                };
+                data.extend_from_slice(&pixel);
+            }
+        }
+        return Ok(PixelBuffer {
+            width: self.width,
+            height: self.height,
+            color_type: ColorType::RGBA,
+            bit_depth: 8,
+            data,
+        });
+    }
+}
+
+// Sample `index` of a decoded row, counting across channels, at `bit_depth`
+fn sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+    return match bit_depth {
+        16 => u16::from_be_bytes([row[2 * index], row[2 * index + 1]]),
+        8 => row[index] as u16,
+        bits => {
+            let bits = bits as usize;
+            let shift = 8 - bits - (index * bits) % 8;
+            ((row[index * bits / 8] >> shift) & ((1u8 << bits) - 1)) as u16
+        }
+    };
+}
+
+// Bytes in one unfiltered scanline of `width` pixels
+pub fn row_bytes(width: u32, color_type: ColorType, bit_depth: u8) -> usize {
+    let bits = width as usize * color_type.channels() * bit_depth as usize;
+    bits.div_ceil(8)
+}
+
+// The byte distance the filters look back, which is never less than 1
+pub fn filter_bpp(color_type: ColorType, bit_depth: u8) -> usize {
+    (color_type.channels() * bit_depth as usize / 8).max(1)
+}
+
+// Inflate exactly `expected` bytes. The output grows with what the stream
+// really holds, since `expected` comes from an untrusted header
+pub(crate) fn inflate(compressed: &[u8], expected: usize) -> Result<Vec<u8>> {
+    let mut out: Vec<u8> = Vec::new();
+    ZlibDecoder::new(compressed)
+        .take(expected as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|e| PngError::InvalidImageData(format!("zlib stream is corrupt: {}", e)))?;
+    if out.len() != expected {
+        return Err(PngError::InvalidImageData(format!(
+            "expected {} bytes of scanline data, found {}{}",
+            expected,
+            out.len().min(expected),
+            if out.len() > expected {
+                " and more"
+            } else {
+                ""
+            }
+        )));
+    }
+    return Ok(out);
+}
+
+// Reverse the filters on `height` scanlines of `stride` bytes, each led by its
+// filter type byte
+pub(crate) fn unfilter(
+    filtered: &[u8],
+    stride: usize,
+    height: usize,
+    bpp: usize,
+) -> Result<Vec<u8>> {
+    let mut out: Vec<u8> = vec![0; stride * height];
+    let zero_row: Vec<u8> = vec![0; stride];
+    for y in 0..height {
+        let line = &filtered[y * (stride + 1)..(y + 1) * (stride + 1)];
+        let filter = FilterType::try_from(line[0]).map_err(|v| {
+            PngError::InvalidImageData(format!("unknown filter type {} on scanline {}", v, y))
+        })?;
+        let (done, rest) = out.split_at_mut(y * stride);
+        let prev = if y == 0 {
+            &zero_row[..]
+        } else {
+            &done[(y - 1) * stride..]
+        };
+        let cur = &mut rest[..stride];
+        cur.copy_from_slice(&line[1..]);
+        unfilter_scanline(filter, bpp, prev, cur);
+    }
+    return Ok(out);
+}
+
+impl Png {
+    // All IDAT data, joined in file order
+    pub fn image_data(&self) -> Vec<u8> {
+        return self
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().bytes() == Chunk_IDAT::HEADER)
+            .flat_map(|c| c.data().iter().copied())
+            .collect();
+    }
+
+    // Decode the image into unfiltered pixel samples, merging interlace passes
+    pub fn decode_pixels(&self) -> Result<PixelBuffer> {
+        return decode_stream(&self.image_data(), &self.ihdr()?);
+    }
+
+    // The palette, checked against the image header
+    pub fn palette(&self) -> Result<Option<Chunk_PLTE>> {
+        let palette = match self.get::<Chunk_PLTE>()? {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+        palette.check(&self.ihdr()?)?;
+        return Ok(Some(palette));
+    }
+
+    // The tRNS chunk, decoded as the colour type requires
+    pub fn transparency(&self) -> Result<Option<Chunk_tRNS>> {
+        let chunk = match self
+            .chunks()
+            .iter()
+            .find(|c| c.chunk_type().bytes() == Chunk_tRNS::HEADER)
+        {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        let palette = self.palette()?;
+        return Chunk_tRNS::decode(chunk, &self.ihdr()?, palette.as_ref()).map(Some);
+    }
+
+    // Decode the image as 8 bit RGBA, expanding palette indices and applying
+    // any tRNS transparency
+    pub fn decode_rgba(&self) -> Result<PixelBuffer> {
+        let image = self.decode_pixels()?;
+        let palette = self.palette()?;
+        if image.color_type == ColorType::PLT && palette.is_none() {
+            return Err(PngError::ChunkNotFound(String::from("PLTE")));
+        }
+        return image.to_rgba8(palette.as_ref(), self.transparency()?.as_ref());
+    }
+
+    // Decode each pass of the image on its own. An Adam7 image yields seven
+    // passes in transmission order, some possibly empty; any other image a
+    // single pass holding the whole picture
+    pub fn decode_passes(&self) -> Result<Vec<PixelBuffer>> {
+        return decode_stream_passes(&self.image_data(), &self.ihdr()?);
+    }
+}
+
+// Decode a zlib stream of image data laid out as `ihdr` describes
+pub(crate) fn decode_stream(compressed: &[u8], ihdr: &Chunk_IHDR) -> Result<PixelBuffer> {
+    let mut passes = decode_stream_passes(compressed, ihdr)?;
+    return match ihdr.interlace_method {
+        InterlaceMethod::None => Ok(passes.remove(0)),
+        InterlaceMethod::Adam7 => Ok(deinterlace(&passes, ihdr.width, ihdr.height)),
+    };
+}
+
+pub(crate) fn decode_stream_passes(
+    compressed: &[u8],
+    ihdr: &Chunk_IHDR,
+) -> Result<Vec<PixelBuffer>> {
+    let dims: Vec<(u32, u32)> = match ihdr.interlace_method {
+        InterlaceMethod::None => vec![(ihdr.width, ihdr.height)],
+        InterlaceMethod::Adam7 => ADAM7
+            .iter()
+            .map(|p| p.dimensions(ihdr.width, ihdr.height))
+            .collect(),
+    };
+    let layout: Vec<(usize, usize)> = dims
+        .iter()
+        .map(|&(w, h)| (row_bytes(w, ihdr.color_type, ihdr.bit_depth), h as usize))
+        .collect();
+    // Empty passes carry no scanlines, not even filter bytes
+    let lengths: Vec<usize> = layout
+        .iter()
+        .map(|&(stride, h)| match stride {
+            0 => Some(0),
+            _ => stride.checked_add(1).and_then(|s| s.checked_mul(h)),
+        })
+        .collect::<Option<Vec<usize>>>()
+        .ok_or_else(|| too_large(ihdr))?;
+    let expected = lengths
+        .iter()
+        .try_fold(0usize, |sum, &len| sum.checked_add(len))
+        .ok_or_else(|| too_large(ihdr))?;
+    let filtered = inflate(compressed, expected)?;
+
+    let bpp = filter_bpp(ihdr.color_type, ihdr.bit_depth);
+    let mut at = 0;
+    let mut passes: Vec<PixelBuffer> = Vec::with_capacity(dims.len());
+    for ((&(width, height), &(stride, h)), &len) in
+        dims.iter().zip(layout.iter()).zip(lengths.iter())
+    {
+        passes.push(PixelBuffer {
+            width,
+            height,
+            color_type: ihdr.color_type,
+            bit_depth: ihdr.bit_depth,
+            data: unfilter(&filtered[at..at + len], stride, h, bpp)?,
+        });
+        at += len;
+    }
+    return Ok(passes);
+}
+
+fn too_large(ihdr: &Chunk_IHDR) -> PngError {
+    return PngError::InvalidImageData(format!(
+        "{}x{} image is too large to decode",
+        ihdr.width, ihdr.height
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunk::Chunk,
+        chunk_spec::{Chunk_IEND, CompressionMethod, FilterMethod},
+        png_util::read_png,
+    };
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+        e.write_all(data).unwrap();
+        e.finish().unwrap()
+    }
+
+    fn testing_png(width: u32, color_type: ColorType, bit_depth: u8, filtered: &[u8]) -> Png {
+        let stride = row_bytes(width, color_type, bit_depth);
+        let ihdr = Chunk_IHDR {
+            width,
+            height: (filtered.len() / (stride + 1)) as u32,
+            bit_depth,
+            color_type,
+            compression_method: CompressionMethod::DeflateInflate,
+            filter_method: FilterMethod::Adaptive,
+            interlace_method: InterlaceMethod::None,
+        };
+        let compressed = compress(filtered);
+        let (a, b) = compressed.split_at(compressed.len() / 2);
+        Png::from_chunks(vec![
+            ihdr.encode(),
+            Chunk_IDAT { data: a.to_vec() }.encode(),
+            Chunk_IDAT { data: b.to_vec() }.encode(),
+            Chunk_IEND {}.encode(),
+        ])
+    }
+
+    #[test]
+    fn test_decode_image_files() {
+        let png = read_png("./data/png/rust_crab.png").unwrap();
+        let pixels = png.decode_pixels().unwrap();
+        assert_eq!((pixels.width, pixels.height), (1168, 687));
+        assert_eq!(pixels.data.len(), 1168 * 687 * 4);
+        assert_eq!(Chunk::CHUNK_CRC.checksum(&pixels.data), 823087632);
+
+        // Uses the Sub, Up and Paeth filters across two IDAT chunks
+        let png = read_png("./data/png/transparent.png").unwrap();
+        let pixels = png.decode_pixels().unwrap();
+        assert_eq!(pixels.data.len(), 840 * 859 * 4);
+        assert_eq!(Chunk::CHUNK_CRC.checksum(&pixels.data), 4010088803);
+    }
+
+    #[test]
+    fn test_decode_every_filter() {
+        #[rustfmt::skip]
+        let filtered = [
+            0, 1, 2, 3, 4,
+            1, 1, 1, 1, 1,
+            2, 1, 1, 1, 1,
+            3, 1, 1, 1, 1,
+            4, 1, 1, 1, 1,
+        ];
+        let png = testing_png(2, ColorType::GRYA, 8, &filtered);
+        let pixels = png.decode_pixels().unwrap();
+        #[rustfmt::skip]
+        let expected = vec![
+            1, 2, 3, 4,
+            1, 1, 2, 2,
+            2, 2, 3, 3,
+            2, 2, 3, 3,
+            3, 3, 4, 4,
+        ];
+        assert_eq!(pixels.data, expected);
+        assert_eq!(pixels.stride(), 4);
+        assert_eq!(pixels.row(1), &[1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn test_decode_packed_samples() {
+        // 10 one-bit pixels per row take 2 bytes, and Sub looks back 1 byte
+        let filtered = [0, 0b1010_1010, 0b1100_0000, 1, 0b0000_0001, 0b0100_0000];
+        let png = testing_png(10, ColorType::GRY, 1, &filtered);
+        let pixels = png.decode_pixels().unwrap();
+        assert_eq!(pixels.data, vec![0b1010_1010, 0b1100_0000, 1, 0b0100_0001]);
+    }
+
+    fn gradient_value(x: usize, y: usize) -> [u8; 3] {
+        [(x * 20) as u8, (y * 30) as u8, (x + y) as u8]
+    }
+
+    #[test]
+    fn test_decode_interlaced_files() {
+        let png = read_png("./data/png/adam7_rgb.png").unwrap();
+        let pixels = png.decode_pixels().unwrap();
+        assert_eq!((pixels.width, pixels.height), (10, 7));
+        for y in 0..7 {
+            let row = pixels.row(y as u32);
+            for x in 0..10 {
+                assert_eq!(row[x * 3..x * 3 + 3], gradient_value(x, y));
+            }
+        }
+
+        let png = read_png("./data/png/adam7_grey2.png").unwrap();
+        let pixels = png.decode_pixels().unwrap();
+        assert_eq!(pixels.stride(), 3);
+        for y in 0..5 {
+            let row = pixels.row(y as u32);
+            for x in 0..11 {
+                let v = (row[x / 4] >> (6 - 2 * (x % 4))) & 0b11;
+                assert_eq!(v as usize, (x + y) % 4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_passes() {
+        let png = read_png("./data/png/adam7_rgb.png").unwrap();
+        let passes = png.decode_passes().unwrap();
+        assert_eq!(passes.len(), 7);
+        // Pass 2 holds the single pixel at (4, 0)
+        assert_eq!((passes[1].width, passes[1].height), (1, 1));
+        assert_eq!(passes[1].data, gradient_value(4, 0).to_vec());
+        // Pass 7 holds every odd row in full
+        assert_eq!((passes[6].width, passes[6].height), (10, 3));
+        assert_eq!(passes[6].row(1)[..3], gradient_value(0, 3));
+
+        let png = read_png("./data/png/rust_crab.png").unwrap();
+        let passes = png.decode_passes().unwrap();
+        assert_eq!(passes.len(), 1);
+        assert_eq!(passes[0], png.decode_pixels().unwrap());
+    }
+
+    #[test]
+    fn test_bad_filter_type() {
+        let png = testing_png(1, ColorType::GRY, 8, &[0, 1, 5, 1]);
+        assert!(matches!(
+            png.decode_pixels(),
+            Err(PngError::InvalidImageData(_))
+        ));
+    }
+
+    #[test]
+    fn test_short_image_data() {
+        let mut png = testing_png(1, ColorType::GRY, 8, &[0, 1, 0, 1]);
+        let ihdr = Chunk_IHDR {
+            height: 3,
+            ..png.ihdr().unwrap()
+        };
+        png = Png::from_chunks(
+            std::iter::once(ihdr.encode())
+                .chain(png.chunks()[1..].iter().cloned())
+                .collect(),
+        );
+        assert!(matches!(
+            png.decode_pixels(),
+            Err(PngError::InvalidImageData(_))
+        ));
+    }
+
+    #[test]
+    fn test_corrupt_zlib_stream() {
+        let png = Png::from_chunks(vec![
+            testing_png(1, ColorType::GRY, 8, &[0, 1]).chunks()[0].clone(),
+            Chunk_IDAT {
+                data: vec![1, 2, 3, 4],
+            }
+            .encode(),
+        ]);
+        assert!(matches!(
+            png.decode_pixels(),
+            Err(PngError::InvalidImageData(_))
+        ));
+    }
+
+    #[test]
+    fn test_huge_header_small_data() {
+        let header = testing_png(1, ColorType::RGBA, 16, &[0; 9]).ihdr().unwrap();
+        for &(width, height) in [(0x7fff_ffff, 0x7fff_ffff), (0x7fff_ffff, 1000)].iter() {
+            let ihdr = Chunk_IHDR {
+                width,
+                height,
+                ..header
+            };
+            let png = Png::from_chunks(vec![
+                ihdr.encode(),
+                Chunk_IDAT {
+                    data: compress(&[]),
+                }
+                .encode(),
+                Chunk_IEND {}.encode(),
+            ]);
+            assert!(matches!(
+                png.decode_pixels(),
+                Err(PngError::InvalidImageData(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_rgba8_conversion() {
+        // 2 bit palette indices 0..3, index 1 half transparent
+        let image = PixelBuffer {
+            width: 4,
+            height: 1,
+            color_type: ColorType::PLT,
+            bit_depth: 2,
+            data: vec![0b00_01_10_11],
+        };
+        let palette = Chunk_PLTE {
+            entries: vec![[1, 2, 3], [4, 5, 6], [7, 8, 9], [10, 11, 12]],
+        };
+        let trns = Chunk_tRNS {
+            transparency: Transparency::Palette(vec![255, 128]),
+        };
+        let rgba = image.to_rgba8(Some(&palette), Some(&trns)).unwrap();
+        assert_eq!((rgba.color_type, rgba.bit_depth), (ColorType::RGBA, 8));
+        assert_eq!(
+            rgba.data,
+            vec![1, 2, 3, 255, 4, 5, 6, 128, 7, 8, 9, 255, 10, 11, 12, 255]
+        );
+        assert!(image.to_rgba8(None, None).is_err());
+
+        // 16 bit grey keyed out by tRNS
+        let image = PixelBuffer {
+            width: 2,
+            height: 1,
+            color_type: ColorType::GRY,
+            bit_depth: 16,
+            data: vec![0x12, 0x34, 0xab, 0xcd],
+        };
+        let trns = Chunk_tRNS {
+            transparency: Transparency::Grey(0x1234),
+        };
+        assert_eq!(
+            image.to_rgba8(None, Some(&trns)).unwrap().data,
+            vec![0x12, 0x12, 0x12, 0, 0xab, 0xab, 0xab, 255]
+        );
+
+        // 1 bit grey scales to black and white
+        let image = PixelBuffer {
+            width: 2,
+            height: 1,
+            color_type: ColorType::GRY,
+            bit_depth: 1,
+            data: vec![0b0100_0000],
+        };
+        assert_eq!(
+            image.to_rgba8(None, None).unwrap().data,
+            vec![0, 0, 0, 255, 255, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn test_decode_indexed_as_rgba() {
+        // One row of 4 bit indices 0, 1, 2
+        let mut png = testing_png(3, ColorType::PLT, 4, &[0, 0x01, 0x20]);
+        assert!(matches!(png.decode_rgba(), Err(PngError::ChunkNotFound(_))));
+        let palette = Chunk_PLTE {
+            entries: vec![[255, 0, 0], [0, 255, 0], [0, 0, 255]],
+        };
+        png.chunks_mut().insert(1, palette.encode());
+        png.chunks_mut().insert(
+            2,
+            Chunk_tRNS {
+                transparency: Transparency::Palette(vec![0]),
+            }
+            .encode(),
+        );
+        assert_eq!(png.palette().unwrap(), Some(palette));
+        assert_eq!(
+            png.decode_rgba().unwrap().data,
+            vec![255, 0, 0, 0, 0, 255, 0, 255, 0, 0, 255, 255]
+        );
+
+        // 17 entries cannot all be reached by a 4 bit index
+        png.chunks_mut()[1] = Chunk_PLTE {
+            entries: vec![[0; 3]; 17],
+        }
+        .encode();
+        assert!(png.palette().is_err());
+        assert!(png.decode_rgba().is_err());
+    }
+
+    #[test]
+    fn test_decode_rgba_keeps_rgba8() {
+        let png = read_png("./data/png/rust_crab.png").unwrap();
+        assert_eq!(png.decode_rgba().unwrap(), png.decode_pixels().unwrap());
+    }
+}
@@ -0,0 +1,529 @@
+use std::io::Write;
+
+use flate2::{write::ZlibEncoder, Compression};
+
+use crate::{
+    chunk::Chunk,
+    chunk_spec::{
+        ChunkSpec, Chunk_IDAT, Chunk_IEND, Chunk_IHDR, Chunk_PLTE, ColorType, CompressionMethod,
+        FilterMethod, InterlaceMethod,
+    },
+    decoder::PixelBuffer,
+    error::invalid_field,
+    filter::{filter_scanline, FilterType},
+    interlace::interlace,
+    png::Png,
+    PngError, Result,
+};
+
+/*
+ * Turns raw pixel samples into IDAT data: filter each scanline, then deflate
+ */
+
+// Largest IDAT chunk the encoder emits; longer streams are split
+pub const IDAT_CHUNK_SIZE: usize = 8192;
+
+const ALL_FILTERS: [FilterType; 5] = [
+    FilterType::None,
+    FilterType::Sub,
+    FilterType::Up,
+    FilterType::Average,
+    FilterType::Paeth,
+];
+
+// How the encoder picks the filter type for each scanline
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum FilterStrategy {
+    // Use the same filter on every line
+    Fixed(FilterType),
+    // Smallest sum of the filtered bytes read as signed distances from zero
+    MinSumAbs,
+    // Deflate every candidate line and keep the one that compresses best
+    BruteForce,
+    // Lowest Shannon entropy over the byte values of the filtered line
+    Entropy,
+}
+
+impl FilterStrategy {
+    // Filtering rarely helps palette or sub-byte images, so leave those
+    // unfiltered and use the cheap heuristic for everything else
+    pub fn default_for(color_type: ColorType, bit_depth: u8) -> FilterStrategy {
+        if color_type == ColorType::PLT || bit_depth < 8 {
+            FilterStrategy::Fixed(FilterType::None)
+        } else {
+            FilterStrategy::MinSumAbs
+        }
+    }
+
+    // Lower is better
+    fn score(self, line: &[u8]) -> u64 {
+        match self {
+            FilterStrategy::Fixed(_) => 0,
+            FilterStrategy::MinSumAbs => {
+                line.iter().map(|&b| (b as i8).unsigned_abs() as u64).sum()
+            }
+            FilterStrategy::BruteForce => {
+                let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+                e.write_all(line).unwrap();
+                e.finish().unwrap().len() as u64
+            }
+            FilterStrategy::Entropy => {
+                let mut counts = [0u32; 256];
+                for &b in line.iter() {
+                    counts[b as usize] += 1;
+                }
+                let n = line.len() as f64;
+                let bits: f64 = counts
+                    .iter()
+                    .filter(|&&c| c > 0)
+                    .map(|&c| {
+                        let p = c as f64 / n;
+                        -p * p.log2()
+                    })
+                    .sum();
+                // Scaled so that ties between close candidates survive the cast
+                (bits * n * 1024.0) as u64
+            }
+        }
+    }
+}
+
+// Append the filtered scanlines of `image` to `out`
+fn filter_image(image: &PixelBuffer, strategy: FilterStrategy, out: &mut Vec<u8>) {
+    let stride = image.stride();
+    if stride == 0 || image.height == 0 {
+        return;
+    }
+    let bpp = image.bytes_per_pixel();
+    let zero_row: Vec<u8> = vec![0; stride];
+    let mut line: Vec<u8> = vec![0; stride];
+    let mut best: Vec<u8> = vec![0; stride];
+    for y in 0..image.height {
+        let prev = if y == 0 {
+            &zero_row[..]
+        } else {
+            image.row(y - 1)
+        };
+        let cur = image.row(y);
+        let chosen = match strategy {
+            FilterStrategy::Fixed(filter) => {
+                filter_scanline(filter, bpp, prev, cur, &mut best);
+                filter
+            }
+            _ => {
+                let mut chosen = FilterType::None;
+                let mut best_score = u64::MAX;
+                for &filter in ALL_FILTERS.iter() {
+                    filter_scanline(filter, bpp, prev, cur, &mut line);
+                    let score = strategy.score(&line);
+                    if score < best_score {
+                        best_score = score;
+                        chosen = filter;
+                        std::mem::swap(&mut line, &mut best);
+                    }
+                }
+                chosen
+            }
+        };
+        out.push(chosen as u8);
+        out.extend_from_slice(&best);
+    }
+}
+
+// The zlib stream that the IDAT chunks of `image` carry
+pub fn encode_image_data(
+    image: &PixelBuffer,
+    interlace_method: InterlaceMethod,
+    strategy: FilterStrategy,
+) -> Vec<u8> {
+    let mut filtered: Vec<u8> = Vec::new();
+    match interlace_method {
+        InterlaceMethod::None => filter_image(image, strategy, &mut filtered),
+        InterlaceMethod::Adam7 => {
+            for pass in interlace(image).iter() {
+                filter_image(pass, strategy, &mut filtered);
+            }
+        }
+    }
+    let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+    e.write_all(&filtered).unwrap();
+    return e.finish().unwrap();
+}
+
+pub(crate) fn idat_chunks(compressed: &[u8]) -> Vec<Chunk> {
+    return compressed
+        .chunks(IDAT_CHUNK_SIZE)
+        .map(|c| Chunk_IDAT { data: c.to_vec() }.encode())
+        .collect();
+}
+
+pub(crate) fn check_buffer(image: &PixelBuffer) -> Result<()> {
+    let expected = image.stride() * image.height as usize;
+    if image.data.len() != expected {
+        return Err(PngError::InvalidImageData(format!(
+            "pixel buffer holds {} bytes, expected {}",
+            image.data.len(),
+            expected
+        )));
+    }
+    return Ok(());
+}
+
+// Builds a complete PNG (IHDR, PLTE if given, IDAT, IEND) from raw pixels
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    image: PixelBuffer,
+    palette: Option<Vec<[u8; 3]>>,
+    interlace_method: InterlaceMethod,
+    filter_strategy: FilterStrategy,
+}
+
+impl Encoder {
+    // `data` holds the scanlines without filter bytes, packed as in `PixelBuffer`
+    pub fn new(
+        width: u32,
+        height: u32,
+        color_type: ColorType,
+        bit_depth: u8,
+        data: Vec<u8>,
+    ) -> Encoder {
+        return Encoder::from_pixels(PixelBuffer {
+            width,
+            height,
+            color_type,
+            bit_depth,
+            data,
+        });
+    }
+
+    pub fn from_pixels(image: PixelBuffer) -> Encoder {
+        let filter_strategy = FilterStrategy::default_for(image.color_type, image.bit_depth);
+        return Encoder {
+            image,
+            filter_strategy,
+            palette: None,
+            interlace_method: InterlaceMethod::None,
+        };
+    }
+
+    // Required for colour type 3; optional (a suggested palette) for RGB and RGBA
+    pub fn with_palette(mut self, palette: Vec<[u8; 3]>) -> Encoder {
+        self.palette = Some(palette);
+        return self;
+    }
+
+    pub fn with_interlace(mut self, interlace_method: InterlaceMethod) -> Encoder {
+        self.interlace_method = interlace_method;
+        return self;
+    }
+
+    pub fn with_filter_strategy(mut self, filter_strategy: FilterStrategy) -> Encoder {
+        self.filter_strategy = filter_strategy;
+        return self;
+    }
+
+    fn check_palette(&self, ihdr: &Chunk_IHDR) -> Result<Option<Chunk_PLTE>> {
+        let palette = match &self.palette {
+            None if ihdr.color_type == ColorType::PLT => {
+                return Err(invalid_field(
+                    "PLTE",
+                    "entries",
+                    "colour type 3 requires a palette".to_string(),
+                ));
+            }
+            None => return Ok(None),
+            Some(palette) => Chunk_PLTE {
+                entries: palette.clone(),
+            },
+        };
+        palette.check(ihdr)?;
+        return Ok(Some(palette));
+    }
+
+    pub fn encode(&self) -> Result<Png> {
+        let ihdr = Chunk_IHDR {
+            width: self.image.width,
+            height: self.image.height,
+            bit_depth: self.image.bit_depth,
+            color_type: self.image.color_type,
+            compression_method: CompressionMethod::DeflateInflate,
+            filter_method: FilterMethod::Adaptive,
+            interlace_method: self.interlace_method,
+        };
+        ihdr.validate()?;
+        check_buffer(&self.image)?;
+
+        let mut chunks: Vec<Chunk> = vec![ihdr.encode()];
+        if let Some(plte) = self.check_palette(&ihdr)? {
+            chunks.push(plte.encode());
+        }
+        chunks.extend(idat_chunks(&encode_image_data(
+            &self.image,
+            self.interlace_method,
+            self.filter_strategy,
+        )));
+        chunks.push(Chunk_IEND {}.encode());
+        return Ok(Png::from_chunks(chunks));
+    }
+}
+
+impl Png {
+    // Replace the image content with `image`, rewriting IHDR to match and
+    // storing it with the requested interlacing and filter strategy
+    pub fn set_pixels(
+        &mut self,
+        image: &PixelBuffer,
+        interlace_method: InterlaceMethod,
+        strategy: FilterStrategy,
+    ) -> Result<()> {
+        let ihdr = Chunk_IHDR {
+            width: image.width,
+            height: image.height,
+            bit_depth: image.bit_depth,
+            color_type: image.color_type,
+            interlace_method,
+            ..self.ihdr()?
+        };
+        ihdr.validate()?;
+        check_buffer(image)?;
+        let idats = idat_chunks(&encode_image_data(image, interlace_method, strategy));
+
+        let chunks = self.chunks_mut();
+        let is_idat = |c: &Chunk| c.chunk_type().bytes() == Chunk_IDAT::HEADER;
+        let at = match chunks.iter().position(is_idat) {
+            Some(i) => i,
+            None => chunks
+                .iter()
+                .position(|c| c.chunk_type().bytes() == Chunk_IEND::HEADER)
+                .unwrap_or(chunks.len()),
+        };
+        let before = chunks[..at].iter().filter(|c| !is_idat(c)).count();
+        chunks.retain(|c| !is_idat(c));
+        let tail = chunks.split_off(before);
+        chunks.extend(idats);
+        chunks.extend(tail);
+        for c in chunks.iter_mut() {
+            if c.chunk_type().bytes() == Chunk_IHDR::HEADER {
+                *c = ihdr.encode();
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decoder::inflate, png_util::read_png};
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_reencode_interlaced() {
+        let mut png = read_png("./data/png/transparent.png").unwrap();
+        let pixels = png.decode_pixels().unwrap();
+
+        png.set_pixels(&pixels, InterlaceMethod::Adam7, FilterStrategy::MinSumAbs)
+            .unwrap();
+        assert_eq!(png.ihdr().unwrap().interlace_method, InterlaceMethod::Adam7);
+        assert!(png.validate().is_empty());
+        assert_eq!(png.decode_passes().unwrap().len(), 7);
+        assert_eq!(png.decode_pixels().unwrap(), pixels);
+
+        png.set_pixels(&pixels, InterlaceMethod::None, FilterStrategy::MinSumAbs)
+            .unwrap();
+        assert_eq!(png.ihdr().unwrap().interlace_method, InterlaceMethod::None);
+        assert_eq!(png.decode_pixels().unwrap(), pixels);
+    }
+
+    #[test]
+    fn test_reencode_packed_interlaced() {
+        let mut png = read_png("./data/png/adam7_grey2.png").unwrap();
+        let pixels = png.decode_pixels().unwrap();
+        png.set_pixels(&pixels, InterlaceMethod::None, FilterStrategy::MinSumAbs)
+            .unwrap();
+        assert_eq!(png.decode_pixels().unwrap(), pixels);
+        png.set_pixels(&pixels, InterlaceMethod::Adam7, FilterStrategy::MinSumAbs)
+            .unwrap();
+        assert_eq!(png.decode_pixels().unwrap(), pixels);
+    }
+
+    #[test]
+    fn test_idat_is_split() {
+        let mut png = read_png("./data/png/rust_crab.png").unwrap();
+        let pixels = png.decode_pixels().unwrap();
+        png.set_pixels(&pixels, InterlaceMethod::None, FilterStrategy::MinSumAbs)
+            .unwrap();
+        let idats = png.get_all::<Chunk_IDAT>().unwrap();
+        assert!(idats.len() > 1);
+        assert!(idats.iter().all(|c| c.data.len() <= IDAT_CHUNK_SIZE));
+        assert!(png.validate().is_empty());
+    }
+
+    #[test]
+    fn test_reencode_with_strategy() {
+        let mut png = read_png("./data/png/rust_crab.png").unwrap();
+        let pixels = png.decode_pixels().unwrap();
+        for &filter in ALL_FILTERS.iter() {
+            png.set_pixels(
+                &pixels,
+                InterlaceMethod::None,
+                FilterStrategy::Fixed(filter),
+            )
+            .unwrap();
+            let stride = pixels.stride() + 1;
+            let expected = stride * pixels.height as usize;
+            let filtered = inflate(&png.image_data(), expected).unwrap();
+            assert!(filtered.chunks(stride).all(|line| line[0] == filter as u8));
+            assert_eq!(png.decode_pixels().unwrap(), pixels);
+        }
+    }
+
+    fn round_trip(encoder: &Encoder) -> PixelBuffer {
+        let png = encoder.encode().unwrap();
+        assert!(png.validate().is_empty());
+        let png = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        return png.decode_pixels().unwrap();
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let data: Vec<u8> = (0..12 * 9 * 3).map(|i| (i * 7 % 256) as u8).collect();
+        let encoder = Encoder::new(12, 9, ColorType::RGB, 8, data);
+        assert_eq!(round_trip(&encoder), encoder.image);
+        let encoder = encoder.with_interlace(InterlaceMethod::Adam7);
+        assert_eq!(round_trip(&encoder), encoder.image);
+
+        let data: Vec<u8> = (0..5 * 4 * 8).map(|i| (i * 13 % 256) as u8).collect();
+        let encoder = Encoder::new(5, 4, ColorType::RGBA, 16, data);
+        assert_eq!(round_trip(&encoder), encoder.image);
+
+        // 1 bit rows of 10 pixels leave the low 6 bits of each second byte unused
+        let data: Vec<u8> = (0..6).flat_map(|i| vec![0xa5 ^ i, 0xc0]).collect();
+        let encoder = Encoder::new(10, 6, ColorType::GRY, 1, data);
+        assert_eq!(round_trip(&encoder), encoder.image);
+    }
+
+    #[test]
+    fn test_filter_strategies_round_trip() {
+        let png = read_png("./data/png/rust_crab.png").unwrap();
+        let pixels = png.decode_pixels().unwrap();
+        for &strategy in [
+            FilterStrategy::Fixed(FilterType::Up),
+            FilterStrategy::MinSumAbs,
+            FilterStrategy::BruteForce,
+            FilterStrategy::Entropy,
+        ]
+        .iter()
+        {
+            let encoder = Encoder::from_pixels(pixels.clone()).with_filter_strategy(strategy);
+            assert_eq!(round_trip(&encoder), pixels);
+        }
+    }
+
+    #[test]
+    fn test_heuristics_pick_filter_per_line() {
+        // Row 0 is a ramp, which Sub flattens; row 2 repeats row 1, which Up zeroes
+        let ramp: Vec<u8> = (0..32).map(|i| i * 5).collect();
+        let noise: Vec<u8> = (0..32).map(|i| (i * 97 % 251) as u8).collect();
+        let data = [ramp, noise.clone(), noise].concat();
+        let image = Encoder::new(32, 3, ColorType::GRY, 8, data).image;
+        for &strategy in [
+            FilterStrategy::MinSumAbs,
+            FilterStrategy::BruteForce,
+            FilterStrategy::Entropy,
+        ]
+        .iter()
+        {
+            let mut filtered: Vec<u8> = Vec::new();
+            filter_image(&image, strategy, &mut filtered);
+            assert_eq!(filtered[0], FilterType::Sub as u8, "{:?}", strategy);
+            assert_eq!(filtered[66], FilterType::Up as u8, "{:?}", strategy);
+        }
+    }
+
+    #[test]
+    fn test_default_strategy() {
+        assert_eq!(
+            FilterStrategy::default_for(ColorType::PLT, 8),
+            FilterStrategy::Fixed(FilterType::None)
+        );
+        assert_eq!(
+            FilterStrategy::default_for(ColorType::GRY, 4),
+            FilterStrategy::Fixed(FilterType::None)
+        );
+        assert_eq!(
+            FilterStrategy::default_for(ColorType::RGBA, 8),
+            FilterStrategy::MinSumAbs
+        );
+    }
+
+    #[test]
+    fn test_encode_existing_image() {
+        let png = read_png("./data/png/rust_crab.png").unwrap();
+        let pixels = png.decode_pixels().unwrap();
+        let encoder = Encoder::from_pixels(pixels.clone());
+        assert_eq!(round_trip(&encoder), pixels);
+    }
+
+    #[test]
+    fn test_encode_palette() {
+        let palette = vec![[255, 0, 0], [0, 255, 0], [0, 0, 255]];
+        let data = vec![0x01, 0x20, 0x12, 0x00];
+        let encoder = Encoder::new(8, 2, ColorType::PLT, 2, data).with_palette(palette.clone());
+        let png = encoder.encode().unwrap();
+        let plte = png.get::<Chunk_PLTE>().unwrap().unwrap();
+        assert_eq!(plte.entries, palette);
+        assert_eq!(png.chunks()[1].chunk_type().to_string(), "PLTE");
+        assert_eq!(round_trip(&encoder), encoder.image);
+    }
+
+    #[test]
+    fn test_encode_bad_palette() {
+        let missing = Encoder::new(2, 1, ColorType::PLT, 8, vec![0, 1]);
+        assert!(matches!(
+            missing.encode(),
+            Err(PngError::InvalidField { chunk: "PLTE", .. })
+        ));
+        let grey = Encoder::new(2, 1, ColorType::GRY, 8, vec![0, 1]).with_palette(vec![[0; 3]]);
+        assert!(matches!(
+            grey.encode(),
+            Err(PngError::InvalidField { chunk: "PLTE", .. })
+        ));
+        let too_many =
+            Encoder::new(2, 1, ColorType::PLT, 1, vec![0x40]).with_palette(vec![[0; 3]; 3]);
+        assert!(matches!(
+            too_many.encode(),
+            Err(PngError::InvalidField { chunk: "PLTE", .. })
+        ));
+    }
+
+    #[test]
+    fn test_encode_bad_header() {
+        let encoder = Encoder::new(0, 1, ColorType::RGB, 8, Vec::new());
+        assert!(matches!(
+            encoder.encode(),
+            Err(PngError::InvalidField { chunk: "IHDR", .. })
+        ));
+        let encoder = Encoder::new(1, 1, ColorType::RGB, 4, vec![0, 0]);
+        assert!(matches!(
+            encoder.encode(),
+            Err(PngError::InvalidField { chunk: "IHDR", .. })
+        ));
+        let encoder = Encoder::new(2, 2, ColorType::RGB, 8, vec![0; 11]);
+        assert!(matches!(
+            encoder.encode(),
+            Err(PngError::InvalidImageData(_))
+        ));
+    }
+
+    #[test]
+    fn test_wrong_buffer_size() {
+        let mut png = read_png("./data/png/rust_crab.png").unwrap();
+        let mut pixels = png.decode_pixels().unwrap();
+        pixels.data.pop();
+        assert!(matches!(
+            png.set_pixels(&pixels, InterlaceMethod::None, FilterStrategy::MinSumAbs),
+            Err(PngError::InvalidImageData(_))
+        ));
+    }
+}
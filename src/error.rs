@@ -0,0 +1,150 @@
+use std::{
+    fmt::{self, Display},
+    io,
+    string::FromUtf8Error,
+};
+
+use crate::chunk_type::ChunkType;
+
+/*
+ * Every way reading, writing or editing a PNG can fail
+ */
+
+#[derive(Debug)]
+pub enum PngError {
+    /// The underlying reader or writer failed
+    Io(io::Error),
+    /// The first 8 bytes are not `Png::STANDARD_HEADER`
+    BadSignature,
+    /// The input ends partway through the chunk starting at `offset`
+    TruncatedChunk { offset: usize },
+    /// The chunk at `offset` declares a length above the reader's limit
+    ChunkTooLarge {
+        offset: usize,
+        length: u32,
+        max: u32,
+    },
+    /// Chunk data of this many bytes is too long for a chunk's 32 bit length field
+    DataTooLarge(usize),
+    /// A chunk was given a slice whose size disagrees with its length field
+    LengthMismatch { expected: usize, actual: usize },
+    /// `expected` is the CRC computed over the chunk, `actual` the one stored with it
+    CrcMismatch {
+        chunk_type: ChunkType,
+        expected: u32,
+        actual: u32,
+    },
+    /// A chunk was written after the stream had already been closed by IEND
+    ChunkAfterIend(ChunkType),
+    /// The bytes are not a legal chunk type
+    InvalidChunkType(Vec<u8>),
+    /// A field inside a chunk's data holds an illegal value
+    InvalidField {
+        chunk: &'static str,
+        field: &'static str,
+        reason: String,
+    },
+    /// The compressed or filtered image data cannot be decoded
+    InvalidImageData(String),
+    /// The file uses a feature this crate does not handle
+    Unsupported(String),
+    /// No chunk of the named type is present
+    ChunkNotFound(String),
+    /// Chunk data was expected to be UTF-8 but is not
+    InvalidUtf8(FromUtf8Error),
+    /// A hidden payload is not laid out the way its reader expects
+    InvalidPayload(String),
+    /// Ciphertext did not verify: a wrong passphrase, or tampered data
+    AuthenticationFailed,
+}
+
+impl Display for PngError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PngError::Io(e) => write!(f, "{}", e),
+            PngError::BadSignature => write!(f, "not a PNG file (bad signature)"),
+            PngError::TruncatedChunk { offset } => {
+                write!(f, "chunk at byte {} is truncated", offset)
+            }
+            PngError::ChunkTooLarge {
+                offset,
+                length,
+                max,
+            } => write!(
+                f,
+                "chunk at byte {} is {} bytes long, above the limit of {}",
+                offset, length, max
+            ),
+            PngError::DataTooLarge(length) => write!(
+                f,
+                "{} bytes of data do not fit in one chunk (at most {})",
+                length,
+                u32::MAX
+            ),
+            PngError::LengthMismatch { expected, actual } => write!(
+                f,
+                "chunk length mismatch: expected {} bytes, got {}",
+                expected, actual
+            ),
+            PngError::CrcMismatch {
+                chunk_type,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "CRC mismatch in {} chunk: expected {:#010x}, found {:#010x}",
+                chunk_type, expected, actual
+            ),
+            PngError::ChunkAfterIend(chunk_type) => {
+                write!(f, "cannot write {} chunk after IEND", chunk_type)
+            }
+            PngError::InvalidChunkType(bytes) => {
+                write!(f, "invalid chunk type {:?}", String::from_utf8_lossy(bytes))
+            }
+            PngError::InvalidField {
+                chunk,
+                field,
+                reason,
+            } => write!(f, "invalid {} in {} chunk: {}", field, chunk, reason),
+            PngError::InvalidImageData(reason) => write!(f, "invalid image data: {}", reason),
+            PngError::Unsupported(what) => write!(f, "unsupported: {}", what),
+            PngError::ChunkNotFound(chunk_type) => write!(f, "no {} chunk found", chunk_type),
+            PngError::InvalidUtf8(e) => write!(f, "chunk data is not UTF-8: {}", e),
+            PngError::InvalidPayload(reason) => write!(f, "invalid payload: {}", reason),
+            PngError::AuthenticationFailed => {
+                write!(f, "decryption failed: wrong passphrase or corrupted data")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PngError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PngError::Io(e) => Some(e),
+            PngError::InvalidUtf8(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PngError {
+    fn from(e: io::Error) -> Self {
+        PngError::Io(e)
+    }
+}
+
+impl From<FromUtf8Error> for PngError {
+    fn from(e: FromUtf8Error) -> Self {
+        PngError::InvalidUtf8(e)
+    }
+}
+
+// Build the error for a field of `chunk` holding an illegal value
+pub(crate) fn invalid_field(chunk: &'static str, field: &'static str, reason: String) -> PngError {
+    PngError::InvalidField {
+        chunk,
+        field,
+        reason,
+    }
+}
@@ -0,0 +1,961 @@
+use std::fmt::{self, Display};
+
+use crate::{
+    chunk::Chunk,
+    chunk_spec::{ChunkSpec, Chunk_IDAT, Chunk_eXIf},
+    error::invalid_field,
+    png::Png,
+    PngError, Result,
+};
+
+/*
+ * Exif metadata, stored in eXIf as a TIFF structure: a byte order mark, then
+ * image file directories (IFDs) of tagged values. IFD0 describes the image and
+ * points to the Exif IFD (camera settings, which points to the Interop IFD) and
+ * the GPS IFD; IFD1 describes the thumbnail. Pointers are resolved on parsing
+ * and regenerated on writing, so tags can be added, changed and removed in
+ * between.
+ *
+ * Writing moves values, so offsets that are not IFD pointers go stale. A
+ * MakerNote often holds offsets into the TIFF structure in a layout only its
+ * vendor knows; so do values of type IFD and of unknown types, which are kept
+ * as stored. Strip a MakerNote rather than expect it to survive a rewrite
+ */
+
+const EXIF_IFD_POINTER: u16 = 0x8769;
+const GPS_IFD_POINTER: u16 = 0x8825;
+const INTEROP_IFD_POINTER: u16 = 0xa005;
+const THUMBNAIL_OFFSET: u16 = 0x0201;
+const THUMBNAIL_LENGTH: u16 = 0x0202;
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum ByteOrder {
+    LittleEndian,
+    BigEndian,
+}
+
+impl ByteOrder {
+    fn u16(self, b: &[u8]) -> u16 {
+        let bytes = [b[0], b[1]];
+        match self {
+            ByteOrder::LittleEndian => u16::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32(self, b: &[u8]) -> u32 {
+        let bytes = [b[0], b[1], b[2], b[3]];
+        match self {
+            ByteOrder::LittleEndian => u32::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u32::from_be_bytes(bytes),
+        }
+    }
+
+    fn u64(self, b: &[u8]) -> u64 {
+        let (hi, lo) = match self {
+            ByteOrder::LittleEndian => (self.u32(&b[4..]), self.u32(b)),
+            ByteOrder::BigEndian => (self.u32(b), self.u32(&b[4..])),
+        };
+        (hi as u64) << 32 | lo as u64
+    }
+
+    fn put_u16(self, out: &mut Vec<u8>, v: u16) {
+        match self {
+            ByteOrder::LittleEndian => out.extend_from_slice(&v.to_le_bytes()),
+            ByteOrder::BigEndian => out.extend_from_slice(&v.to_be_bytes()),
+        }
+    }
+
+    fn put_u32(self, out: &mut Vec<u8>, v: u32) {
+        match self {
+            ByteOrder::LittleEndian => out.extend_from_slice(&v.to_le_bytes()),
+            ByteOrder::BigEndian => out.extend_from_slice(&v.to_be_bytes()),
+        }
+    }
+
+    fn put_u64(self, out: &mut Vec<u8>, v: u64) {
+        match self {
+            ByteOrder::LittleEndian => out.extend_from_slice(&v.to_le_bytes()),
+            ByteOrder::BigEndian => out.extend_from_slice(&v.to_be_bytes()),
+        }
+    }
+}
+
+// The value of a tag, by TIFF field type
+#[derive(PartialEq, Debug, Clone)]
+pub enum Value {
+    Byte(Vec<u8>),
+    // Stored NUL terminated
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<(u32, u32)>),
+    SByte(Vec<i8>),
+    Undefined(Vec<u8>),
+    SShort(Vec<i16>),
+    SLong(Vec<i32>),
+    SRational(Vec<(i32, i32)>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+    // Offsets of IFDs outside the standard set, e.g. in a MakerNote
+    IfdOffset(Vec<u32>),
+    // A field type this crate cannot size: the count and the 4 byte value or
+    // offset are kept exactly as stored
+    Unknown {
+        field_type: u16,
+        count: u32,
+        raw: [u8; 4],
+    },
+}
+
+// Bytes per component of a TIFF field type; None for types we cannot size
+fn component_size(field_type: u16) -> Option<usize> {
+    match field_type {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 | 13 => Some(4),
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+impl Value {
+    pub fn field_type(&self) -> u16 {
+        match self {
+            Value::Byte(_) => 1,
+            Value::Ascii(_) => 2,
+            Value::Short(_) => 3,
+            Value::Long(_) => 4,
+            Value::Rational(_) => 5,
+            Value::SByte(_) => 6,
+            Value::Undefined(_) => 7,
+            Value::SShort(_) => 8,
+            Value::SLong(_) => 9,
+            Value::SRational(_) => 10,
+            Value::Float(_) => 11,
+            Value::Double(_) => 12,
+            Value::IfdOffset(_) => 13,
+            Value::Unknown { field_type, .. } => *field_type,
+        }
+    }
+
+    // Number of components, counting an ASCII string's terminating NUL
+    pub fn count(&self) -> usize {
+        match self {
+            Value::Byte(v) | Value::Undefined(v) => v.len(),
+            Value::Ascii(s) => s.len() + 1,
+            Value::Short(v) => v.len(),
+            Value::Long(v) => v.len(),
+            Value::Rational(v) => v.len(),
+            Value::SByte(v) => v.len(),
+            Value::SShort(v) => v.len(),
+            Value::SLong(v) => v.len(),
+            Value::SRational(v) => v.len(),
+            Value::Float(v) => v.len(),
+            Value::Double(v) => v.len(),
+            Value::IfdOffset(v) => v.len(),
+            Value::Unknown { count, .. } => *count as usize,
+        }
+    }
+
+    // The first component as an unsigned integer, for counts and offsets
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            Value::Byte(v) => v.first().map(|&x| x as u32),
+            Value::Short(v) => v.first().map(|&x| x as u32),
+            Value::Long(v) | Value::IfdOffset(v) => v.first().copied(),
+            _ => None,
+        }
+    }
+
+    fn decode(field_type: u16, bytes: &[u8], order: ByteOrder) -> Value {
+        let each = |size: usize| bytes.chunks(size);
+        match field_type {
+            1 => Value::Byte(bytes.to_vec()),
+            2 => {
+                let text = bytes.split(|&b| b == 0).next().unwrap_or(&[]);
+                Value::Ascii(String::from_utf8_lossy(text).into_owned())
+            }
+            3 => Value::Short(each(2).map(|b| order.u16(b)).collect()),
+            4 => Value::Long(each(4).map(|b| order.u32(b)).collect()),
+            5 => Value::Rational(
+                each(8)
+                    .map(|b| (order.u32(b), order.u32(&b[4..])))
+                    .collect(),
+            ),
+            6 => Value::SByte(bytes.iter().map(|&b| b as i8).collect()),
+            8 => Value::SShort(each(2).map(|b| order.u16(b) as i16).collect()),
+            9 => Value::SLong(each(4).map(|b| order.u32(b) as i32).collect()),
+            10 => Value::SRational(
+                each(8)
+                    .map(|b| (order.u32(b) as i32, order.u32(&b[4..]) as i32))
+                    .collect(),
+            ),
+            11 => Value::Float(each(4).map(|b| f32::from_bits(order.u32(b))).collect()),
+            12 => Value::Double(each(8).map(|b| f64::from_bits(order.u64(b))).collect()),
+            13 => Value::IfdOffset(each(4).map(|b| order.u32(b)).collect()),
+            _ => Value::Undefined(bytes.to_vec()),
+        }
+    }
+
+    fn encode(&self, order: ByteOrder) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        match self {
+            Value::Byte(v) | Value::Undefined(v) => out.extend_from_slice(v),
+            Value::Ascii(s) => {
+                out.extend_from_slice(s.as_bytes());
+                out.push(0);
+            }
+            Value::Short(v) => v.iter().for_each(|&x| order.put_u16(&mut out, x)),
+            Value::Long(v) | Value::IfdOffset(v) => {
+                v.iter().for_each(|&x| order.put_u32(&mut out, x))
+            }
+            Value::Rational(v) => v.iter().for_each(|&(n, d)| {
+                order.put_u32(&mut out, n);
+                order.put_u32(&mut out, d);
+            }),
+            Value::SByte(v) => out.extend(v.iter().map(|&x| x as u8)),
+            Value::SShort(v) => v.iter().for_each(|&x| order.put_u16(&mut out, x as u16)),
+            Value::SLong(v) => v.iter().for_each(|&x| order.put_u32(&mut out, x as u32)),
+            Value::SRational(v) => v.iter().for_each(|&(n, d)| {
+                order.put_u32(&mut out, n as u32);
+                order.put_u32(&mut out, d as u32);
+            }),
+            Value::Float(v) => v.iter().for_each(|&x| order.put_u32(&mut out, x.to_bits())),
+            Value::Double(v) => v.iter().for_each(|&x| order.put_u64(&mut out, x.to_bits())),
+            Value::Unknown { raw, .. } => out.extend_from_slice(raw),
+        }
+        return out;
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list<T: Display>(f: &mut fmt::Formatter<'_>, v: &[T]) -> fmt::Result {
+            let parts: Vec<String> = v.iter().map(|x| x.to_string()).collect();
+            write!(f, "{}", parts.join(", "))
+        }
+        let ratios = |v: Vec<String>| v.join(", ");
+        match self {
+            Value::Ascii(s) => write!(f, "{:?}", s),
+            Value::Byte(v) | Value::Undefined(v) if v.len() > 16 => write!(f, "{} bytes", v.len()),
+            Value::Byte(v) | Value::Undefined(v) => list(f, v),
+            Value::Short(v) => list(f, v),
+            Value::Long(v) | Value::IfdOffset(v) => list(f, v),
+            Value::SByte(v) => list(f, v),
+            Value::SShort(v) => list(f, v),
+            Value::SLong(v) => list(f, v),
+            Value::Float(v) => list(f, v),
+            Value::Double(v) => list(f, v),
+            Value::Rational(v) => write!(
+                f,
+                "{}",
+                ratios(v.iter().map(|(n, d)| format!("{}/{}", n, d)).collect())
+            ),
+            Value::SRational(v) => write!(
+                f,
+                "{}",
+                ratios(v.iter().map(|(n, d)| format!("{}/{}", n, d)).collect())
+            ),
+            Value::Unknown {
+                field_type, count, ..
+            } => write!(f, "{} values of unknown type {}", count, field_type),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Tag {
+    pub id: u16,
+    pub value: Value,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Ifd {
+    // IFD0, describing the main image
+    Primary,
+    Exif,
+    Gps,
+    Interop,
+    // IFD1, describing the thumbnail
+    Thumbnail,
+}
+
+impl Ifd {
+    pub const ALL: [Ifd; 5] = [
+        Ifd::Primary,
+        Ifd::Exif,
+        Ifd::Gps,
+        Ifd::Interop,
+        Ifd::Thumbnail,
+    ];
+}
+
+// Names of the tags most worth knowing about, in particular those that
+// identify a person, a camera or a place
+const TAG_NAMES: [(Ifd, u16, &str); 43] = [
+    (Ifd::Primary, 0x0103, "Compression"),
+    (Ifd::Primary, 0x010e, "ImageDescription"),
+    (Ifd::Primary, 0x010f, "Make"),
+    (Ifd::Primary, 0x0110, "Model"),
+    (Ifd::Primary, 0x0112, "Orientation"),
+    (Ifd::Primary, 0x011a, "XResolution"),
+    (Ifd::Primary, 0x011b, "YResolution"),
+    (Ifd::Primary, 0x0128, "ResolutionUnit"),
+    (Ifd::Primary, 0x0131, "Software"),
+    (Ifd::Primary, 0x0132, "DateTime"),
+    (Ifd::Primary, 0x013b, "Artist"),
+    (Ifd::Primary, 0x8298, "Copyright"),
+    (Ifd::Exif, 0x829a, "ExposureTime"),
+    (Ifd::Exif, 0x829d, "FNumber"),
+    (Ifd::Exif, 0x8827, "ISOSpeedRatings"),
+    (Ifd::Exif, 0x9000, "ExifVersion"),
+    (Ifd::Exif, 0x9003, "DateTimeOriginal"),
+    (Ifd::Exif, 0x9004, "DateTimeDigitized"),
+    (Ifd::Exif, 0x9010, "OffsetTime"),
+    (Ifd::Exif, 0x920a, "FocalLength"),
+    (Ifd::Exif, 0x927c, "MakerNote"),
+    (Ifd::Exif, 0x9286, "UserComment"),
+    (Ifd::Exif, 0xa002, "PixelXDimension"),
+    (Ifd::Exif, 0xa003, "PixelYDimension"),
+    (Ifd::Exif, 0xa420, "ImageUniqueID"),
+    (Ifd::Exif, 0xa430, "CameraOwnerName"),
+    (Ifd::Exif, 0xa431, "BodySerialNumber"),
+    (Ifd::Exif, 0xa433, "LensMake"),
+    (Ifd::Exif, 0xa434, "LensModel"),
+    (Ifd::Exif, 0xa435, "LensSerialNumber"),
+    (Ifd::Gps, 0x0000, "GPSVersionID"),
+    (Ifd::Gps, 0x0001, "GPSLatitudeRef"),
+    (Ifd::Gps, 0x0002, "GPSLatitude"),
+    (Ifd::Gps, 0x0003, "GPSLongitudeRef"),
+    (Ifd::Gps, 0x0004, "GPSLongitude"),
+    (Ifd::Gps, 0x0005, "GPSAltitudeRef"),
+    (Ifd::Gps, 0x0006, "GPSAltitude"),
+    (Ifd::Gps, 0x0007, "GPSTimeStamp"),
+    (Ifd::Gps, 0x0010, "GPSImgDirectionRef"),
+    (Ifd::Gps, 0x0011, "GPSImgDirection"),
+    (Ifd::Gps, 0x0012, "GPSMapDatum"),
+    (Ifd::Gps, 0x001d, "GPSDateStamp"),
+    (Ifd::Interop, 0x0001, "InteroperabilityIndex"),
+];
+
+// Name of tag `id` in `ifd`; the thumbnail IFD uses the IFD0 names
+pub fn tag_name(ifd: Ifd, id: u16) -> Option<&'static str> {
+    let ifd = if ifd == Ifd::Thumbnail {
+        Ifd::Primary
+    } else {
+        ifd
+    };
+    return TAG_NAMES
+        .iter()
+        .find(|&&(i, t, _)| i == ifd && t == id)
+        .map(|&(_, _, name)| name);
+}
+
+pub fn tag_by_name(name: &str) -> Option<(Ifd, u16)> {
+    return TAG_NAMES
+        .iter()
+        .find(|&&(_, _, n)| n == name)
+        .map(|&(ifd, id, _)| (ifd, id));
+}
+
+fn bad(field: &'static str, reason: String) -> PngError {
+    invalid_field("eXIf", field, reason)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    order: ByteOrder,
+    // IFD offsets already read, so that a loop of pointers ends
+    visited: Vec<u32>,
+}
+
+impl Reader<'_> {
+    fn slice(&self, at: usize, len: usize) -> Result<&[u8]> {
+        return at
+            .checked_add(len)
+            .and_then(|end| self.data.get(at..end))
+            .ok_or_else(|| {
+                bad(
+                    "offset",
+                    format!(
+                        "{} bytes at {} run past the {} byte block",
+                        len,
+                        at,
+                        self.data.len()
+                    ),
+                )
+            });
+    }
+
+    // The tags of the IFD at `offset`, and the offset of the next IFD (0 if
+    // none). Entries of unknown field types cannot be sized, so their value
+    // field is kept as it stands
+    fn read_ifd(&mut self, offset: u32) -> Result<(Vec<Tag>, u32)> {
+        if self.visited.contains(&offset) {
+            return Err(bad(
+                "offset",
+                format!("IFD at {} is referenced twice", offset),
+            ));
+        }
+        self.visited.push(offset);
+        let at = offset as usize;
+        let count = self.order.u16(self.slice(at, 2)?) as usize;
+        let entries = self.slice(at + 2, count * 12 + 4)?;
+        let mut tags: Vec<Tag> = Vec::with_capacity(count);
+        for entry in entries.chunks_exact(12) {
+            let field_type = self.order.u16(&entry[2..]);
+            let size = match component_size(field_type) {
+                Some(size) => size,
+                None => {
+                    tags.push(Tag {
+                        id: self.order.u16(entry),
+                        value: Value::Unknown {
+                            field_type,
+                            count: self.order.u32(&entry[4..]),
+                            raw: [entry[8], entry[9], entry[10], entry[11]],
+                        },
+                    });
+                    continue;
+                }
+            };
+            let len = (self.order.u32(&entry[4..]) as usize)
+                .checked_mul(size)
+                .ok_or_else(|| bad("count", "value size overflows".to_string()))?;
+            let bytes = if len <= 4 {
+                &entry[8..8 + len]
+            } else {
+                self.slice(self.order.u32(&entry[8..]) as usize, len)?
+            };
+            tags.push(Tag {
+                id: self.order.u16(entry),
+                value: Value::decode(field_type, bytes, self.order),
+            });
+        }
+        let next = self.order.u32(&entries[count * 12..]);
+        return Ok((tags, next));
+    }
+}
+
+// Remove tag `id` from `tags`, returning its value as an offset or length
+fn take_u32(tags: &mut Vec<Tag>, id: u16) -> Result<Option<u32>> {
+    let at = match tags.iter().position(|t| t.id == id) {
+        Some(at) => at,
+        None => return Ok(None),
+    };
+    let tag = tags.remove(at);
+    return match tag.value.as_u32() {
+        Some(v) if tag.value.count() == 1 => Ok(Some(v)),
+        _ => Err(bad(
+            "offset",
+            format!("tag {:#06x} does not hold a single offset", id),
+        )),
+    };
+}
+
+// Where `write_ifd` left fields to be filled in once later blocks are placed
+struct Written {
+    start: u32,
+    pointers: Vec<(u16, usize)>,
+    next: usize,
+}
+
+impl Written {
+    fn pointer(&self, id: u16) -> usize {
+        return self.pointers.iter().find(|&&(t, _)| t == id).unwrap().1;
+    }
+}
+
+fn patch(out: &mut [u8], order: ByteOrder, at: usize, value: usize) {
+    let mut bytes: Vec<u8> = Vec::with_capacity(4);
+    order.put_u32(&mut bytes, value as u32);
+    out[at..at + 4].copy_from_slice(&bytes);
+}
+
+// Append an IFD of `tags`, with zeroed LONG entries for each of `pointers`,
+// followed by the values too long to fit in their entries
+fn write_ifd(out: &mut Vec<u8>, order: ByteOrder, tags: &[Tag], pointers: &[u16]) -> Written {
+    // Every offset must fall on a word boundary
+    if out.len() % 2 == 1 {
+        out.push(0);
+    }
+    let mut entries: Vec<(u16, u16, usize, Vec<u8>)> = tags
+        .iter()
+        .map(|t| {
+            (
+                t.id,
+                t.value.field_type(),
+                t.value.count(),
+                t.value.encode(order),
+            )
+        })
+        .collect();
+    entries.extend(pointers.iter().map(|&id| (id, 4, 1, vec![0; 4])));
+    entries.sort_by_key(|e| e.0);
+
+    let start = out.len();
+    let data_start = start + 2 + entries.len() * 12 + 4;
+    let mut data: Vec<u8> = Vec::new();
+    let mut written = Written {
+        start: start as u32,
+        pointers: Vec::new(),
+        next: 0,
+    };
+    order.put_u16(out, entries.len() as u16);
+    for (id, field_type, count, bytes) in entries {
+        order.put_u16(out, id);
+        order.put_u16(out, field_type);
+        order.put_u32(out, count as u32);
+        if pointers.contains(&id) {
+            written.pointers.push((id, out.len()));
+        }
+        if bytes.len() <= 4 {
+            out.extend_from_slice(&bytes);
+            out.resize(out.len() + 4 - bytes.len(), 0);
+        } else {
+            if data.len() % 2 == 1 {
+                data.push(0);
+            }
+            order.put_u32(out, (data_start + data.len()) as u32);
+            data.extend_from_slice(&bytes);
+        }
+    }
+    written.next = out.len();
+    order.put_u32(out, 0);
+    out.extend_from_slice(&data);
+    return written;
+}
+
+// The TIFF structure of an eXIf chunk
+#[derive(PartialEq, Debug, Clone)]
+pub struct Exif {
+    pub byte_order: ByteOrder,
+    primary: Vec<Tag>,
+    exif: Vec<Tag>,
+    gps: Vec<Tag>,
+    interop: Vec<Tag>,
+    thumbnail_tags: Vec<Tag>,
+    // JPEG thumbnail bytes, located by IFD1
+    thumbnail: Option<Vec<u8>>,
+}
+
+impl Exif {
+    pub fn new(byte_order: ByteOrder) -> Exif {
+        return Exif {
+            byte_order,
+            primary: Vec::new(),
+            exif: Vec::new(),
+            gps: Vec::new(),
+            interop: Vec::new(),
+            thumbnail_tags: Vec::new(),
+            thumbnail: None,
+        };
+    }
+
+    // Parse a TIFF structure. The "Exif\0\0" prefix of the JPEG APP1 segment
+    // does not belong in eXIf, but is tolerated
+    pub fn parse(data: &[u8]) -> Result<Exif> {
+        let data = data.strip_prefix(b"Exif\0\0").unwrap_or(data);
+        if data.len() < 8 {
+            return Err(bad(
+                "header",
+                format!("{} bytes is too short for a TIFF header", data.len()),
+            ));
+        }
+        let byte_order = match &data[..2] {
+            b"II" => ByteOrder::LittleEndian,
+            b"MM" => ByteOrder::BigEndian,
+            other => {
+                return Err(bad(
+                    "byte_order",
+                    format!("{:?} is neither II nor MM", other),
+                ))
+            }
+        };
+        let magic = byte_order.u16(&data[2..]);
+        if magic != 42 {
+            return Err(bad("magic", format!("expected 42, found {}", magic)));
+        }
+        let mut reader = Reader {
+            data,
+            order: byte_order,
+            visited: Vec::new(),
+        };
+        let mut exif = Exif::new(byte_order);
+        let (mut primary, next) = reader.read_ifd(byte_order.u32(&data[4..]))?;
+        if let Some(offset) = take_u32(&mut primary, EXIF_IFD_POINTER)? {
+            exif.exif = reader.read_ifd(offset)?.0;
+            if let Some(offset) = take_u32(&mut exif.exif, INTEROP_IFD_POINTER)? {
+                exif.interop = reader.read_ifd(offset)?.0;
+            }
+        }
+        if let Some(offset) = take_u32(&mut primary, GPS_IFD_POINTER)? {
+            exif.gps = reader.read_ifd(offset)?.0;
+        }
+        exif.primary = primary;
+        if next != 0 {
+            let mut tags = reader.read_ifd(next)?.0;
+            let offset = take_u32(&mut tags, THUMBNAIL_OFFSET)?;
+            let length = take_u32(&mut tags, THUMBNAIL_LENGTH)?;
+            if let (Some(offset), Some(length)) = (offset, length) {
+                exif.thumbnail = Some(reader.slice(offset as usize, length as usize)?.to_vec());
+            }
+            exif.thumbnail_tags = tags;
+        }
+        return Ok(exif);
+    }
+
+    // Serialise as TIFF: header, IFD0, the Exif, Interop and GPS IFDs, then
+    // IFD1 and the thumbnail. Empty IFDs are left out
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let order = self.byte_order;
+        let mut out: Vec<u8> = match order {
+            ByteOrder::LittleEndian => b"II".to_vec(),
+            ByteOrder::BigEndian => b"MM".to_vec(),
+        };
+        order.put_u16(&mut out, 42);
+        order.put_u32(&mut out, 8);
+
+        let has_exif = !self.exif.is_empty() || !self.interop.is_empty();
+        let mut pointers: Vec<u16> = Vec::new();
+        if has_exif {
+            pointers.push(EXIF_IFD_POINTER);
+        }
+        if !self.gps.is_empty() {
+            pointers.push(GPS_IFD_POINTER);
+        }
+        let ifd0 = write_ifd(&mut out, order, &self.primary, &pointers);
+        if has_exif {
+            let interop: &[u16] = if self.interop.is_empty() {
+                &[]
+            } else {
+                &[INTEROP_IFD_POINTER]
+            };
+            let exif = write_ifd(&mut out, order, &self.exif, interop);
+            patch(
+                &mut out,
+                order,
+                ifd0.pointer(EXIF_IFD_POINTER),
+                exif.start as usize,
+            );
+            if !self.interop.is_empty() {
+                let ifd = write_ifd(&mut out, order, &self.interop, &[]);
+                patch(
+                    &mut out,
+                    order,
+                    exif.pointer(INTEROP_IFD_POINTER),
+                    ifd.start as usize,
+                );
+            }
+        }
+        if !self.gps.is_empty() {
+            let gps = write_ifd(&mut out, order, &self.gps, &[]);
+            patch(
+                &mut out,
+                order,
+                ifd0.pointer(GPS_IFD_POINTER),
+                gps.start as usize,
+            );
+        }
+        if self.thumbnail.is_some() || !self.thumbnail_tags.is_empty() {
+            let mut tags = self.thumbnail_tags.clone();
+            let mut pointers: Vec<u16> = Vec::new();
+            if let Some(thumbnail) = &self.thumbnail {
+                tags.push(Tag {
+                    id: THUMBNAIL_LENGTH,
+                    value: Value::Long(vec![thumbnail.len() as u32]),
+                });
+                pointers.push(THUMBNAIL_OFFSET);
+            }
+            let ifd1 = write_ifd(&mut out, order, &tags, &pointers);
+            patch(&mut out, order, ifd0.next, ifd1.start as usize);
+            if let Some(thumbnail) = &self.thumbnail {
+                let at = out.len();
+                patch(&mut out, order, ifd1.pointer(THUMBNAIL_OFFSET), at);
+                out.extend_from_slice(thumbnail);
+            }
+        }
+        return out;
+    }
+
+    fn ifd(&self, ifd: Ifd) -> &Vec<Tag> {
+        match ifd {
+            Ifd::Primary => &self.primary,
+            Ifd::Exif => &self.exif,
+            Ifd::Gps => &self.gps,
+            Ifd::Interop => &self.interop,
+            Ifd::Thumbnail => &self.thumbnail_tags,
+        }
+    }
+
+    fn ifd_mut(&mut self, ifd: Ifd) -> &mut Vec<Tag> {
+        match ifd {
+            Ifd::Primary => &mut self.primary,
+            Ifd::Exif => &mut self.exif,
+            Ifd::Gps => &mut self.gps,
+            Ifd::Interop => &mut self.interop,
+            Ifd::Thumbnail => &mut self.thumbnail_tags,
+        }
+    }
+
+    // The tags of one IFD, without the pointers linking the IFDs
+    pub fn tags(&self, ifd: Ifd) -> &[Tag] {
+        return self.ifd(ifd);
+    }
+
+    pub fn get(&self, ifd: Ifd, id: u16) -> Option<&Value> {
+        return self.ifd(ifd).iter().find(|t| t.id == id).map(|t| &t.value);
+    }
+
+    // Add tag `id`, or replace its value
+    pub fn set(&mut self, ifd: Ifd, id: u16, value: Value) {
+        let tags = self.ifd_mut(ifd);
+        match tags.iter_mut().find(|t| t.id == id) {
+            Some(tag) => tag.value = value,
+            None => {
+                tags.push(Tag { id, value });
+                tags.sort_by_key(|t| t.id);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, ifd: Ifd, id: u16) -> Option<Value> {
+        let tags = self.ifd_mut(ifd);
+        let at = tags.iter().position(|t| t.id == id)?;
+        return Some(tags.remove(at).value);
+    }
+
+    pub fn thumbnail(&self) -> Option<&[u8]> {
+        return self.thumbnail.as_deref();
+    }
+
+    // Drop the thumbnail and IFD1, which may show the image before cropping
+    pub fn remove_thumbnail(&mut self) {
+        self.thumbnail = None;
+        self.thumbnail_tags.clear();
+    }
+
+    // Latitude and longitude in degrees, negative south and west
+    pub fn gps_position(&self) -> Option<(f64, f64)> {
+        let degrees = |reference: u16, value: u16, negative: &str| -> Option<f64> {
+            let dms = match self.get(Ifd::Gps, value)? {
+                Value::Rational(v) if v.len() == 3 && v.iter().all(|&(_, d)| d != 0) => v,
+                _ => return None,
+            };
+            let part = |i: usize| dms[i].0 as f64 / dms[i].1 as f64;
+            let magnitude = part(0) + part(1) / 60.0 + part(2) / 3600.0;
+            return match self.get(Ifd::Gps, reference)? {
+                Value::Ascii(r) if r == negative => Some(-magnitude),
+                Value::Ascii(_) => Some(magnitude),
+                _ => None,
+            };
+        };
+        return Some((degrees(0x0001, 0x0002, "S")?, degrees(0x0003, 0x0004, "W")?));
+    }
+}
+
+impl Png {
+    // Store `exif` in the eXIf chunk, replacing the one there is; a new chunk
+    // goes just before the image data
+    pub fn set_exif(&mut self, exif: &Exif) {
+        let chunk = Chunk::new(Chunk_eXIf::chunk_type(), exif.to_bytes());
+        let chunks = self.chunks_mut();
+        let existing = chunks
+            .iter()
+            .position(|c| c.chunk_type().bytes() == Chunk_eXIf::HEADER);
+        let first_idat = chunks
+            .iter()
+            .position(|c| c.chunk_type().bytes() == Chunk_IDAT::HEADER);
+        match (existing, first_idat) {
+            (Some(at), _) => chunks[at] = chunk,
+            (None, Some(at)) => chunks.insert(at, chunk),
+            (None, None) => self.append_chunk(chunk),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png_util::read_png;
+    use std::convert::TryFrom;
+
+    fn fixture() -> Png {
+        read_png("./data/png/exif.png").unwrap()
+    }
+
+    fn ascii(s: &str) -> Option<Value> {
+        Some(Value::Ascii(s.to_string()))
+    }
+
+    #[test]
+    fn test_parse_file() {
+        let png = fixture();
+        assert!(png.validate().is_empty());
+        let exif = png.metadata().exif().unwrap().unwrap();
+        assert_eq!(exif.byte_order, ByteOrder::LittleEndian);
+        assert_eq!(exif.get(Ifd::Primary, 0x010f).cloned(), ascii("Canon"));
+        assert_eq!(exif.get(Ifd::Primary, 0x0112), Some(&Value::Short(vec![1])));
+        assert_eq!(exif.get(Ifd::Exif, 0xa431).cloned(), ascii("032021001234"));
+        assert_eq!(
+            exif.get(Ifd::Exif, 0x829a),
+            Some(&Value::Rational(vec![(1, 250)]))
+        );
+        // Pointers are not exposed as tags
+        assert!(exif.get(Ifd::Primary, EXIF_IFD_POINTER).is_none());
+        assert_eq!(exif.tags(Ifd::Gps).len(), 5);
+        let (lat, lon) = exif.gps_position().unwrap();
+        assert!((lat - 51.5).abs() < 1e-9);
+        assert!((lon + 0.1275).abs() < 1e-9);
+        assert_eq!(exif.tags(Ifd::Thumbnail).len(), 1);
+        assert!(exif.thumbnail().unwrap().starts_with(&[0xff, 0xd8]));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let exif = fixture().metadata().exif().unwrap().unwrap();
+        let bytes = exif.to_bytes();
+        let again = Exif::parse(&bytes).unwrap();
+        assert_eq!(again, exif);
+        assert_eq!(again.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_remove_and_rewrite_tags() {
+        let mut png = fixture();
+        let mut exif = png.metadata().exif().unwrap().unwrap();
+        for id in 1..=4 {
+            assert!(exif.remove(Ifd::Gps, id).is_some());
+        }
+        assert_eq!(exif.remove(Ifd::Gps, 2), None);
+        assert!(exif.remove(Ifd::Exif, 0xa431).is_some());
+        exif.set(Ifd::Primary, 0x0110, Value::Ascii("camera".to_string()));
+        exif.set(Ifd::Primary, 0x013b, Value::Ascii("nobody".to_string()));
+        exif.remove_thumbnail();
+        png.set_exif(&exif);
+        assert_eq!(png.chunks().len(), 4);
+        assert!(png.validate().is_empty());
+
+        let png = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        let read = png.metadata().exif().unwrap().unwrap();
+        assert_eq!(read, exif);
+        assert_eq!(read.gps_position(), None);
+        assert_eq!(read.tags(Ifd::Gps).len(), 1);
+        assert_eq!(read.get(Ifd::Primary, 0x0110).cloned(), ascii("camera"));
+        assert_eq!(read.get(Ifd::Primary, 0x010f).cloned(), ascii("Canon"));
+        // Tags stay in ascending order
+        let ids: Vec<u16> = read.tags(Ifd::Primary).iter().map(|t| t.id).collect();
+        assert_eq!(ids, vec![0x010f, 0x0110, 0x0112, 0x0131, 0x013b]);
+        assert_eq!(read.thumbnail(), None);
+    }
+
+    #[test]
+    fn test_add_exif_to_png() {
+        let mut png = read_png("./data/png/rust_crab.png").unwrap();
+        let mut exif = Exif::new(ByteOrder::BigEndian);
+        exif.set(Ifd::Primary, 0x0131, Value::Ascii("pngme".to_string()));
+        png.set_exif(&exif);
+        assert!(png.validate().is_empty());
+        assert_eq!(png.metadata().exif().unwrap(), Some(exif));
+    }
+
+    #[test]
+    fn test_every_field_type_big_endian() {
+        let mut exif = Exif::new(ByteOrder::BigEndian);
+        let values = vec![
+            Value::Byte(vec![1, 2, 3, 4, 5]),
+            Value::Ascii("short".to_string()),
+            Value::Short(vec![1, 65535]),
+            Value::Long(vec![70000]),
+            Value::Rational(vec![(1, 3), (2, 3)]),
+            Value::SByte(vec![-1]),
+            Value::Undefined(b"0231".to_vec()),
+            Value::SShort(vec![-2, 2, -3]),
+            Value::SLong(vec![-70000]),
+            Value::SRational(vec![(-1, 3)]),
+            Value::Float(vec![1.5]),
+            Value::Double(vec![-0.25, 1e100]),
+        ];
+        for (i, value) in values.into_iter().enumerate() {
+            exif.set(Ifd::Exif, 0x9000 + i as u16, value);
+        }
+        exif.set(Ifd::Interop, 0x0001, Value::Ascii("R98".to_string()));
+        let bytes = exif.to_bytes();
+        assert_eq!(&bytes[..4], b"MM\0*");
+        assert_eq!(Exif::parse(&bytes).unwrap(), exif);
+    }
+
+    #[test]
+    fn test_parse_by_hand() {
+        // Big endian IFD0 with one entry, Orientation = 6, stored inline
+        let mut data = b"MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+        data.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0]);
+        data.extend_from_slice(&[0; 4]);
+        let exif = Exif::parse(&data).unwrap();
+        assert_eq!(exif.get(Ifd::Primary, 0x0112), Some(&Value::Short(vec![6])));
+        assert_eq!(exif.to_bytes(), data);
+
+        // The JPEG "Exif\0\0" prefix is tolerated
+        let mut prefixed = b"Exif\0\0".to_vec();
+        prefixed.extend_from_slice(&data);
+        assert_eq!(Exif::parse(&prefixed).unwrap(), exif);
+
+        // An entry of unknown type is kept as stored, and so written back
+        data[13] = 99;
+        let exif = Exif::parse(&data).unwrap();
+        assert_eq!(
+            exif.get(Ifd::Primary, 0x0112),
+            Some(&Value::Unknown {
+                field_type: 99,
+                count: 1,
+                raw: [0, 6, 0, 0]
+            })
+        );
+        assert_eq!(exif.to_bytes(), data);
+
+        // So is the type of an IFD offset
+        data[13] = 13;
+        data[19] = 0;
+        data[21] = 40;
+        let exif = Exif::parse(&data).unwrap();
+        assert_eq!(
+            exif.get(Ifd::Primary, 0x0112),
+            Some(&Value::IfdOffset(vec![40]))
+        );
+        assert_eq!(exif.to_bytes(), data);
+    }
+
+    #[test]
+    fn test_malformed_exif() {
+        let field = |data: &[u8]| match Exif::parse(data) {
+            Err(PngError::InvalidField { field, .. }) => field,
+            other => panic!("expected an error, got {:?}", other),
+        };
+        assert_eq!(field(b"II*\0"), "header");
+        assert_eq!(field(b"XX*\0\x08\0\0\0"), "byte_order");
+        assert_eq!(field(b"II+\0\x08\0\0\0"), "magic");
+        assert_eq!(field(b"II*\0\x40\0\0\0"), "offset");
+
+        // IFD0 whose next IFD is itself
+        let mut data = b"II*\0\x08\0\0\0\0\0".to_vec();
+        data.extend_from_slice(&8u32.to_le_bytes());
+        assert_eq!(field(&data), "offset");
+
+        // A value whose offset runs past the end
+        let mut data = b"II*\0\x08\0\0\0\x01\0".to_vec();
+        data.extend_from_slice(&[0x0f, 0x01, 2, 0, 20, 0, 0, 0, 0xff, 0, 0, 0]);
+        data.extend_from_slice(&[0; 4]);
+        assert_eq!(field(&data), "offset");
+    }
+
+    #[test]
+    fn test_tag_names() {
+        assert_eq!(tag_name(Ifd::Gps, 2), Some("GPSLatitude"));
+        assert_eq!(tag_name(Ifd::Primary, 2), None);
+        assert_eq!(tag_name(Ifd::Thumbnail, 0x0103), Some("Compression"));
+        assert_eq!(tag_by_name("BodySerialNumber"), Some((Ifd::Exif, 0xa431)));
+        assert_eq!(tag_by_name("Nonsense"), None);
+        assert_eq!(Value::Rational(vec![(1, 250)]).to_string(), "1/250");
+        assert_eq!(Value::Undefined(vec![0; 100]).to_string(), "100 bytes");
+    }
+}
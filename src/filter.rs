@@ -0,0 +1,149 @@
+use std::convert::TryFrom;
+
+/*
+ * The five scanline filters of PNG filter method 0
+ */
+
+#[repr(u8)]
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum FilterType {
+    None = 0,
+    Sub = 1,
+    Up = 2,
+    Average = 3,
+    Paeth = 4,
+}
+
+impl TryFrom<u8> for FilterType {
+    type Error = u8;
+    fn try_from(value: u8) -> std::result::Result<Self, u8> {
+        match value {
+            0 => Ok(FilterType::None),
+            1 => Ok(FilterType::Sub),
+            2 => Ok(FilterType::Up),
+            3 => Ok(FilterType::Average),
+            4 => Ok(FilterType::Paeth),
+            other => Err(other),
+        }
+    }
+}
+
+pub fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// Undo `filter` on `cur` in place. `prev` is the already reconstructed previous
+// scanline (all zeros for the first), `bpp` the bytes per complete pixel, rounded up to 1
+pub fn unfilter_scanline(filter: FilterType, bpp: usize, prev: &[u8], cur: &mut [u8]) {
+    match filter {
+        FilterType::None => {}
+        FilterType::Sub => {
+            for i in bpp..cur.len() {
+                cur[i] = cur[i].wrapping_add(cur[i - bpp]);
+            }
+        }
+        FilterType::Up => {
+            for i in 0..cur.len() {
+                cur[i] = cur[i].wrapping_add(prev[i]);
+            }
+        }
+        FilterType::Average => {
+            for i in 0..cur.len() {
+                let a = if i >= bpp { cur[i - bpp] } else { 0 };
+                let avg = ((a as u16 + prev[i] as u16) / 2) as u8;
+                cur[i] = cur[i].wrapping_add(avg);
+            }
+        }
+        FilterType::Paeth => {
+            for i in 0..cur.len() {
+                let (a, c) = if i >= bpp {
+                    (cur[i - bpp], prev[i - bpp])
+                } else {
+                    (0, 0)
+                };
+                cur[i] = cur[i].wrapping_add(paeth_predictor(a, prev[i], c));
+            }
+        }
+    }
+}
+
+// Apply `filter` to the raw scanline `cur`, writing the filtered bytes to `out`.
+// `prev` is the raw previous scanline (all zeros for the first)
+pub fn filter_scanline(filter: FilterType, bpp: usize, prev: &[u8], cur: &[u8], out: &mut [u8]) {
+    for i in 0..cur.len() {
+        let a = if i >= bpp { cur[i - bpp] } else { 0 };
+        let b = prev[i];
+        let c = if i >= bpp { prev[i - bpp] } else { 0 };
+        let predicted = match filter {
+            FilterType::None => 0,
+            FilterType::Sub => a,
+            FilterType::Up => b,
+            FilterType::Average => ((a as u16 + b as u16) / 2) as u8,
+            FilterType::Paeth => paeth_predictor(a, b, c),
+        };
+        out[i] = cur[i].wrapping_sub(predicted);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paeth_predictor() {
+        assert_eq!(paeth_predictor(10, 20, 10), 20);
+        assert_eq!(paeth_predictor(20, 10, 10), 20);
+        assert_eq!(paeth_predictor(10, 10, 20), 10);
+        assert_eq!(paeth_predictor(0, 0, 0), 0);
+    }
+
+    #[test]
+    fn test_unfilter_scanlines() {
+        let prev = [10, 20, 30, 40];
+
+        let mut cur = [1, 2, 3, 4];
+        unfilter_scanline(FilterType::Sub, 2, &prev, &mut cur);
+        assert_eq!(cur, [1, 2, 4, 6]);
+
+        let mut cur = [1, 2, 3, 4];
+        unfilter_scanline(FilterType::Up, 2, &prev, &mut cur);
+        assert_eq!(cur, [11, 22, 33, 44]);
+
+        let mut cur = [1, 2, 3, 4];
+        unfilter_scanline(FilterType::Average, 2, &prev, &mut cur);
+        assert_eq!(cur, [6, 12, 21, 30]);
+
+        let mut cur = [1, 2, 3, 4];
+        unfilter_scanline(FilterType::Paeth, 2, &prev, &mut cur);
+        assert_eq!(cur, [11, 22, 33, 44]);
+    }
+
+    #[test]
+    fn test_filter_round_trip() {
+        let prev = [200, 3, 17, 255, 0, 9];
+        let raw = [1, 250, 40, 40, 128, 7];
+        for v in 0..5 {
+            let filter = FilterType::try_from(v).unwrap();
+            let mut out = [0; 6];
+            filter_scanline(filter, 3, &prev, &raw, &mut out);
+            unfilter_scanline(filter, 3, &prev, &mut out);
+            assert_eq!(out, raw);
+        }
+    }
+
+    #[test]
+    fn test_filter_type_from_byte() {
+        assert_eq!(FilterType::try_from(4), Ok(FilterType::Paeth));
+        assert_eq!(FilterType::try_from(5), Err(5));
+    }
+}
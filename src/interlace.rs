@@ -0,0 +1,230 @@
+use crate::{chunk_spec::ColorType, decoder::PixelBuffer};
+
+/*
+ * Adam7 interlacing: the image is split into seven sub-images ("passes"), each
+ * sampling the pixel grid at a different offset and spacing
+ */
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Adam7Pass {
+    pub x0: u32,
+    pub y0: u32,
+    pub dx: u32,
+    pub dy: u32,
+}
+
+pub const ADAM7: [Adam7Pass; 7] = [
+    Adam7Pass {
+        x0: 0,
+        y0: 0,
+        dx: 8,
+        dy: 8,
+    },
+    Adam7Pass {
+        x0: 4,
+        y0: 0,
+        dx: 8,
+        dy: 8,
+    },
+    Adam7Pass {
+        x0: 0,
+        y0: 4,
+        dx: 4,
+        dy: 8,
+    },
+    Adam7Pass {
+        x0: 2,
+        y0: 0,
+        dx: 4,
+        dy: 4,
+    },
+    Adam7Pass {
+        x0: 0,
+        y0: 2,
+        dx: 2,
+        dy: 4,
+    },
+    Adam7Pass {
+        x0: 1,
+        y0: 0,
+        dx: 2,
+        dy: 2,
+    },
+    Adam7Pass {
+        x0: 0,
+        y0: 1,
+        dx: 1,
+        dy: 2,
+    },
+];
+
+impl Adam7Pass {
+    // Size of this pass for a `width` x `height` image; either may be 0
+    pub fn dimensions(&self, width: u32, height: u32) -> (u32, u32) {
+        let span = |len: u32, start: u32, step: u32| {
+            if len > start {
+                (len - start).div_ceil(step)
+            } else {
+                0
+            }
+        };
+        (
+            span(width, self.x0, self.dx),
+            span(height, self.y0, self.dy),
+        )
+    }
+}
+
+pub(crate) fn bits_per_pixel(color_type: ColorType, bit_depth: u8) -> usize {
+    color_type.channels() * bit_depth as usize
+}
+
+// Copy pixel `sx` of the packed row `src` into pixel `dx` of `dst`
+fn copy_pixel(src: &[u8], sx: usize, dst: &mut [u8], dx: usize, bits: usize) {
+    if bits >= 8 {
+        let n = bits / 8;
+        dst[dx * n..(dx + 1) * n].copy_from_slice(&src[sx * n..(sx + 1) * n]);
+        return;
+    }
+    let mask = (1u8 << bits) - 1;
+    let s_shift = 8 - bits - (sx * bits) % 8;
+    let d_shift = 8 - bits - (dx * bits) % 8;
+    let value = (src[sx * bits / 8] >> s_shift) & mask;
+    let d = &mut dst[dx * bits / 8];
+    *d = (*d & !(mask << d_shift)) | (value << d_shift);
+}
+
+// Split a full image into its seven passes, in transmission order
+pub fn interlace(image: &PixelBuffer) -> Vec<PixelBuffer> {
+    let bits = bits_per_pixel(image.color_type, image.bit_depth);
+    return ADAM7
+        .iter()
+        .map(|pass| {
+            let (w, h) = pass.dimensions(image.width, image.height);
+            let mut sub = PixelBuffer {
+                width: w,
+                height: h,
+                color_type: image.color_type,
+                bit_depth: image.bit_depth,
+                data: Vec::new(),
+            };
+            let stride = sub.stride();
+            sub.data = vec![0; stride * h as usize];
+            for y in 0..h {
+                let src = image.row(pass.y0 + y * pass.dy);
+                let dst = &mut sub.data[y as usize * stride..(y as usize + 1) * stride];
+                for x in 0..w {
+                    let sx = (pass.x0 + x * pass.dx) as usize;
+                    copy_pixel(src, sx, dst, x as usize, bits);
+                }
+            }
+            sub
+        })
+        .collect();
+}
+
+// Reassemble the seven passes produced by `interlace` into the full image
+pub fn deinterlace(passes: &[PixelBuffer], width: u32, height: u32) -> PixelBuffer {
+    let color_type = passes[0].color_type;
+    let bit_depth = passes[0].bit_depth;
+    let bits = bits_per_pixel(color_type, bit_depth);
+    let mut image = PixelBuffer {
+        width,
+        height,
+        color_type,
+        bit_depth,
+        data: Vec::new(),
+    };
+    let stride = image.stride();
+    image.data = vec![0; stride * height as usize];
+    for (pass, sub) in ADAM7.iter().zip(passes.iter()) {
+        for y in 0..sub.height {
+            let src = sub.row(y);
+            let row = (pass.y0 + y * pass.dy) as usize;
+            let dst = &mut image.data[row * stride..(row + 1) * stride];
+            for x in 0..sub.width {
+                let dx = (pass.x0 + x * pass.dx) as usize;
+                copy_pixel(src, x as usize, dst, dx, bits);
+            }
+        }
+    }
+    return image;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: u32, height: u32, color_type: ColorType, bit_depth: u8) -> PixelBuffer {
+        let mut image = PixelBuffer {
+            width,
+            height,
+            color_type,
+            bit_depth,
+            data: Vec::new(),
+        };
+        let len = image.stride() * height as usize;
+        image.data = (0..len).map(|i| (i * 37 % 251) as u8).collect();
+        image
+    }
+
+    #[test]
+    fn test_pass_dimensions() {
+        let dims: Vec<(u32, u32)> = ADAM7.iter().map(|p| p.dimensions(10, 7)).collect();
+        assert_eq!(
+            dims,
+            vec![(2, 1), (1, 1), (3, 1), (2, 2), (5, 2), (5, 4), (10, 3)]
+        );
+        let dims: Vec<(u32, u32)> = ADAM7.iter().map(|p| p.dimensions(1, 1)).collect();
+        assert_eq!(
+            dims,
+            vec![(1, 1), (0, 1), (1, 0), (0, 1), (1, 0), (0, 1), (1, 0)]
+        );
+    }
+
+    #[test]
+    fn test_interlace_round_trip() {
+        for &(ct, bd) in [
+            (ColorType::RGB, 8),
+            (ColorType::RGBA, 16),
+            (ColorType::GRY, 1),
+            (ColorType::GRY, 2),
+            (ColorType::PLT, 4),
+        ]
+        .iter()
+        {
+            let image = gradient(13, 11, ct, bd);
+            // Padding bits at the end of each row are not carried by the passes
+            let mut expected = image.clone();
+            let passes = interlace(&image);
+            let back = deinterlace(&passes, 13, 11);
+            let used_bits = 13 * bits_per_pixel(ct, bd);
+            if !used_bits.is_multiple_of(8) {
+                let stride = image.stride();
+                let keep = 0xffu8 << (8 - used_bits % 8);
+                for y in 0..11 {
+                    expected.data[y * stride + stride - 1] &= keep;
+                }
+            }
+            assert_eq!(back, expected);
+        }
+    }
+
+    #[test]
+    fn test_first_pass_samples_every_eighth_pixel() {
+        let image = gradient(17, 9, ColorType::GRY, 8);
+        let passes = interlace(&image);
+        assert_eq!((passes[0].width, passes[0].height), (3, 2));
+        assert_eq!(
+            passes[0].data,
+            vec![
+                image.row(0)[0],
+                image.row(0)[8],
+                image.row(0)[16],
+                image.row(8)[0],
+                image.row(8)[8],
+                image.row(8)[16]
+            ]
+        );
+    }
+}
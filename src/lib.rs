@@ -0,0 +1,27 @@
+#![allow(clippy::needless_return)]
+
+pub mod apng;
+pub mod chunk;
+pub mod chunk_spec;
+pub mod chunk_type;
+pub mod crypto;
+pub mod decoder;
+pub mod encoder;
+pub mod error;
+pub mod exif;
+pub mod filter;
+pub mod interlace;
+pub mod metadata;
+pub mod png;
+pub mod png_reader;
+pub mod png_util;
+pub mod png_writer;
+pub mod recovery;
+pub mod split;
+pub mod stego;
+pub mod text;
+pub mod validate;
+
+pub use error::PngError;
+
+pub type Result<T> = std::result::Result<T, PngError>;
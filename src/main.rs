@@ -1,16 +1,30 @@
-// mod args;
-mod chunk;
-mod chunk_spec;
-mod chunk_type;
-mod png_util;
-// mod commands;
-mod png;
+use std::process::ExitCode;
 
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
-pub fn throw_string_error(s: &'static str) -> Error {
-    return std::io::Error::new(std::io::ErrorKind::Other, "Bad Header").into();
-}
-fn main() -> Result<()> {
-    todo!()
+use clap::Parser;
+
+mod args;
+mod commands;
+
+use args::{Cli, PngMeArgs};
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let res = match &cli.command {
+        PngMeArgs::Encode(args) => commands::encode(args),
+        PngMeArgs::Decode(args) => commands::decode(args),
+        PngMeArgs::Remove(args) => commands::remove(args),
+        PngMeArgs::Print(args) => commands::print(args),
+        PngMeArgs::Repair(args) => commands::repair(args),
+        PngMeArgs::Embed(args) => commands::embed(args),
+        PngMeArgs::Extract(args) => commands::extract(args),
+        PngMeArgs::Frames(args) => commands::frames(args),
+        PngMeArgs::Exif(args) => commands::exif(args),
+    };
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("pngme: {}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,359 @@
+use crate::{
+    chunk_spec::{
+        Background, Chunk_bKGD, Chunk_cHRM, Chunk_cICP, Chunk_cLLI, Chunk_eXIf, Chunk_gAMA,
+        Chunk_hIST, Chunk_iCCP, Chunk_mDCV, Chunk_pHYs, Chunk_sBIT, Chunk_sPLT, Chunk_sRGB,
+        Chunk_tIME, RenderingIntent, SignificantBits,
+    },
+    error::invalid_field,
+    exif::Exif,
+    png::Png,
+    Result,
+};
+
+/*
+ * A read-only view of the ancillary chunks describing an image. Every accessor
+ * decodes the first chunk of its type, giving None when there is none and an
+ * error when it is malformed
+ */
+
+pub struct Metadata<'a> {
+    png: &'a Png,
+}
+
+impl Png {
+    pub fn metadata(&self) -> Metadata<'_> {
+        return Metadata { png: self };
+    }
+}
+
+impl Metadata<'_> {
+    pub fn physical_dimensions(&self) -> Result<Option<Chunk_pHYs>> {
+        return self.png.get::<Chunk_pHYs>();
+    }
+
+    // The gamma the image was encoded with, e.g. 0.45455
+    pub fn gamma(&self) -> Result<Option<f64>> {
+        let gama = self.png.get::<Chunk_gAMA>()?;
+        return Ok(gama.map(|g| g.value()));
+    }
+
+    pub fn chromaticities(&self) -> Result<Option<Chunk_cHRM>> {
+        return self.png.get::<Chunk_cHRM>();
+    }
+
+    pub fn srgb_intent(&self) -> Result<Option<RenderingIntent>> {
+        let srgb = self.png.get::<Chunk_sRGB>()?;
+        return Ok(srgb.map(|s| s.intent));
+    }
+
+    pub fn icc_profile_name(&self) -> Result<Option<String>> {
+        let iccp = self.png.get::<Chunk_iCCP>()?;
+        return Ok(iccp.map(|i| i.name().to_string()));
+    }
+
+    // The embedded ICC profile, inflated
+    pub fn icc_profile(&self) -> Result<Option<Chunk_iCCP>> {
+        return self.png.get::<Chunk_iCCP>();
+    }
+
+    pub fn coding_independent_code_points(&self) -> Result<Option<Chunk_cICP>> {
+        return self.png.get::<Chunk_cICP>();
+    }
+
+    pub fn mastering_display(&self) -> Result<Option<Chunk_mDCV>> {
+        return self.png.get::<Chunk_mDCV>();
+    }
+
+    pub fn content_light_level(&self) -> Result<Option<Chunk_cLLI>> {
+        return self.png.get::<Chunk_cLLI>();
+    }
+
+    // Exif tags, as cameras record them
+    pub fn exif(&self) -> Result<Option<Exif>> {
+        let exif = self.png.get::<Chunk_eXIf>()?;
+        return Ok(exif.map(|e| e.exif));
+    }
+
+    // Significant bits of each channel, checked against the image header
+    pub fn significant_bits(&self) -> Result<Option<SignificantBits>> {
+        let sbit = match self.png.get::<Chunk_sBIT>()? {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+        sbit.check(&self.png.ihdr()?)?;
+        return Ok(Some(sbit.bits));
+    }
+
+    // Every suggested palette, in file order
+    pub fn suggested_palettes(&self) -> Result<Vec<Chunk_sPLT>> {
+        return self.png.get_all::<Chunk_sPLT>();
+    }
+
+    pub fn modification_time(&self) -> Result<Option<Chunk_tIME>> {
+        return self.png.get::<Chunk_tIME>();
+    }
+
+    // The background colour, which must use the layout of the IHDR colour type
+    // and, for an indexed image, name an entry of the palette
+    pub fn background(&self) -> Result<Option<Background>> {
+        let bkgd = match self.png.get::<Chunk_bKGD>()? {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+        let color_type = self.png.ihdr()?.color_type;
+        if !bkgd.matches(color_type) {
+            return Err(invalid_field(
+                "bKGD",
+                "color",
+                format!("{:?} does not fit colour type {:?}", bkgd.color, color_type),
+            ));
+        }
+        if let Background::PaletteIndex(index) = bkgd.color {
+            let palette = self
+                .png
+                .palette()?
+                .ok_or_else(|| invalid_field("bKGD", "color", "no PLTE chunk".to_string()))?;
+            if index as usize >= palette.entries.len() {
+                return Err(invalid_field(
+                    "bKGD",
+                    "color",
+                    format!(
+                        "index {} is past the {} palette entries",
+                        index,
+                        palette.entries.len()
+                    ),
+                ));
+            }
+        }
+        return Ok(Some(bkgd.color));
+    }
+
+    // How often each palette entry is used, one frequency per entry
+    pub fn histogram(&self) -> Result<Option<Vec<u16>>> {
+        let hist = match self.png.get::<Chunk_hIST>()? {
+            Some(h) => h,
+            None => return Ok(None),
+        };
+        let palette = self
+            .png
+            .palette()?
+            .ok_or_else(|| invalid_field("hIST", "frequencies", "no PLTE chunk".to_string()))?;
+        hist.check(&palette)?;
+        return Ok(Some(hist.frequencies));
+    }
+
+    // Keyword and text of every tEXt, zTXt and iTXt chunk, in file order
+    pub fn text(&self) -> Result<Vec<(String, String)>> {
+        return self.png.text_entries().collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunk::Chunk,
+        chunk_spec::{ChunkSpec, ColorType, PhysUnit},
+        encoder::Encoder,
+        png_util::read_png,
+        text::TextChunk,
+        PngError,
+    };
+
+    #[test]
+    fn test_metadata_of_file() {
+        let png = read_png("./data/png/dice.png").unwrap();
+        let meta = png.metadata();
+        let phys = meta.physical_dimensions().unwrap().unwrap();
+        assert_eq!(phys.pixels_per_unit_x, 3778);
+        assert_eq!(phys.unit, PhysUnit::Metre);
+        assert_eq!(meta.gamma().unwrap(), Some(0.45455));
+        assert_eq!(
+            meta.srgb_intent().unwrap(),
+            Some(RenderingIntent::Perceptual)
+        );
+        assert_eq!(meta.chromaticities().unwrap(), None);
+        assert_eq!(meta.icc_profile_name().unwrap(), None);
+        assert_eq!(meta.modification_time().unwrap(), None);
+        assert_eq!(meta.background().unwrap(), None);
+        assert_eq!(meta.significant_bits().unwrap(), None);
+        assert_eq!(meta.suggested_palettes().unwrap(), Vec::new());
+        assert_eq!(meta.text().unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn test_metadata_of_added_chunks() {
+        let mut png = read_png("./data/png/rust_crab.png").unwrap();
+        let time = Chunk_tIME {
+            year: 2022,
+            month: 1,
+            day: 2,
+            hour: 3,
+            minute: 4,
+            second: 5,
+        };
+        png.append_chunk(time.encode());
+        png.append_chunk(
+            Chunk_iCCP::new("Display P3", vec![1, 2, 3])
+                .unwrap()
+                .encode(),
+        );
+        png.append_chunk(TextChunk::new("Title", "Crab").unwrap().encode());
+        let sbit = SignificantBits::Rgba(5, 5, 5, 1);
+        png.append_chunk(Chunk_sBIT { bits: sbit }.encode());
+        let splt = Chunk_sPLT::new("Few colours", 8, Vec::new()).unwrap();
+        png.append_chunk(splt.encode());
+        let meta = png.metadata();
+        assert_eq!(meta.significant_bits().unwrap(), Some(sbit));
+        assert_eq!(meta.suggested_palettes().unwrap(), vec![splt]);
+        assert_eq!(meta.modification_time().unwrap(), Some(time));
+        assert_eq!(
+            meta.icc_profile_name().unwrap(),
+            Some("Display P3".to_string())
+        );
+        assert_eq!(meta.icc_profile().unwrap().unwrap().profile(), [1, 2, 3]);
+        assert_eq!(
+            meta.text().unwrap(),
+            vec![("Title".to_string(), "Crab".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_background_must_fit_color_type() {
+        let mut png = read_png("./data/png/rust_crab.png").unwrap();
+        let color_type = png.ihdr().unwrap().color_type;
+        let color = if color_type.pallate_used() {
+            Background::PaletteIndex(0)
+        } else if color_type.color_used() {
+            Background::Rgb(1, 2, 3)
+        } else {
+            Background::Grey(7)
+        };
+        png.append_chunk(Chunk_bKGD { color }.encode());
+        assert_eq!(png.metadata().background().unwrap(), Some(color));
+
+        let mut png = read_png("./data/png/rust_crab.png").unwrap();
+        let wrong = if color_type.color_used() {
+            Background::Grey(7)
+        } else {
+            Background::Rgb(1, 2, 3)
+        };
+        png.append_chunk(Chunk_bKGD { color: wrong }.encode());
+        assert!(matches!(
+            png.metadata().background(),
+            Err(PngError::InvalidField {
+                chunk: "bKGD",
+                field: "color",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_background_index_must_fit_palette() {
+        let encoder =
+            Encoder::new(2, 1, ColorType::PLT, 8, vec![0, 1]).with_palette(vec![[0; 3], [255; 3]]);
+        let mut png = encoder.encode().unwrap();
+        let bkgd = |i| {
+            Chunk_bKGD {
+                color: Background::PaletteIndex(i),
+            }
+            .encode()
+        };
+        png.chunks_mut().insert(2, bkgd(1));
+        assert_eq!(
+            png.metadata().background().unwrap(),
+            Some(Background::PaletteIndex(1))
+        );
+        png.chunks_mut()[2] = bkgd(2);
+        assert!(matches!(
+            png.metadata().background(),
+            Err(PngError::InvalidField {
+                chunk: "bKGD",
+                field: "color",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_malformed_metadata() {
+        let mut png = read_png("./data/png/rust_crab.png").unwrap();
+        png.append_chunk(Chunk::new(Chunk_gAMA::chunk_type(), vec![0, 1]));
+        png.append_chunk(Chunk::new(
+            Chunk_tIME::chunk_type(),
+            vec![7, 230, 13, 1, 0, 0, 0],
+        ));
+        let meta = png.metadata();
+        assert!(meta.gamma().is_err());
+        assert!(meta.modification_time().is_err());
+        assert_eq!(meta.chromaticities().unwrap(), None);
+    }
+
+    #[test]
+    fn test_histogram_must_fit_palette() {
+        let encoder =
+            Encoder::new(2, 1, ColorType::PLT, 8, vec![0, 1]).with_palette(vec![[0; 3], [255; 3]]);
+        let mut png = encoder.encode().unwrap();
+        assert_eq!(png.metadata().histogram().unwrap(), None);
+        png.append_chunk(
+            Chunk_hIST {
+                frequencies: vec![3, 4],
+            }
+            .encode(),
+        );
+        assert_eq!(png.metadata().histogram().unwrap(), Some(vec![3, 4]));
+        png.remove_chunk("hIST").unwrap();
+        png.append_chunk(
+            Chunk_hIST {
+                frequencies: vec![3],
+            }
+            .encode(),
+        );
+        assert!(matches!(
+            png.metadata().histogram(),
+            Err(PngError::InvalidField { chunk: "hIST", .. })
+        ));
+    }
+
+    #[test]
+    fn test_hdr_metadata() {
+        let mut png = read_png("./data/png/rust_crab.png").unwrap();
+        assert_eq!(
+            png.metadata().coding_independent_code_points().unwrap(),
+            None
+        );
+        // BT.2100 PQ
+        let cicp = Chunk_cICP {
+            colour_primaries: 9,
+            transfer_function: 16,
+            matrix_coefficients: 0,
+            video_full_range: true,
+        };
+        let clli = Chunk_cLLI {
+            max_content_light_level: 10_000_000,
+            max_frame_average_light_level: 4_000_000,
+        };
+        let mdcv = Chunk_mDCV {
+            red_x: 35400,
+            red_y: 14600,
+            green_x: 8500,
+            green_y: 39850,
+            blue_x: 6550,
+            blue_y: 2300,
+            white_x: 15635,
+            white_y: 16450,
+            max_luminance: 10_000_000,
+            min_luminance: 1,
+        };
+        // These belong before PLTE and IDAT
+        for chunk in [cicp.encode(), mdcv.encode(), clli.encode()].iter() {
+            png.chunks_mut().insert(1, chunk.clone());
+        }
+        assert!(png.validate().is_empty());
+        let meta = png.metadata();
+        assert_eq!(meta.coding_independent_code_points().unwrap(), Some(cicp));
+        assert_eq!(meta.mastering_display().unwrap(), Some(mdcv));
+        assert_eq!(meta.content_light_level().unwrap(), Some(clli));
+    }
+}
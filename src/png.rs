@@ -1,8 +1,10 @@
-use std::{convert::TryFrom, convert::TryInto, fmt::Display, iter::once};
+use std::{convert::TryFrom, convert::TryInto, fmt::Display, io::Write};
 
 use crate::{
-    chunk::{self, Chunk},
-    Error, Result,
+    chunk::Chunk,
+    chunk_spec::{ChunkSpec, Chunk_IEND, Chunk_IHDR},
+    png_writer::PngWriter,
+    PngError, Result,
 };
 
 #[derive(Eq, PartialEq, Debug)]
@@ -11,28 +13,25 @@ pub struct Png {
 }
 
 impl TryFrom<&[u8]> for Png {
-    type Error = Error;
+    type Error = PngError;
 
     fn try_from(value: &[u8]) -> Result<Self> {
-        let header_slice: &[u8; 8] = &value[0..8].try_into()?;
-        if header_slice != &Png::STANDARD_HEADER {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Bad Header").into());
+        if value.len() < 8 || value[0..8] != Png::STANDARD_HEADER {
+            return Err(PngError::BadSignature);
         }
         let mut read_size: usize;
         let mut raw_read: &[u8];
         let mut chunks: Vec<Chunk> = Vec::new();
         let mut count = 8;
         while count != value.len() {
-            read_size = i32::from_be_bytes(value[count..count + 4].try_into()?) as usize;
-            raw_read = &value[count..count + read_size + 12];
-            if raw_read.len() != read_size + 12 {
-                panic!("Raw read returned too few bytes");
+            let truncated = PngError::TruncatedChunk { offset: count };
+            if value.len() - count < 12 {
+                return Err(truncated);
             }
+            read_size = u32::from_be_bytes(value[count..count + 4].try_into().unwrap()) as usize;
+            raw_read = value.get(count..count + read_size + 12).ok_or(truncated)?;
             chunks.push(Chunk::try_from(raw_read)?);
             count += read_size + 12;
-            if count > value.len() {
-                panic!("Attempting to read past the end of the file");
-            }
         }
         return Ok(Png { chunks });
     }
@@ -40,7 +39,11 @@ impl TryFrom<&[u8]> for Png {
 
 impl Display for Png {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "[ PNG (data presentation not implemented) ]")
+        writeln!(f, "PNG:: {} chunks", self.chunks.len())?;
+        for c in self.chunks.iter() {
+            write!(f, "{}", c)?;
+        }
+        Ok(())
     }
 }
 
@@ -49,8 +52,15 @@ impl Png {
     pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
         return Png { chunks };
     }
+    // Add a chunk to the end of the file, keeping a trailing IEND last
     pub fn append_chunk(&mut self, chunk: Chunk) {
-        self.chunks.push(chunk);
+        match self.chunks.last() {
+            Some(c) if c.chunk_type().bytes() == Chunk_IEND::HEADER => {
+                let at = self.chunks.len() - 1;
+                self.chunks.insert(at, chunk);
+            }
+            _ => self.chunks.push(chunk),
+        }
     }
     pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk> {
         match self
@@ -63,9 +73,7 @@ impl Png {
                 return Ok(res);
             }
             None => {
-                return Err(
-                    std::io::Error::new(std::io::ErrorKind::Other, " Header not found").into(),
-                );
+                return Err(PngError::ChunkNotFound(chunk_type.to_string()));
             }
         }
     }
@@ -75,39 +83,68 @@ impl Png {
     pub fn chunks(&self) -> &[Chunk] {
         return &self.chunks;
     }
+    pub(crate) fn chunks_mut(&mut self) -> &mut Vec<Chunk> {
+        return &mut self.chunks;
+    }
     pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
         let bs = chunk_type.as_bytes();
-        for c in self.chunks.iter() {
-            if c.chunk_type().bytes() == bs {
-                return Some(c);
-            }
-        }
-        return None;
+        return self.chunks.iter().find(|c| c.chunk_type().bytes() == bs);
+    }
+    // Decode the first chunk of type `T`, if there is one
+    pub fn get<T: ChunkSpec>(&self) -> Result<Option<T>> {
+        return self
+            .chunks
+            .iter()
+            .find(|c| c.chunk_type().bytes() == T::HEADER)
+            .map(T::decode)
+            .transpose();
+    }
+    // Decode every chunk of type `T`, in file order
+    pub fn get_all<T: ChunkSpec>(&self) -> Result<Vec<T>> {
+        return self
+            .chunks
+            .iter()
+            .filter(|c| c.chunk_type().bytes() == T::HEADER)
+            .map(T::decode)
+            .collect();
+    }
+    // The decoded and validated image header
+    pub fn ihdr(&self) -> Result<Chunk_IHDR> {
+        return self
+            .get::<Chunk_IHDR>()?
+            .ok_or_else(|| PngError::ChunkNotFound(Chunk_IHDR::chunk_type().to_string()));
     }
     pub fn as_bytes(&self) -> Vec<u8> {
         let mut hdr: Vec<u8> = Png::STANDARD_HEADER.to_vec();
-        let x: Vec<u8> = self.chunks.iter().map(|c| c.as_bytes()).flatten().collect();
+        let x: Vec<u8> = self.chunks.iter().flat_map(|c| c.as_bytes()).collect();
         hdr.extend(x.iter().cloned());
         return hdr;
     }
+    // Stream the file to `w`, adding an IEND chunk if there is none
+    pub fn write_to<W: Write>(&self, w: W) -> Result<W> {
+        let mut writer = PngWriter::new(w)?;
+        for c in self.chunks.iter() {
+            writer.write_chunk(c)?;
+        }
+        return writer.finish();
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::chunk::Chunk;
+    use crate::chunk_spec::Chunk_tIME;
     use crate::chunk_type::ChunkType;
     use crate::Result;
     use std::convert::TryFrom;
 
     fn testing_chunks() -> Vec<Chunk> {
-        let mut chunks = Vec::new();
-
-        chunks.push(chunk_from_strings("FrSt", "I am the first chunk").unwrap());
-        chunks.push(chunk_from_strings("miDl", "I am another chunk").unwrap());
-        chunks.push(chunk_from_strings("LASt", "I am the last chunk").unwrap());
-
-        chunks
+        vec![
+            chunk_from_strings("FrSt", "I am the first chunk").unwrap(),
+            chunk_from_strings("miDl", "I am another chunk").unwrap(),
+            chunk_from_strings("LASt", "I am the last chunk").unwrap(),
+        ]
     }
 
     fn testing_png() -> Png {
@@ -190,6 +227,49 @@ mod tests {
         assert!(png.is_err());
     }
 
+    #[test]
+    fn test_short_input_is_bad_signature() {
+        let png = Png::try_from(&Png::STANDARD_HEADER[..4]);
+        assert!(matches!(png, Err(PngError::BadSignature)));
+    }
+
+    #[test]
+    fn test_truncated_chunk_reports_offset() {
+        let first_len = testing_chunks()[0].as_bytes().len();
+        let mut bytes: Vec<u8> = Png::STANDARD_HEADER
+            .iter()
+            .copied()
+            .chain(testing_chunks().into_iter().flat_map(|c| c.as_bytes()))
+            .collect();
+        bytes.truncate(8 + first_len + 10);
+
+        let png = Png::try_from(bytes.as_ref());
+        match png {
+            Err(PngError::TruncatedChunk { offset }) => assert_eq!(offset, 8 + first_len),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_crc_mismatch_is_reported() {
+        let mut bytes = PNG_FILE.to_vec();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+
+        let png = Png::try_from(bytes.as_ref());
+        match png {
+            Err(PngError::CrcMismatch {
+                chunk_type,
+                expected,
+                actual,
+            }) => {
+                assert_eq!(&chunk_type.to_string(), "IEND");
+                assert_eq!(expected ^ actual, 0xff);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
     #[test]
     fn test_list_chunks() {
         let png = testing_png();
@@ -214,6 +294,22 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    #[test]
+    fn test_append_chunk_before_iend() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        let n = png.chunks().len();
+        assert_eq!(&png.chunks()[n - 2].chunk_type().to_string(), "TeSt");
+        assert_eq!(&png.chunks()[n - 1].chunk_type().to_string(), "IEND");
+    }
+
+    #[test]
+    fn test_write_to() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let out = png.write_to(Vec::new()).unwrap();
+        assert_eq!(out, PNG_FILE.to_vec());
+    }
+
     #[test]
     fn test_remove_chunk() {
         let mut png = testing_png();
@@ -223,6 +319,49 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_remove_missing_chunk() {
+        let mut png = testing_png();
+        let res = png.remove_chunk("TeSt");
+        assert!(matches!(res, Err(PngError::ChunkNotFound(t)) if t == "TeSt"));
+    }
+
+    #[test]
+    fn test_get_typed_chunks() {
+        use crate::chunk_spec::{
+            Chunk_IDAT, Chunk_gAMA, Chunk_pHYs, Chunk_sRGB, PhysUnit, RenderingIntent,
+        };
+
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let ihdr = png.get::<Chunk_IHDR>().unwrap().unwrap();
+        assert_eq!((ihdr.width, ihdr.height), (50, 50));
+
+        let srgb = png.get::<Chunk_sRGB>().unwrap().unwrap();
+        assert_eq!(srgb.intent, RenderingIntent::Perceptual);
+        let gama = png.get::<Chunk_gAMA>().unwrap().unwrap();
+        assert_eq!(gama.gamma, 45455);
+        let phys = png.get::<Chunk_pHYs>().unwrap().unwrap();
+        assert_eq!(phys.pixels_per_unit_x, 3778);
+        assert_eq!(phys.unit, PhysUnit::Metre);
+
+        assert_eq!(png.get_all::<Chunk_IDAT>().unwrap().len(), 1);
+        assert!(png.get::<Chunk_tIME>().unwrap().is_none());
+        assert!(png.get_all::<Chunk_tIME>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get_malformed_chunk() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(Chunk_IHDR::chunk_type(), vec![0; 3]));
+        assert!(matches!(
+            png.get::<Chunk_IHDR>(),
+            Err(PngError::InvalidField {
+                field: "length",
+                ..
+            })
+        ));
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -233,7 +372,7 @@ mod tests {
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap();
         let actual = png.as_bytes();
-        let expected: Vec<u8> = PNG_FILE.iter().copied().collect();
+        let expected: Vec<u8> = PNG_FILE.to_vec();
         assert_eq!(actual, expected);
     }
 
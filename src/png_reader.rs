@@ -0,0 +1,231 @@
+use std::{
+    convert::{TryFrom, TryInto},
+    io::{self, Read},
+};
+
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png, PngError, Result};
+
+/*
+ * Streaming PNG reader: checks the signature, then yields one chunk at a time
+ * without ever holding more than a single chunk in memory
+ */
+
+const READ_BLOCK: usize = 8192;
+
+pub struct PngReader<R: Read> {
+    inner: R,
+    max_chunk_length: u32,
+    offset: usize,
+    done: bool,
+}
+
+impl<R: Read> PngReader<R> {
+    // Chunks longer than this are refused unless the limit is raised
+    pub const DEFAULT_MAX_CHUNK_LENGTH: u32 = 64 * 1024 * 1024;
+
+    pub fn new(mut inner: R) -> Result<PngReader<R>> {
+        let mut header = [0u8; 8];
+        match inner.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(PngError::BadSignature)
+            }
+            Err(e) => return Err(e.into()),
+        }
+        if header != Png::STANDARD_HEADER {
+            return Err(PngError::BadSignature);
+        }
+        return Ok(PngReader {
+            inner,
+            max_chunk_length: PngReader::<R>::DEFAULT_MAX_CHUNK_LENGTH,
+            offset: 8,
+            done: false,
+        });
+    }
+
+    pub fn with_max_chunk_length(mut self, max: u32) -> PngReader<R> {
+        self.max_chunk_length = max;
+        return self;
+    }
+
+    // Byte offset of the next chunk in the underlying stream
+    pub fn offset(&self) -> usize {
+        return self.offset;
+    }
+
+    pub fn into_inner(self) -> R {
+        return self.inner;
+    }
+
+    // Fill `buf` completely, or report how many bytes were available before EOF
+    fn fill(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.inner.read(&mut buf[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        return Ok(read);
+    }
+
+    fn read_chunk(&mut self) -> Result<Option<Chunk>> {
+        let start = self.offset;
+        let truncated = PngError::TruncatedChunk { offset: start };
+
+        let mut head = [0u8; 8];
+        match self.fill(&mut head)? {
+            0 => return Ok(None),
+            8 => {}
+            _ => return Err(truncated),
+        }
+        let length = u32::from_be_bytes(head[0..4].try_into().unwrap());
+        if length > self.max_chunk_length {
+            return Err(PngError::ChunkTooLarge {
+                offset: start,
+                length,
+                max: self.max_chunk_length,
+            });
+        }
+        let ctype = ChunkType::try_from(<[u8; 4]>::try_from(&head[4..8]).unwrap())?;
+
+        let mut digest = Chunk::CHUNK_CRC.digest();
+        digest.update(&head[4..8]);
+        let mut data: Vec<u8> = Vec::with_capacity(length as usize);
+        let mut block = [0u8; READ_BLOCK];
+        let mut remaining = length as usize;
+        while remaining > 0 {
+            let want = remaining.min(block.len());
+            let got = self.fill(&mut block[..want])?;
+            if got != want {
+                return Err(truncated);
+            }
+            digest.update(&block[..got]);
+            data.extend_from_slice(&block[..got]);
+            remaining -= got;
+        }
+
+        let mut raw_crc = [0u8; 4];
+        if self.fill(&mut raw_crc)? != 4 {
+            return Err(truncated);
+        }
+        let ccrc = u32::from_be_bytes(raw_crc);
+        let computed = digest.finalize();
+        if ccrc != computed {
+            return Err(PngError::CrcMismatch {
+                chunk_type: ctype,
+                expected: computed,
+                actual: ccrc,
+            });
+        }
+
+        self.offset += length as usize + 12;
+        return Ok(Some(Chunk::from_parts(ctype, data, ccrc)));
+    }
+}
+
+impl<R: Read> Iterator for PngReader<R> {
+    type Item = Result<Chunk>;
+
+    fn next(&mut self) -> Option<Result<Chunk>> {
+        if self.done {
+            return None;
+        }
+        let res = self.read_chunk().transpose();
+        if !matches!(res, Some(Ok(_))) {
+            self.done = true;
+        }
+        return res;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs::File, io::Cursor, str::FromStr};
+
+    fn testing_bytes() -> Vec<u8> {
+        let chunks = vec![
+            Chunk::new(ChunkType::from_str("FrSt").unwrap(), b"first".to_vec()),
+            Chunk::new(ChunkType::from_str("miDl").unwrap(), vec![7; 20000]),
+            Chunk::new(ChunkType::from_str("LASt").unwrap(), Vec::new()),
+        ];
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    #[test]
+    fn test_reads_every_chunk() {
+        let bytes = testing_bytes();
+        let reader = PngReader::new(Cursor::new(&bytes)).unwrap();
+        let chunks: Vec<Chunk> = reader.collect::<Result<_>>().unwrap();
+        assert_eq!(Png::from_chunks(chunks), Png::try_from(&bytes[..]).unwrap());
+    }
+
+    #[test]
+    fn test_reads_image_file() {
+        let f = File::open("./data/png/rust_crab.png").unwrap();
+        let types: Vec<String> = PngReader::new(f)
+            .unwrap()
+            .map(|c| c.unwrap().chunk_type().to_string())
+            .collect();
+        assert_eq!(types, vec!["IHDR", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_bad_signature() {
+        let mut bytes = testing_bytes();
+        bytes[0] = 0;
+        assert!(matches!(
+            PngReader::new(Cursor::new(&bytes)),
+            Err(PngError::BadSignature)
+        ));
+        assert!(matches!(
+            PngReader::new(Cursor::new(&bytes[..3])),
+            Err(PngError::BadSignature)
+        ));
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        let mut bytes = testing_bytes();
+        bytes.truncate(bytes.len() - 2);
+        let results: Vec<Result<Chunk>> = PngReader::new(Cursor::new(&bytes)).unwrap().collect();
+        assert_eq!(results.len(), 3);
+        let second_end = 8 + (12 + 5) + (12 + 20000);
+        assert!(matches!(
+            results[2],
+            Err(PngError::TruncatedChunk { offset }) if offset == second_end
+        ));
+    }
+
+    #[test]
+    fn test_chunk_too_large() {
+        let bytes = testing_bytes();
+        let mut reader = PngReader::new(Cursor::new(&bytes))
+            .unwrap()
+            .with_max_chunk_length(1024);
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(
+            reader.next(),
+            Some(Err(PngError::ChunkTooLarge {
+                offset: 25,
+                length: 20000,
+                max: 1024
+            }))
+        ));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_crc_mismatch() {
+        let mut bytes = testing_bytes();
+        bytes[8 + 12] ^= 1;
+        let mut reader = PngReader::new(Cursor::new(&bytes)).unwrap();
+        assert!(matches!(
+            reader.next(),
+            Some(Err(PngError::CrcMismatch { .. }))
+        ));
+    }
+}
@@ -1,22 +1,44 @@
 use std::{
     convert::TryFrom,
     fs::File,
-    io::{self, Read},
+    io::{BufWriter, Read},
+    path::Path,
 };
 
-use crate::{chunk_type::ChunkType, png::Png, Error, Result};
+use crate::{
+    chunk_type::ChunkType,
+    png::Png,
+    recovery::{ParseOptions, ParseReport},
+    Result,
+};
 
 /*
  * General purpose helper functions for PNG analysis
  */
 
-pub fn read_png(filepath: &str) -> Result<Png> {
+pub fn read_png<P: AsRef<Path>>(filepath: P) -> Result<Png> {
     let mut buffer: Vec<u8> = Vec::new();
     let mut f = File::open(filepath)?;
     f.read_to_end(&mut buffer)?;
     Png::try_from(&buffer[..])
 }
 
+pub fn read_png_with<P: AsRef<Path>>(
+    filepath: P,
+    options: &ParseOptions,
+) -> Result<(Png, ParseReport)> {
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut f = File::open(filepath)?;
+    f.read_to_end(&mut buffer)?;
+    Png::parse_with(&buffer, options)
+}
+
+pub fn write_png<P: AsRef<Path>>(filepath: P, png: &Png) -> Result<()> {
+    let f = File::create(filepath)?;
+    png.write_to(BufWriter::new(f))?;
+    Ok(())
+}
+
 pub fn chunk_headers(png: Png) -> Vec<ChunkType> {
     let k = png
         .chunks()
@@ -31,7 +53,6 @@ pub fn chunk_headers(png: Png) -> Vec<ChunkType> {
 pub fn chunk_headers_show(png: Png) -> Vec<String> {
     let mut ret: Vec<String> = Vec::new();
     for chunk in png.chunks() {
-        print!("{:?}", &chunk.chunk_type().bytes());
         ret.push(String::from(
             std::str::from_utf8(&chunk.chunk_type().bytes()[..]).unwrap(),
         ));
@@ -0,0 +1,126 @@
+use std::{convert::TryFrom, io::Write};
+
+use crate::{
+    chunk::Chunk,
+    chunk_spec::{ChunkSpec, Chunk_IEND},
+    chunk_type::ChunkType,
+    png::Png,
+    PngError, Result,
+};
+
+/*
+ * Streaming PNG writer: emits the signature, then each chunk straight to the
+ * sink. Raw chunks get their CRC computed as the bytes go out; a `Chunk` is
+ * written with the CRC it holds, exactly as `Png::as_bytes` would, so a chunk
+ * kept by the lenient parser stays corrupt until `Png::repair_crcs` fixes it
+ */
+
+pub struct PngWriter<W: Write> {
+    inner: W,
+    ended: bool,
+}
+
+impl<W: Write> PngWriter<W> {
+    pub fn new(mut inner: W) -> Result<PngWriter<W>> {
+        inner.write_all(&Png::STANDARD_HEADER)?;
+        return Ok(PngWriter {
+            inner,
+            ended: false,
+        });
+    }
+
+    pub fn write_chunk(&mut self, chunk: &Chunk) -> Result<()> {
+        return self.write_parts(chunk.chunk_type(), chunk.data(), Some(chunk.crc()));
+    }
+
+    // Write a chunk without first assembling it into a `Chunk`
+    pub fn write_raw(&mut self, chunk_type: &ChunkType, data: &[u8]) -> Result<()> {
+        return self.write_parts(chunk_type, data, None);
+    }
+
+    // Write a chunk with the given CRC, or the one computed over its bytes
+    fn write_parts(&mut self, chunk_type: &ChunkType, data: &[u8], crc: Option<u32>) -> Result<()> {
+        if self.ended {
+            return Err(PngError::ChunkAfterIend(chunk_type.clone()));
+        }
+        let length = u32::try_from(data.len()).map_err(|_| PngError::DataTooLarge(data.len()))?;
+        let ctype = chunk_type.bytes();
+
+        self.inner.write_all(&length.to_be_bytes())?;
+        self.inner.write_all(&ctype)?;
+        self.inner.write_all(data)?;
+        let crc = match crc {
+            Some(crc) => crc,
+            None => {
+                let mut digest = Chunk::CHUNK_CRC.digest();
+                digest.update(&ctype);
+                digest.update(data);
+                digest.finalize()
+            }
+        };
+        self.inner.write_all(&crc.to_be_bytes())?;
+
+        if ctype == Chunk_IEND::HEADER {
+            self.ended = true;
+        }
+        return Ok(());
+    }
+
+    // Terminate the stream with IEND if the caller has not already, then flush
+    pub fn finish(mut self) -> Result<W> {
+        if !self.ended {
+            self.write_chunk(&Chunk_IEND {}.encode())?;
+        }
+        self.inner.flush()?;
+        return Ok(self.inner);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_matches_as_bytes() {
+        let bytes = std::fs::read("./data/png/rust_crab.png").unwrap();
+        let png = Png::try_from(&bytes[..]).unwrap();
+
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        for c in png.chunks() {
+            writer.write_chunk(c).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_finish_appends_iend() {
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        writer.write_chunk(&chunk("RuSt", b"message")).unwrap();
+        let out = writer.finish().unwrap();
+
+        let png = Png::try_from(&out[..]).unwrap();
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, vec!["RuSt", "IEND"]);
+        assert_eq!(png.chunks()[1], Chunk_IEND {}.encode());
+    }
+
+    #[test]
+    fn test_no_chunks_after_iend() {
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        writer.write_chunk(&chunk("IEND", b"")).unwrap();
+        let res = writer.write_chunk(&chunk("RuSt", b"message"));
+        assert!(matches!(res, Err(PngError::ChunkAfterIend(_))));
+
+        let out = writer.finish().unwrap();
+        assert_eq!(out.len(), 8 + 12);
+    }
+}
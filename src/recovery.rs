@@ -0,0 +1,459 @@
+use std::{
+    convert::{TryFrom, TryInto},
+    fmt::{self, Display},
+};
+
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png, PngError, Result};
+
+/*
+ * Parsing for damaged files. In lenient mode the parser keeps chunks whose CRC
+ * does not match (`Chunk::is_corrupt` tells them apart), skips over bytes that
+ * do not form a chunk, and keeps the data of a chunk cut short by the end of
+ * the file, noting each step
+ */
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Strictness {
+    // Fail on the first problem, exactly like `Png::try_from`
+    Strict,
+    // Recover from whatever it can and report what it did
+    Lenient,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct ParseOptions {
+    pub strictness: Strictness,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            strictness: Strictness::Strict,
+        }
+    }
+}
+
+// One step the lenient parser took to get past damage
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Recovery {
+    // Chunk number `chunk` was kept although its stored CRC is wrong
+    CorruptCrc {
+        offset: usize,
+        chunk: usize,
+        chunk_type: ChunkType,
+        expected: u32,
+        actual: u32,
+    },
+    // `length` bytes that do not start a valid chunk were dropped
+    SkippedBytes {
+        offset: usize,
+        length: usize,
+    },
+    // Chunk number `chunk` ended `missing` bytes past the end of the file;
+    // the data that was there is kept with a freshly computed CRC
+    PartialChunk {
+        offset: usize,
+        chunk: usize,
+        chunk_type: ChunkType,
+        missing: usize,
+    },
+}
+
+impl Recovery {
+    // Byte offset in the file where the problem starts
+    pub fn offset(&self) -> usize {
+        match self {
+            Recovery::CorruptCrc { offset, .. } => *offset,
+            Recovery::SkippedBytes { offset, .. } => *offset,
+            Recovery::PartialChunk { offset, .. } => *offset,
+        }
+    }
+}
+
+impl Display for Recovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Recovery::CorruptCrc {
+                offset,
+                chunk,
+                chunk_type,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "byte {}: kept chunk {} ({}) with bad CRC {:#010x}, expected {:#010x}",
+                offset, chunk, chunk_type, actual, expected
+            ),
+            Recovery::SkippedBytes { offset, length } => {
+                write!(f, "byte {}: skipped {} unparseable bytes", offset, length)
+            }
+            Recovery::PartialChunk {
+                offset,
+                chunk,
+                chunk_type,
+                missing,
+            } => write!(
+                f,
+                "byte {}: kept chunk {} ({}) missing its last {} bytes",
+                offset, chunk, chunk_type, missing
+            ),
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct ParseReport {
+    pub recoveries: Vec<Recovery>,
+}
+
+impl ParseReport {
+    pub fn is_clean(&self) -> bool {
+        self.recoveries.is_empty()
+    }
+}
+
+// A chunk whose stored CRC does not match its type and data
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct CrcFix {
+    pub chunk: usize,
+    pub chunk_type: ChunkType,
+    pub stored: u32,
+    pub computed: u32,
+}
+
+impl Display for CrcFix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "chunk {} ({}): stored CRC {:#010x}, computed {:#010x}",
+            self.chunk, self.chunk_type, self.stored, self.computed
+        )
+    }
+}
+
+// Length and type of a chunk header at `at`, if those bytes could be one
+fn header_at(bytes: &[u8], at: usize) -> Option<(usize, ChunkType)> {
+    let head = bytes.get(at..at + 8)?;
+    let length = u32::from_be_bytes(head[0..4].try_into().unwrap()) as usize;
+    let ctype = ChunkType::try_from(<[u8; 4]>::try_from(&head[4..8]).unwrap()).ok()?;
+    return Some((length, ctype));
+}
+
+// Stored and computed CRC of a chunk known to fit inside `bytes`
+fn crcs_at(bytes: &[u8], at: usize, length: usize) -> (u32, u32) {
+    let end = at + 8 + length;
+    let stored = u32::from_be_bytes(bytes[end..end + 4].try_into().unwrap());
+    return (stored, Chunk::CHUNK_CRC.checksum(&bytes[at + 4..end]));
+}
+
+// The next offset after `from` holding a complete chunk with a correct CRC.
+// Requiring the CRC keeps random bytes from being taken for a chunk
+fn resync(bytes: &[u8], from: usize) -> Option<usize> {
+    return (from..bytes.len().saturating_sub(11)).find(|&at| match header_at(bytes, at) {
+        Some((length, _)) if at + 12 + length <= bytes.len() => {
+            let (stored, computed) = crcs_at(bytes, at, length);
+            stored == computed
+        }
+        _ => false,
+    });
+}
+
+impl Png {
+    // Parse `bytes` as a PNG, recovering from damage as `options` allow
+    pub fn parse_with(bytes: &[u8], options: &ParseOptions) -> Result<(Png, ParseReport)> {
+        if options.strictness == Strictness::Strict {
+            return Ok((Png::try_from(bytes)?, ParseReport::default()));
+        }
+        if bytes.len() < 8 || bytes[0..8] != Png::STANDARD_HEADER {
+            return Err(PngError::BadSignature);
+        }
+        let mut report = ParseReport::default();
+        let mut chunks: Vec<Chunk> = Vec::new();
+        let mut at = 8;
+        while at < bytes.len() {
+            let header = header_at(bytes, at);
+            if let Some((length, ctype)) = header.clone() {
+                let end = at + 12 + length;
+                if end <= bytes.len() {
+                    let (stored, computed) = crcs_at(bytes, at, length);
+                    // A bad CRC on a chunk that is not followed by another chunk
+                    // more likely means these bytes were never a chunk at all
+                    let plausible = end == bytes.len() || header_at(bytes, end).is_some();
+                    if stored != computed && plausible {
+                        report.recoveries.push(Recovery::CorruptCrc {
+                            offset: at,
+                            chunk: chunks.len(),
+                            chunk_type: ctype.clone(),
+                            expected: computed,
+                            actual: stored,
+                        });
+                    }
+                    if stored == computed || plausible {
+                        let data = bytes[at + 8..at + 8 + length].to_vec();
+                        chunks.push(Chunk::from_parts(ctype, data, stored));
+                        at = end;
+                        continue;
+                    }
+                }
+            }
+            // Either garbage, or a header whose chunk runs past the end
+            match (resync(bytes, at + 1), header) {
+                (Some(next), _) => {
+                    report.recoveries.push(Recovery::SkippedBytes {
+                        offset: at,
+                        length: next - at,
+                    });
+                    at = next;
+                }
+                (None, Some((length, ctype))) if at + 12 + length > bytes.len() => {
+                    report.recoveries.push(Recovery::PartialChunk {
+                        offset: at,
+                        chunk: chunks.len(),
+                        chunk_type: ctype.clone(),
+                        missing: at + 12 + length - bytes.len(),
+                    });
+                    let end = bytes.len().min(at + 8 + length);
+                    chunks.push(Chunk::new(ctype, bytes[at + 8..end].to_vec()));
+                    at = bytes.len();
+                }
+                (None, _) => {
+                    report.recoveries.push(Recovery::SkippedBytes {
+                        offset: at,
+                        length: bytes.len() - at,
+                    });
+                    at = bytes.len();
+                }
+            }
+        }
+        return Ok((Png::from_chunks(chunks), report));
+    }
+
+    // Every chunk whose stored CRC is wrong, without changing anything
+    pub fn crc_mismatches(&self) -> Vec<CrcFix> {
+        return self
+            .chunks()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| {
+                if !c.is_corrupt() {
+                    return None;
+                }
+                let computed = c.computed_crc();
+                return Some(CrcFix {
+                    chunk: i,
+                    chunk_type: c.chunk_type().clone(),
+                    stored: c.crc(),
+                    computed,
+                });
+            })
+            .collect();
+    }
+
+    // Recompute the CRC of every chunk, returning the ones that were wrong
+    pub fn repair_crcs(&mut self) -> Vec<CrcFix> {
+        let fixes = self.crc_mismatches();
+        let chunks = self.chunks_mut();
+        for fix in fixes.iter() {
+            let c = &chunks[fix.chunk];
+            chunks[fix.chunk] = Chunk::new(c.chunk_type().clone(), c.data().to_vec());
+        }
+        return fixes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const LENIENT: ParseOptions = ParseOptions {
+        strictness: Strictness::Lenient,
+    };
+
+    fn dice() -> Vec<u8> {
+        fs::read("./data/png/dice.png").unwrap()
+    }
+
+    // Byte offset of each chunk in an undamaged file
+    fn offsets(bytes: &[u8]) -> Vec<usize> {
+        let png = Png::try_from(bytes).unwrap();
+        let mut at = 8;
+        let mut out: Vec<usize> = Vec::new();
+        for c in png.chunks() {
+            out.push(at);
+            at += c.length() as usize + 12;
+        }
+        out
+    }
+
+    #[test]
+    fn test_clean_file() {
+        let bytes = dice();
+        let (png, report) = Png::parse_with(&bytes, &LENIENT).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(png, Png::try_from(&bytes[..]).unwrap());
+    }
+
+    #[test]
+    fn test_strict_fails() {
+        let mut bytes = dice();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(matches!(
+            Png::parse_with(&bytes, &ParseOptions::default()),
+            Err(PngError::CrcMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_keeps_corrupt_crc() {
+        let mut bytes = dice();
+        let at = offsets(&bytes)[1];
+        // Flip a data byte of the second chunk
+        bytes[at + 8] ^= 0xff;
+        let (png, report) = Png::parse_with(&bytes, &LENIENT).unwrap();
+        assert_eq!(png.chunks().len(), 7);
+        assert_eq!(report.recoveries.len(), 1);
+        match &report.recoveries[0] {
+            Recovery::CorruptCrc {
+                offset,
+                chunk,
+                chunk_type,
+                ..
+            } => {
+                assert_eq!(*offset, at);
+                assert_eq!(*chunk, 1);
+                assert_eq!(chunk_type.to_string(), "sRGB");
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+        assert!(png.chunks()[1].is_corrupt());
+        assert_eq!(png.chunks().iter().filter(|c| c.is_corrupt()).count(), 1);
+        // The stored CRC is kept, so writing the file back reproduces it
+        assert_eq!(png.as_bytes(), bytes);
+        assert_eq!(png.write_to(Vec::new()).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_skips_garbage() {
+        let clean = dice();
+        let at = offsets(&clean)[2];
+        let mut bytes = clean[..at].to_vec();
+        bytes.extend_from_slice(b"\x00\x00\x01garbage!");
+        bytes.extend_from_slice(&clean[at..]);
+        let (png, report) = Png::parse_with(&bytes, &LENIENT).unwrap();
+        assert_eq!(png, Png::try_from(&clean[..]).unwrap());
+        assert_eq!(
+            report.recoveries,
+            vec![Recovery::SkippedBytes {
+                offset: at,
+                length: 11
+            }]
+        );
+        assert_eq!(report.recoveries[0].offset(), at);
+    }
+
+    #[test]
+    fn test_skips_bytes_that_look_like_a_chunk() {
+        let clean = dice();
+        let at = offsets(&clean)[2];
+        // A well formed header whose chunk would end in the middle of gAMA
+        let mut bytes = clean[..at].to_vec();
+        bytes.extend_from_slice(b"\x00\x00\x00\x05RaNdxy");
+        bytes.extend_from_slice(&clean[at..]);
+        let (png, report) = Png::parse_with(&bytes, &LENIENT).unwrap();
+        assert_eq!(png, Png::try_from(&clean[..]).unwrap());
+        assert_eq!(
+            report.recoveries,
+            vec![Recovery::SkippedBytes {
+                offset: at,
+                length: 10
+            }]
+        );
+    }
+
+    #[test]
+    fn test_keeps_partial_chunk_at_eof() {
+        let clean = dice();
+        let idat = offsets(&clean)[4];
+        // Cut the file in the middle of IDAT, losing RuSt and IEND as well
+        let bytes = &clean[..idat + 100];
+        let (png, report) = Png::parse_with(bytes, &LENIENT).unwrap();
+        assert_eq!(png.chunks().len(), 5);
+        let partial = &png.chunks()[4];
+        assert_eq!(partial.chunk_type().to_string(), "IDAT");
+        assert_eq!(partial.data(), &clean[idat + 8..idat + 100]);
+        match &report.recoveries[..] {
+            [Recovery::PartialChunk {
+                offset,
+                chunk,
+                missing,
+                ..
+            }] => {
+                assert_eq!((*offset, *chunk), (idat, 4));
+                assert_eq!(
+                    *missing,
+                    clean.len() - bytes.len() - (clean.len() - offsets(&clean)[5])
+                );
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_trailing_garbage() {
+        let mut bytes = dice();
+        let len = bytes.len();
+        bytes.extend_from_slice(&[1, 2, 3]);
+        let (png, report) = Png::parse_with(&bytes, &LENIENT).unwrap();
+        assert_eq!(png.chunks().len(), 7);
+        assert_eq!(
+            report.recoveries,
+            vec![Recovery::SkippedBytes {
+                offset: len,
+                length: 3
+            }]
+        );
+        assert_eq!(
+            report.recoveries[0].to_string(),
+            format!("byte {}: skipped 3 unparseable bytes", len)
+        );
+    }
+
+    #[test]
+    fn test_repair_crcs() {
+        let clean = dice();
+        let mut bytes = clean.clone();
+        let at = offsets(&bytes);
+        bytes[at[1] + 8] ^= 0xff;
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        let (mut png, _) = Png::parse_with(&bytes, &LENIENT).unwrap();
+
+        let found = png.crc_mismatches();
+        assert_eq!(
+            found.iter().map(|f| f.chunk).collect::<Vec<usize>>(),
+            vec![1, 6]
+        );
+        assert_eq!(found[1].chunk_type.to_string(), "IEND");
+        assert_eq!(png.as_bytes(), bytes);
+
+        assert_eq!(png.repair_crcs(), found);
+        assert!(png.crc_mismatches().is_empty());
+        assert!(png.chunks().iter().all(|c| !c.is_corrupt()));
+        assert_eq!(png.write_to(Vec::new()).unwrap(), png.as_bytes());
+        assert!(png.repair_crcs().is_empty());
+        // Only the damaged sRGB data differs from the original now
+        let repaired = png.as_bytes();
+        assert_eq!(repaired[last], clean[last]);
+        assert!(Png::try_from(&repaired[..]).is_ok());
+    }
+
+    #[test]
+    fn test_bad_signature_is_fatal() {
+        assert!(matches!(
+            Png::parse_with(b"not a png", &LENIENT),
+            Err(PngError::BadSignature)
+        ));
+    }
+}
@@ -0,0 +1,241 @@
+use std::{collections::BTreeMap, convert::TryInto};
+
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png, PngError, Result};
+
+/*
+ * Payloads spread over several chunks of one type. Each chunk's data is
+ *
+ *   sequence number (4) | total number of parts (4) | part of the payload
+ *
+ * with both numbers big endian and the sequence counting from 0
+ */
+
+pub const PART_HEADER: usize = 8;
+
+// Gaps named one by one in a reassembly error, before the rest are only counted
+const LISTED_GAPS: usize = 10;
+
+// Cut `payload` into `parts` chunks of `chunk_type`, as evenly as possible.
+// Every part but those of an empty payload holds at least one byte
+pub fn split_payload(chunk_type: &ChunkType, payload: &[u8], parts: u32) -> Result<Vec<Chunk>> {
+    if parts == 0 {
+        return Err(PngError::InvalidPayload(
+            "a payload must be split into at least 1 part".to_string(),
+        ));
+    }
+    if parts as usize > payload.len().max(1) {
+        return Err(PngError::InvalidPayload(format!(
+            "{} bytes cannot be split into {} parts",
+            payload.len(),
+            parts
+        )));
+    }
+    let n = parts as usize;
+    let (base, extra) = (payload.len() / n, payload.len() % n);
+    let mut at = 0;
+    let mut chunks: Vec<Chunk> = Vec::with_capacity(n);
+    for seq in 0..n {
+        let len = if seq < extra { base + 1 } else { base };
+        let mut data: Vec<u8> = Vec::with_capacity(PART_HEADER + len);
+        data.extend_from_slice(&(seq as u32).to_be_bytes());
+        data.extend_from_slice(&parts.to_be_bytes());
+        data.extend_from_slice(&payload[at..at + len]);
+        chunks.push(Chunk::new(chunk_type.clone(), data));
+        at += len;
+    }
+    return Ok(chunks);
+}
+
+fn list(numbers: &[u32]) -> String {
+    numbers
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// Put the parts made by `split_payload` back together, in any order. Fails,
+// naming every gap and duplicate, unless each part is there exactly once
+pub fn reassemble<'a, I: IntoIterator<Item = &'a Chunk>>(chunks: I) -> Result<Vec<u8>> {
+    let mut total: Option<u32> = None;
+    let mut parts: BTreeMap<u32, &[u8]> = BTreeMap::new();
+    let mut duplicates: Vec<u32> = Vec::new();
+    for chunk in chunks {
+        let data = chunk.data();
+        if data.len() < PART_HEADER {
+            return Err(PngError::InvalidPayload(format!(
+                "{} bytes is too short for a payload part",
+                data.len()
+            )));
+        }
+        let seq = u32::from_be_bytes(data[0..4].try_into().unwrap());
+        let count = u32::from_be_bytes(data[4..8].try_into().unwrap());
+        match total {
+            Some(t) if t != count => {
+                return Err(PngError::InvalidPayload(format!(
+                    "parts disagree on the total: {} and {}",
+                    t, count
+                )))
+            }
+            _ => total = Some(count),
+        }
+        if seq >= count {
+            return Err(PngError::InvalidPayload(format!(
+                "part {} is out of range for {} parts",
+                seq, count
+            )));
+        }
+        if parts.insert(seq, &data[PART_HEADER..]).is_some() {
+            duplicates.push(seq);
+        }
+    }
+    let total = match total {
+        Some(t) => t,
+        None => return Err(PngError::InvalidPayload("no parts found".to_string())),
+    };
+    // `total` comes from the file, so only the first few gaps are collected
+    let gaps = total as usize - parts.len();
+    let missing: Vec<u32> = (0..total)
+        .filter(|s| !parts.contains_key(s))
+        .take(LISTED_GAPS)
+        .collect();
+    let mut problems: Vec<String> = Vec::new();
+    if gaps > missing.len() {
+        problems.push(format!(
+            "missing parts {}, ... ({} in all)",
+            list(&missing),
+            gaps
+        ));
+    } else if !missing.is_empty() {
+        problems.push(format!("missing parts {}", list(&missing)));
+    }
+    if !duplicates.is_empty() {
+        duplicates.sort_unstable();
+        duplicates.dedup();
+        problems.push(format!("duplicate parts {}", list(&duplicates)));
+    }
+    if !problems.is_empty() {
+        return Err(PngError::InvalidPayload(format!(
+            "{} of {}",
+            problems.join("; "),
+            total
+        )));
+    }
+    return Ok(parts.values().flat_map(|p| p.iter().copied()).collect());
+}
+
+impl Png {
+    // Reassemble the payload split over every chunk of `chunk_type`
+    pub fn reassemble_payload(&self, chunk_type: &str) -> Result<Vec<u8>> {
+        let chunks: Vec<&Chunk> = self
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().bytes() == chunk_type.as_bytes())
+            .collect();
+        if chunks.is_empty() {
+            return Err(PngError::ChunkNotFound(chunk_type.to_string()));
+        }
+        return reassemble(chunks);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png_util::read_png;
+    use std::str::FromStr;
+
+    fn private_type() -> ChunkType {
+        ChunkType::from_str("ruSt").unwrap()
+    }
+
+    #[test]
+    fn test_split_sizes() {
+        let payload: Vec<u8> = (0..10).collect();
+        let chunks = split_payload(&private_type(), &payload, 4).unwrap();
+        let sizes: Vec<u32> = chunks.iter().map(|c| c.length()).collect();
+        assert_eq!(sizes, vec![11, 11, 10, 10]);
+        assert_eq!(chunks[1].data()[..8], [0, 0, 0, 1, 0, 0, 0, 4]);
+        assert_eq!(chunks[1].data()[8..], [3, 4, 5]);
+
+        assert_eq!(split_payload(&private_type(), b"ab", 2).unwrap().len(), 2);
+        assert_eq!(split_payload(&private_type(), b"", 1).unwrap().len(), 1);
+        // Every part must carry some of the payload
+        assert!(split_payload(&private_type(), b"ab", 5).is_err());
+        assert!(split_payload(&private_type(), b"ab", u32::MAX).is_err());
+        assert!(split_payload(&private_type(), b"ab", 0).is_err());
+    }
+
+    #[test]
+    fn test_round_trip_any_order() {
+        let payload: Vec<u8> = (0..=255).collect();
+        let mut chunks = split_payload(&private_type(), &payload, 7).unwrap();
+        chunks.reverse();
+        chunks.swap(1, 4);
+        assert_eq!(reassemble(chunks.iter()).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_gaps_and_duplicates() {
+        let chunks = split_payload(&private_type(), b"abcdefghij", 5).unwrap();
+        let picked = vec![&chunks[0], &chunks[2], &chunks[2], &chunks[4]];
+        match reassemble(picked) {
+            Err(PngError::InvalidPayload(reason)) => {
+                assert_eq!(reason, "missing parts 1, 3; duplicate parts 2 of 5")
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_repeated_duplicates() {
+        let chunks = split_payload(&private_type(), b"abcd", 4).unwrap();
+        let picked = vec![
+            &chunks[0], &chunks[1], &chunks[2], &chunks[3], &chunks[2], &chunks[3], &chunks[2],
+        ];
+        match reassemble(picked) {
+            Err(PngError::InvalidPayload(reason)) => {
+                assert_eq!(reason, "duplicate parts 2, 3 of 4")
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_huge_total() {
+        let part = Chunk::new(private_type(), vec![0, 0, 0, 0, 255, 255, 255, 255]);
+        match reassemble(vec![&part]) {
+            Err(PngError::InvalidPayload(reason)) => assert_eq!(
+                reason,
+                "missing parts 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, ... (4294967294 in all) of 4294967295"
+            ),
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_inconsistent_parts() {
+        let a = split_payload(&private_type(), b"abcd", 2).unwrap();
+        let b = split_payload(&private_type(), b"abcd", 3).unwrap();
+        assert!(reassemble(vec![&a[0], &b[1]]).is_err());
+        let short = Chunk::new(private_type(), vec![0, 0, 0]);
+        assert!(reassemble(vec![&short]).is_err());
+        let out_of_range = Chunk::new(private_type(), vec![0, 0, 0, 2, 0, 0, 0, 2]);
+        assert!(reassemble(vec![&out_of_range]).is_err());
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let mut png = read_png("./data/png/rust_crab.png").unwrap();
+        let payload = b"spread across the file".to_vec();
+        for chunk in split_payload(&private_type(), &payload, 3).unwrap() {
+            png.append_chunk(chunk);
+        }
+        assert!(png.validate().is_empty());
+        assert_eq!(png.reassemble_payload("ruSt").unwrap(), payload);
+        assert!(matches!(
+            png.reassemble_payload("abCd"),
+            Err(PngError::ChunkNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,397 @@
+use crate::{
+    chunk::Chunk,
+    chunk_spec::{Background, ChunkSpec, Chunk_IHDR, Chunk_PLTE, Chunk_bKGD, ColorType},
+    decoder::PixelBuffer,
+    encoder::FilterStrategy,
+    png::Png,
+    PngError, Result,
+};
+
+/*
+ * Least significant bit steganography: the payload replaces the low bits of
+ * every decoded sample, preceded by its length as a 32 bit big endian number.
+ * Each sample carries `bits_per_channel` bits, filled most significant first
+ */
+
+pub const LENGTH_HEADER: usize = 4;
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct LsbOptions {
+    pub bits_per_channel: u8,
+    // Palette images are refused unless this is set. The palette is then
+    // doubled so that indices 2i and 2i + 1 show the same colour, and one
+    // bit is hidden per pixel in the low bit of its index
+    pub palette_safe: bool,
+    // How to filter the re-encoded image; None picks
+    // `FilterStrategy::default_for` its colour type and bit depth
+    pub filter_strategy: Option<FilterStrategy>,
+}
+
+impl Default for LsbOptions {
+    fn default() -> Self {
+        LsbOptions {
+            bits_per_channel: 1,
+            palette_safe: false,
+            filter_strategy: None,
+        }
+    }
+}
+
+// Payload bytes an image with header `ihdr` can hold, after the length header
+pub fn lsb_capacity(ihdr: &Chunk_IHDR, bits_per_channel: u8) -> usize {
+    let samples = ihdr.width as usize * ihdr.height as usize * ihdr.color_type.channels();
+    let bits = samples * bits_per_channel.min(ihdr.bit_depth) as usize;
+    return (bits / 8).saturating_sub(LENGTH_HEADER);
+}
+
+fn check_options(ihdr: &Chunk_IHDR, options: &LsbOptions) -> Result<()> {
+    let bits = options.bits_per_channel;
+    if bits == 0 || bits > 8 || bits > ihdr.bit_depth {
+        return Err(PngError::Unsupported(format!(
+            "{} bits per channel with {} bit samples",
+            bits, ihdr.bit_depth
+        )));
+    }
+    if ihdr.color_type == ColorType::PLT && bits != 1 {
+        return Err(PngError::Unsupported(
+            "palette images hold only 1 bit per pixel".to_string(),
+        ));
+    }
+    return Ok(());
+}
+
+// Byte index and shift of the low bits of every sample, in scanline order
+fn sample_positions(image: &PixelBuffer) -> impl Iterator<Item = (usize, u32)> {
+    let stride = image.stride();
+    let depth = image.bit_depth as usize;
+    let per_row = image.width as usize * image.color_type.channels();
+    let height = image.height as usize;
+    (0..height).flat_map(move |y| {
+        (0..per_row).map(move |s| {
+            if depth >= 8 {
+                // The last byte of a big endian sample holds its low bits
+                let bytes = depth / 8;
+                (y * stride + s * bytes + bytes - 1, 0)
+            } else {
+                let bit = s * depth;
+                (y * stride + bit / 8, (8 - depth - bit % 8) as u32)
+            }
+        })
+    })
+}
+
+fn embed_bits(image: &mut PixelBuffer, bits_per_channel: u8, payload: &[u8]) {
+    let k = bits_per_channel as usize;
+    let mask = ((1u16 << k) - 1) as u8;
+    let total = payload.len() * 8;
+    let positions: Vec<(usize, u32)> = sample_positions(image).collect();
+    for (i, (byte, shift)) in positions.into_iter().enumerate() {
+        if i * k >= total {
+            break;
+        }
+        let mut value = 0u8;
+        for b in i * k..i * k + k {
+            let bit = if b < total {
+                (payload[b / 8] >> (7 - b % 8)) & 1
+            } else {
+                0
+            };
+            value = (value << 1) | bit;
+        }
+        let d = &mut image.data[byte];
+        *d = (*d & !(mask << shift)) | (value << shift);
+    }
+}
+
+fn extract_bits(image: &PixelBuffer, bits_per_channel: u8, skip: usize, len: usize) -> Vec<u8> {
+    let k = bits_per_channel as usize;
+    let mask = ((1u16 << k) - 1) as u8;
+    let mut out: Vec<u8> = vec![0; len];
+    let first = skip * 8;
+    let last = first + len * 8;
+    for (i, (byte, shift)) in sample_positions(image).enumerate() {
+        if i * k >= last {
+            break;
+        }
+        let value = (image.data[byte] >> shift) & mask;
+        for j in 0..k {
+            let b = i * k + j;
+            if b >= first && b < last {
+                let bit = (value >> (k - 1 - j)) & 1;
+                out[(b - first) / 8] |= bit << (7 - (b - first) % 8);
+            }
+        }
+    }
+    return out;
+}
+
+// Double every palette entry, and the chunks indexing into it, so that
+// indices 2i and 2i + 1 look identical. Returns the remapped image
+fn double_palette(png: &mut Png, image: &PixelBuffer) -> Result<PixelBuffer> {
+    let plte = png
+        .get::<Chunk_PLTE>()?
+        .ok_or_else(|| PngError::ChunkNotFound("PLTE".to_string()))?;
+    let max = 1usize << image.bit_depth;
+    if plte.entries.len() * 2 > max {
+        return Err(PngError::Unsupported(format!(
+            "a palette of {} entries cannot be doubled at bit depth {}",
+            plte.entries.len(),
+            image.bit_depth
+        )));
+    }
+    let bkgd = png.get::<Chunk_bKGD>()?;
+    let doubled = |data: &[u8]| -> Vec<u8> { data.iter().flat_map(|&v| vec![v, v]).collect() };
+
+    let chunks = png.chunks_mut();
+    // Frequencies no longer match the doubled palette
+    chunks.retain(|c| c.chunk_type().bytes() != *b"hIST");
+    for c in chunks.iter_mut() {
+        let ctype = c.chunk_type().clone();
+        if ctype.bytes() == Chunk_PLTE::HEADER {
+            let entries = plte.entries.iter().flat_map(|&e| vec![e, e]).collect();
+            *c = Chunk_PLTE { entries }.encode();
+        } else if ctype.bytes() == *b"tRNS" {
+            *c = Chunk::new(ctype, doubled(c.data()));
+        } else if ctype.bytes() == Chunk_bKGD::HEADER {
+            if let Some(Chunk_bKGD {
+                color: Background::PaletteIndex(i),
+            }) = bkgd
+            {
+                *c = Chunk_bKGD {
+                    color: Background::PaletteIndex(i.wrapping_mul(2)),
+                }
+                .encode();
+            }
+        }
+    }
+
+    let mut remapped = image.clone();
+    let depth = image.bit_depth as u32;
+    let mask = ((1u16 << depth) - 1) as u8;
+    for (byte, shift) in sample_positions(image) {
+        // Sub-byte samples sit above `shift`; whole bytes have shift 0
+        let index = (image.data[byte] >> shift) & mask;
+        let d = &mut remapped.data[byte];
+        *d = (*d & !(mask << shift)) | ((index << 1) << shift);
+    }
+    return Ok(remapped);
+}
+
+impl Png {
+    // Hide `payload` in the low bits of the image samples and re-encode it
+    pub fn embed_lsb(&mut self, payload: &[u8], options: &LsbOptions) -> Result<()> {
+        let ihdr = self.ihdr()?;
+        check_options(&ihdr, options)?;
+        if ihdr.color_type == ColorType::PLT && !options.palette_safe {
+            return Err(PngError::Unsupported(
+                "LSB embedding would change the colours of a palette image; \
+                 use the palette-safe method"
+                    .to_string(),
+            ));
+        }
+        let capacity = lsb_capacity(&ihdr, options.bits_per_channel);
+        if payload.len() > capacity || payload.len() > u32::MAX as usize {
+            return Err(PngError::InvalidPayload(format!(
+                "{} bytes exceed the image's capacity of {}",
+                payload.len(),
+                capacity
+            )));
+        }
+        let mut image = self.decode_pixels()?;
+        if ihdr.color_type == ColorType::PLT {
+            image = double_palette(self, &image)?;
+        }
+        let mut stream = (payload.len() as u32).to_be_bytes().to_vec();
+        stream.extend_from_slice(payload);
+        embed_bits(&mut image, options.bits_per_channel, &stream);
+        let strategy = options
+            .filter_strategy
+            .unwrap_or_else(|| FilterStrategy::default_for(image.color_type, image.bit_depth));
+        return self.set_pixels(&image, ihdr.interlace_method, strategy);
+    }
+
+    // Recover a payload hidden by `embed_lsb` with the same bits per channel
+    pub fn extract_lsb(&self, options: &LsbOptions) -> Result<Vec<u8>> {
+        let ihdr = self.ihdr()?;
+        check_options(&ihdr, options)?;
+        let image = self.decode_pixels()?;
+        let k = options.bits_per_channel;
+        let header = extract_bits(&image, k, 0, LENGTH_HEADER);
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let capacity = lsb_capacity(&ihdr, k);
+        if length > capacity {
+            return Err(PngError::InvalidPayload(format!(
+                "hidden length {} exceeds the image's capacity of {}",
+                length, capacity
+            )));
+        }
+        return Ok(extract_bits(&image, k, LENGTH_HEADER, length));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encoder::Encoder, filter::FilterType, png_util::read_png};
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 131 % 256) as u8).collect()
+    }
+
+    #[test]
+    fn test_capacity() {
+        let png = read_png("./data/png/rust_crab.png").unwrap();
+        let ihdr = png.ihdr().unwrap();
+        let samples = (ihdr.width * ihdr.height) as usize * ihdr.color_type.channels();
+        assert_eq!(lsb_capacity(&ihdr, 1), samples / 8 - 4);
+        assert_eq!(lsb_capacity(&ihdr, 2), samples / 4 - 4);
+
+        let png = read_png("./data/png/adam7_grey2.png").unwrap();
+        // 11 x 5 pixels of one channel, at most 2 bits each
+        assert_eq!(lsb_capacity(&png.ihdr().unwrap(), 1), 6 - 4);
+        assert_eq!(lsb_capacity(&png.ihdr().unwrap(), 4), 13 - 4);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for &bits in [1u8, 2, 4, 8].iter() {
+            let mut png = read_png("./data/png/rust_crab.png").unwrap();
+            let before = png.decode_pixels().unwrap();
+            let options = LsbOptions {
+                bits_per_channel: bits,
+                palette_safe: false,
+                ..LsbOptions::default()
+            };
+            let secret = payload(1000);
+            png.embed_lsb(&secret, &options).unwrap();
+            assert_eq!(png.extract_lsb(&options).unwrap(), secret);
+            assert!(png.validate().is_empty());
+
+            // Only the low bits changed
+            let after = png.decode_pixels().unwrap();
+            let keep = !(((1u16 << bits) - 1) as u8);
+            assert!(before
+                .data
+                .iter()
+                .zip(after.data.iter())
+                .all(|(a, b)| a & keep == b & keep));
+        }
+    }
+
+    #[test]
+    fn test_round_trip_packed_and_interlaced() {
+        let mut png = read_png("./data/png/adam7_grey2.png").unwrap();
+        let options = LsbOptions {
+            bits_per_channel: 2,
+            palette_safe: false,
+            ..LsbOptions::default()
+        };
+        png.embed_lsb(b"hi!", &options).unwrap();
+        assert_eq!(png.extract_lsb(&options).unwrap(), b"hi!");
+        assert!(matches!(
+            png.embed_lsb(b"far too long", &options),
+            Err(PngError::InvalidPayload(_))
+        ));
+    }
+
+    #[test]
+    fn test_filter_strategy() {
+        let original = read_png("./data/png/rust_crab.png").unwrap();
+        let sizes: Vec<usize> = [
+            FilterStrategy::Fixed(FilterType::None),
+            FilterStrategy::MinSumAbs,
+        ]
+        .iter()
+        .map(|&strategy| {
+            let mut png = read_png("./data/png/rust_crab.png").unwrap();
+            let options = LsbOptions {
+                filter_strategy: Some(strategy),
+                ..LsbOptions::default()
+            };
+            png.embed_lsb(b"filtered", &options).unwrap();
+            assert_eq!(png.extract_lsb(&options).unwrap(), b"filtered");
+            png.image_data().len()
+        })
+        .collect();
+        assert_ne!(sizes[0], sizes[1]);
+        assert_ne!(sizes[0], original.image_data().len());
+    }
+
+    #[test]
+    fn test_too_large() {
+        let mut png = read_png("./data/png/rust_crab.png").unwrap();
+        let capacity = lsb_capacity(&png.ihdr().unwrap(), 1);
+        let options = LsbOptions::default();
+        assert!(matches!(
+            png.embed_lsb(&payload(capacity + 1), &options),
+            Err(PngError::InvalidPayload(_))
+        ));
+        png.embed_lsb(&payload(capacity), &options).unwrap();
+        assert_eq!(png.extract_lsb(&options).unwrap(), payload(capacity));
+    }
+
+    fn palette_png() -> Png {
+        let data: Vec<u8> = (0..64).map(|i| (i % 3) as u8).collect();
+        return Encoder::new(8, 8, ColorType::PLT, 8, data)
+            .with_palette(vec![[255, 0, 0], [0, 255, 0], [0, 0, 255]])
+            .encode()
+            .unwrap();
+    }
+
+    // Every pixel as the RGB colour it shows
+    fn colours(png: &Png) -> Vec<[u8; 3]> {
+        let palette = png.get::<Chunk_PLTE>().unwrap().unwrap().entries;
+        let image = png.decode_pixels().unwrap();
+        image.data.iter().map(|&i| palette[i as usize]).collect()
+    }
+
+    #[test]
+    fn test_palette_refused() {
+        let mut png = palette_png();
+        assert!(matches!(
+            png.embed_lsb(b"x", &LsbOptions::default()),
+            Err(PngError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_palette_safe() {
+        let mut png = palette_png();
+        png.append_chunk(
+            Chunk_bKGD {
+                color: Background::PaletteIndex(2),
+            }
+            .encode(),
+        );
+        let before = colours(&png);
+        let options = LsbOptions {
+            bits_per_channel: 1,
+            palette_safe: true,
+            ..LsbOptions::default()
+        };
+        png.embed_lsb(b"ok", &options).unwrap();
+        assert_eq!(png.extract_lsb(&options).unwrap(), b"ok");
+        assert_eq!(colours(&png), before);
+        assert_eq!(png.get::<Chunk_PLTE>().unwrap().unwrap().entries.len(), 6);
+        assert_eq!(
+            png.metadata().background().unwrap(),
+            Some(Background::PaletteIndex(4))
+        );
+    }
+
+    #[test]
+    fn test_bad_bit_counts() {
+        let mut png = read_png("./data/png/rust_crab.png").unwrap();
+        for &bits in [0u8, 9].iter() {
+            let options = LsbOptions {
+                bits_per_channel: bits,
+                palette_safe: false,
+                ..LsbOptions::default()
+            };
+            assert!(matches!(
+                png.embed_lsb(b"x", &options),
+                Err(PngError::Unsupported(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,679 @@
+use std::io::{Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::{
+    chunk::Chunk,
+    chunk_spec::{check_type, ChunkSpec, CompressionMethod},
+    error::invalid_field,
+    png::Png,
+    Result,
+};
+
+/*
+ * Textual metadata chunks. Keywords, and the text of tEXt and zTXt, are
+ * Latin-1 (ISO 8859-1): every byte is the code point of the same value
+ */
+
+pub const MAX_KEYWORD_LENGTH: usize = 79;
+
+// The most a zTXt, iTXt or iCCP stream may inflate to, so a few bytes of
+// crafted zlib cannot exhaust memory
+pub const MAX_INFLATED_LENGTH: usize = 16 * 1024 * 1024;
+
+pub(crate) fn latin1_decode(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+// None if `s` holds a character outside Latin-1
+pub(crate) fn latin1_encode(s: &str) -> Option<Vec<u8>> {
+    s.chars()
+        .map(|c| {
+            if (c as u32) <= 0xff {
+                Some(c as u8)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// Check the keyword rules shared by all text chunks: 1 to 79 printable
+// Latin-1 characters, with no leading, trailing or consecutive spaces
+pub(crate) fn check_keyword(chunk: &'static str, keyword: &str) -> Result<()> {
+    let bad = |reason: String| invalid_field(chunk, "keyword", reason);
+    let bytes = match latin1_encode(keyword) {
+        Some(bytes) => bytes,
+        None => return Err(bad(format!("{:?} is not Latin-1", keyword))),
+    };
+    if bytes.is_empty() || bytes.len() > MAX_KEYWORD_LENGTH {
+        return Err(bad(format!(
+            "length {} is outside 1..={}",
+            bytes.len(),
+            MAX_KEYWORD_LENGTH
+        )));
+    }
+    if let Some(b) = bytes
+        .iter()
+        .find(|&&b| !((32..=126).contains(&b) || b >= 161))
+    {
+        return Err(bad(format!(
+            "{:?} holds the non-printable byte {}",
+            keyword, b
+        )));
+    }
+    if bytes[0] == b' ' || bytes[bytes.len() - 1] == b' ' {
+        return Err(bad(format!(
+            "{:?} has a leading or trailing space",
+            keyword
+        )));
+    }
+    if keyword.contains("  ") {
+        return Err(bad(format!("{:?} has consecutive spaces", keyword)));
+    }
+    return Ok(());
+}
+
+// Split chunk data at the null byte ending the keyword
+pub(crate) fn split_keyword<'a>(chunk: &'static str, data: &'a [u8]) -> Result<(String, &'a [u8])> {
+    let nul = match data.iter().position(|&b| b == 0) {
+        Some(i) => i,
+        None => {
+            return Err(invalid_field(
+                chunk,
+                "keyword",
+                "missing null separator".to_string(),
+            ))
+        }
+    };
+    let keyword = latin1_decode(&data[..nul]);
+    check_keyword(chunk, &keyword)?;
+    return Ok((keyword, &data[nul + 1..]));
+}
+
+// Latin-1 text, which may span lines but may not hold a null byte
+fn check_text(chunk: &'static str, text: &str) -> Result<Vec<u8>> {
+    let bad = |reason: String| invalid_field(chunk, "text", reason);
+    let bytes = match latin1_encode(text) {
+        Some(bytes) => bytes,
+        None => return Err(bad("text is not Latin-1".to_string())),
+    };
+    if bytes.contains(&0) {
+        return Err(bad("text holds a null byte".to_string()));
+    }
+    return Ok(bytes);
+}
+
+pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+    let mut e = ZlibEncoder::new(Vec::new(), Compression::best());
+    e.write_all(data).unwrap();
+    return e.finish().unwrap();
+}
+
+pub(crate) fn decompress(chunk: &'static str, field: &'static str, data: &[u8]) -> Result<Vec<u8>> {
+    let mut out: Vec<u8> = Vec::new();
+    ZlibDecoder::new(data)
+        .take(MAX_INFLATED_LENGTH as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|e| invalid_field(chunk, field, format!("zlib stream is corrupt: {}", e)))?;
+    if out.len() > MAX_INFLATED_LENGTH {
+        return Err(invalid_field(
+            chunk,
+            field,
+            format!("inflates to more than {} bytes", MAX_INFLATED_LENGTH),
+        ));
+    }
+    return Ok(out);
+}
+
+// An uncompressed tEXt chunk
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct TextChunk {
+    keyword: String,
+    text: String,
+}
+
+impl TextChunk {
+    pub fn new(keyword: &str, text: &str) -> Result<TextChunk> {
+        check_keyword("tEXt", keyword)?;
+        check_text("tEXt", text)?;
+        return Ok(TextChunk {
+            keyword: keyword.to_string(),
+            text: text.to_string(),
+        });
+    }
+
+    pub fn keyword(&self) -> &str {
+        &self.keyword
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl ChunkSpec for TextChunk {
+    const HEADER: [u8; 4] = [116, 69, 88, 116];
+
+    fn decode(chunk: &Chunk) -> Result<TextChunk> {
+        check_type::<TextChunk>(chunk, "tEXt")?;
+        let (keyword, rest) = split_keyword("tEXt", chunk.data())?;
+        if rest.contains(&0) {
+            return Err(invalid_field(
+                "tEXt",
+                "text",
+                "text holds a null byte".to_string(),
+            ));
+        }
+        return Ok(TextChunk {
+            keyword,
+            text: latin1_decode(rest),
+        });
+    }
+
+    fn encode(&self) -> Chunk {
+        // Both fields were checked to be Latin-1 on construction
+        let mut data = latin1_encode(&self.keyword).unwrap();
+        data.push(0);
+        data.extend(latin1_encode(&self.text).unwrap());
+        return Chunk::new(TextChunk::chunk_type(), data);
+    }
+}
+
+// A zTXt chunk: the same content as tEXt, with the text deflated
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct CompressedText {
+    keyword: String,
+    text: String,
+}
+
+impl CompressedText {
+    pub fn new(keyword: &str, text: &str) -> Result<CompressedText> {
+        check_keyword("zTXt", keyword)?;
+        check_text("zTXt", text)?;
+        return Ok(CompressedText {
+            keyword: keyword.to_string(),
+            text: text.to_string(),
+        });
+    }
+
+    pub fn keyword(&self) -> &str {
+        &self.keyword
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl ChunkSpec for CompressedText {
+    const HEADER: [u8; 4] = [122, 84, 88, 116];
+
+    fn decode(chunk: &Chunk) -> Result<CompressedText> {
+        check_type::<CompressedText>(chunk, "zTXt")?;
+        let (keyword, rest) = split_keyword("zTXt", chunk.data())?;
+        match rest.first() {
+            Some(&m) if m == CompressionMethod::DeflateInflate as u8 => {}
+            Some(&m) => {
+                return Err(invalid_field(
+                    "zTXt",
+                    "compression_method",
+                    format!("{} is not 0", m),
+                ))
+            }
+            None => {
+                return Err(invalid_field(
+                    "zTXt",
+                    "compression_method",
+                    "missing".to_string(),
+                ))
+            }
+        }
+        let text = decompress("zTXt", "text", &rest[1..])?;
+        if text.contains(&0) {
+            return Err(invalid_field(
+                "zTXt",
+                "text",
+                "text holds a null byte".to_string(),
+            ));
+        }
+        return Ok(CompressedText {
+            keyword,
+            text: latin1_decode(&text),
+        });
+    }
+
+    fn encode(&self) -> Chunk {
+        let mut data = latin1_encode(&self.keyword).unwrap();
+        data.push(0);
+        data.push(CompressionMethod::DeflateInflate as u8);
+        data.extend(compress(&latin1_encode(&self.text).unwrap()));
+        return Chunk::new(CompressedText::chunk_type(), data);
+    }
+}
+
+// The language tag of an iTXt chunk: empty, or hyphen-separated ASCII words
+fn check_language_tag(tag: &str) -> Result<()> {
+    if !tag.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-') {
+        return Err(invalid_field(
+            "iTXt",
+            "language_tag",
+            format!("{:?} is not an ASCII language tag", tag),
+        ));
+    }
+    return Ok(());
+}
+
+fn check_utf8_field(field: &'static str, value: &str) -> Result<()> {
+    if value.contains('\0') {
+        return Err(invalid_field(
+            "iTXt",
+            field,
+            format!("{} holds a null byte", field),
+        ));
+    }
+    return Ok(());
+}
+
+// Take the null terminated UTF-8 field at the start of `data`
+fn split_utf8<'a>(field: &'static str, data: &'a [u8]) -> Result<(String, &'a [u8])> {
+    let nul = match data.iter().position(|&b| b == 0) {
+        Some(i) => i,
+        None => {
+            return Err(invalid_field(
+                "iTXt",
+                field,
+                "missing null separator".to_string(),
+            ))
+        }
+    };
+    let value = String::from_utf8(data[..nul].to_vec())
+        .map_err(|e| invalid_field("iTXt", field, format!("not UTF-8: {}", e)))?;
+    return Ok((value, &data[nul + 1..]));
+}
+
+// An iTXt chunk: UTF-8 text with an optional language and translated keyword,
+// deflated when `compressed` is set
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct InternationalText {
+    keyword: String,
+    compressed: bool,
+    language_tag: String,
+    translated_keyword: String,
+    text: String,
+}
+
+impl InternationalText {
+    pub fn new(keyword: &str, text: &str) -> Result<InternationalText> {
+        check_keyword("iTXt", keyword)?;
+        check_utf8_field("text", text)?;
+        return Ok(InternationalText {
+            keyword: keyword.to_string(),
+            compressed: false,
+            language_tag: String::new(),
+            translated_keyword: String::new(),
+            text: text.to_string(),
+        });
+    }
+
+    pub fn with_language(
+        mut self,
+        language_tag: &str,
+        translated_keyword: &str,
+    ) -> Result<InternationalText> {
+        check_language_tag(language_tag)?;
+        check_utf8_field("translated_keyword", translated_keyword)?;
+        self.language_tag = language_tag.to_string();
+        self.translated_keyword = translated_keyword.to_string();
+        return Ok(self);
+    }
+
+    pub fn with_compression(mut self, compressed: bool) -> InternationalText {
+        self.compressed = compressed;
+        return self;
+    }
+
+    pub fn keyword(&self) -> &str {
+        &self.keyword
+    }
+
+    pub fn compressed(&self) -> bool {
+        self.compressed
+    }
+
+    pub fn language_tag(&self) -> &str {
+        &self.language_tag
+    }
+
+    pub fn translated_keyword(&self) -> &str {
+        &self.translated_keyword
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl ChunkSpec for InternationalText {
+    const HEADER: [u8; 4] = [105, 84, 88, 116];
+
+    fn decode(chunk: &Chunk) -> Result<InternationalText> {
+        check_type::<InternationalText>(chunk, "iTXt")?;
+        let (keyword, rest) = split_keyword("iTXt", chunk.data())?;
+        if rest.len() < 2 {
+            return Err(invalid_field(
+                "iTXt",
+                "compression_flag",
+                "missing".to_string(),
+            ));
+        }
+        let compressed = match rest[0] {
+            0 => false,
+            1 => true,
+            f => {
+                return Err(invalid_field(
+                    "iTXt",
+                    "compression_flag",
+                    format!("{} is not 0 or 1", f),
+                ))
+            }
+        };
+        if compressed && rest[1] != CompressionMethod::DeflateInflate as u8 {
+            return Err(invalid_field(
+                "iTXt",
+                "compression_method",
+                format!("{} is not 0", rest[1]),
+            ));
+        }
+        let (language_tag, rest) = split_utf8("language_tag", &rest[2..])?;
+        check_language_tag(&language_tag)?;
+        let (translated_keyword, rest) = split_utf8("translated_keyword", rest)?;
+        let text = if compressed {
+            decompress("iTXt", "text", rest)?
+        } else {
+            rest.to_vec()
+        };
+        let text = String::from_utf8(text)
+            .map_err(|e| invalid_field("iTXt", "text", format!("not UTF-8: {}", e)))?;
+        return Ok(InternationalText {
+            keyword,
+            compressed,
+            language_tag,
+            translated_keyword,
+            text,
+        });
+    }
+
+    fn encode(&self) -> Chunk {
+        let mut data = latin1_encode(&self.keyword).unwrap();
+        data.push(0);
+        data.push(self.compressed as u8);
+        data.push(CompressionMethod::DeflateInflate as u8);
+        data.extend(self.language_tag.as_bytes());
+        data.push(0);
+        data.extend(self.translated_keyword.as_bytes());
+        data.push(0);
+        if self.compressed {
+            data.extend(compress(self.text.as_bytes()));
+        } else {
+            data.extend(self.text.as_bytes());
+        }
+        return Chunk::new(InternationalText::chunk_type(), data);
+    }
+}
+
+// Store `text` under `keyword` in the smallest text chunk able to hold it:
+// tEXt or zTXt for Latin-1 text, otherwise iTXt, compressed if that helps
+pub fn text_chunk(keyword: &str, text: &str) -> Result<Chunk> {
+    let candidates = if latin1_encode(text).is_some() {
+        vec![
+            TextChunk::new(keyword, text)?.encode(),
+            CompressedText::new(keyword, text)?.encode(),
+        ]
+    } else {
+        let itxt = InternationalText::new(keyword, text)?;
+        vec![itxt.clone().encode(), itxt.with_compression(true).encode()]
+    };
+    return Ok(candidates.into_iter().min_by_key(|c| c.length()).unwrap());
+}
+
+// The keyword and text of a tEXt, zTXt or iTXt chunk; None for other chunks
+fn text_entry(chunk: &Chunk) -> Option<Result<(String, String)>> {
+    let header = chunk.chunk_type().bytes();
+    let entry = if header == TextChunk::HEADER {
+        TextChunk::decode(chunk).map(|t| (t.keyword, t.text))
+    } else if header == CompressedText::HEADER {
+        CompressedText::decode(chunk).map(|t| (t.keyword, t.text))
+    } else if header == InternationalText::HEADER {
+        InternationalText::decode(chunk).map(|t| (t.keyword, t.text))
+    } else {
+        return None;
+    };
+    return Some(entry);
+}
+
+impl Png {
+    // Every text chunk as a keyword and its text, in file order, whichever of
+    // tEXt, zTXt or iTXt holds it
+    pub fn text_entries(&self) -> impl Iterator<Item = Result<(String, String)>> + '_ {
+        self.chunks().iter().filter_map(text_entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunk_type::ChunkType, PngError};
+    use std::str::FromStr;
+
+    fn raw_text(data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str("tEXt").unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let text = TextChunk::new("Comment", "caf\u{e9}\nsecond line").unwrap();
+        let chunk = text.encode();
+        assert_eq!(chunk.chunk_type().to_string(), "tEXt");
+        assert_eq!(chunk.data(), b"Comment\0caf\xe9\nsecond line");
+        assert_eq!(TextChunk::decode(&chunk).unwrap(), text);
+    }
+
+    #[test]
+    fn test_decode_latin1() {
+        // 0xff is not valid UTF-8 on its own but is a Latin-1 character
+        let chunk = raw_text(b"Author\0\xff\xe9t\xe9");
+        let text = TextChunk::decode(&chunk).unwrap();
+        assert_eq!(text.keyword(), "Author");
+        assert_eq!(text.text(), "\u{ff}\u{e9}t\u{e9}");
+
+        let chunk = raw_text(b"Title\0");
+        assert_eq!(TextChunk::decode(&chunk).unwrap().text(), "");
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let text = CompressedText::new("Description", &"all work and no play ".repeat(20)).unwrap();
+        let chunk = text.encode();
+        assert_eq!(chunk.chunk_type().to_string(), "zTXt");
+        assert!(chunk.data().starts_with(b"Description\0\0"));
+        assert!((chunk.length() as usize) < 12 + 2 + text.text().len());
+        assert_eq!(CompressedText::decode(&chunk).unwrap(), text);
+    }
+
+    #[test]
+    fn test_bad_compressed_text() {
+        let ztxt = |data: &[u8]| Chunk::new(ChunkType::from_str("zTXt").unwrap(), data.to_vec());
+        let mut data = b"Comment\0\x01".to_vec();
+        data.extend(compress(b"text"));
+        assert!(matches!(
+            CompressedText::decode(&ztxt(&data)),
+            Err(PngError::InvalidField {
+                field: "compression_method",
+                ..
+            })
+        ));
+        assert!(CompressedText::decode(&ztxt(b"Comment\0")).is_err());
+        assert!(matches!(
+            CompressedText::decode(&ztxt(b"Comment\0\0not zlib")),
+            Err(PngError::InvalidField { field: "text", .. })
+        ));
+    }
+
+    #[test]
+    fn test_zlib_bomb() {
+        let bomb = compress(&vec![b'a'; MAX_INFLATED_LENGTH + 1]);
+        assert!(bomb.len() < 64 * 1024);
+        match decompress("zTXt", "text", &bomb) {
+            Err(PngError::InvalidField { reason, .. }) => {
+                assert!(reason.starts_with("inflates to more than"))
+            }
+            r => panic!("unexpected {:?}", r.map(|v| v.len())),
+        }
+        let fits = compress(&vec![b'a'; MAX_INFLATED_LENGTH]);
+        assert_eq!(
+            decompress("zTXt", "text", &fits).unwrap().len(),
+            MAX_INFLATED_LENGTH
+        );
+    }
+
+    #[test]
+    fn test_text_chunk_picks_smaller() {
+        assert_eq!(
+            text_chunk("Comment", "short")
+                .unwrap()
+                .chunk_type()
+                .to_string(),
+            "tEXt"
+        );
+        let long = "la ".repeat(100);
+        let chunk = text_chunk("Comment", &long).unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "zTXt");
+        assert_eq!(CompressedText::decode(&chunk).unwrap().text(), long);
+    }
+
+    #[test]
+    fn test_international_round_trip() {
+        let text = InternationalText::new("Title", "\u{65e5}\u{672c}\u{8a9e}\u{306e}\u{984c}")
+            .unwrap()
+            .with_language("ja-JP", "\u{30bf}\u{30a4}\u{30c8}\u{30eb}")
+            .unwrap();
+        let chunk = text.encode();
+        assert_eq!(chunk.chunk_type().to_string(), "iTXt");
+        assert!(chunk.data().starts_with(b"Title\0\0\0ja-JP\0"));
+        assert_eq!(InternationalText::decode(&chunk).unwrap(), text);
+
+        let text = InternationalText::new("Comment", &"\u{3b1}\u{3b2}\u{3b3} ".repeat(30))
+            .unwrap()
+            .with_compression(true);
+        let chunk = text.encode();
+        assert!((chunk.length() as usize) < text.text().len());
+        let decoded = InternationalText::decode(&chunk).unwrap();
+        assert!(decoded.compressed());
+        assert_eq!(decoded.language_tag(), "");
+        assert_eq!(decoded, text);
+    }
+
+    #[test]
+    fn test_bad_international_text() {
+        let itxt = |data: &[u8]| Chunk::new(ChunkType::from_str("iTXt").unwrap(), data.to_vec());
+        assert!(matches!(
+            InternationalText::decode(&itxt(b"Title\0\x02\0\0\0text")),
+            Err(PngError::InvalidField {
+                field: "compression_flag",
+                ..
+            })
+        ));
+        assert!(matches!(
+            InternationalText::decode(&itxt(b"Title\0\0\0en\0\0\xff")),
+            Err(PngError::InvalidField { field: "text", .. })
+        ));
+        assert!(matches!(
+            InternationalText::decode(&itxt(b"Title\0\0\0en_GB\0\0x")),
+            Err(PngError::InvalidField {
+                field: "language_tag",
+                ..
+            })
+        ));
+        assert!(InternationalText::decode(&itxt(b"Title\0\0\0en")).is_err());
+        assert!(InternationalText::new("Title", "a\0b").is_err());
+        assert!(InternationalText::new("Title", "x")
+            .unwrap()
+            .with_language("en GB", "")
+            .is_err());
+    }
+
+    #[test]
+    fn test_text_chunk_falls_back_to_itxt() {
+        let chunk = text_chunk("Comment", "\u{43f}\u{440}\u{438}\u{432}\u{435}\u{442}").unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "iTXt");
+        let long = "\u{43f}\u{440}\u{438}\u{432}\u{435}\u{442} ".repeat(50);
+        let chunk = text_chunk("Comment", &long).unwrap();
+        let decoded = InternationalText::decode(&chunk).unwrap();
+        assert!(decoded.compressed());
+        assert_eq!(decoded.text(), long);
+    }
+
+    #[test]
+    fn test_text_entries() {
+        let mut png = crate::png_util::read_png("./data/png/rust_crab.png").unwrap();
+        png.append_chunk(TextChunk::new("Title", "Crab").unwrap().encode());
+        png.append_chunk(CompressedText::new("Comment", "zipped").unwrap().encode());
+        png.append_chunk(
+            InternationalText::new("Author", "\u{3b1}")
+                .unwrap()
+                .encode(),
+        );
+        let entries: Vec<(String, String)> = png.text_entries().map(|e| e.unwrap()).collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("Title".to_string(), "Crab".to_string()),
+                ("Comment".to_string(), "zipped".to_string()),
+                ("Author".to_string(), "\u{3b1}".to_string()),
+            ]
+        );
+
+        png.append_chunk(raw_text(b"Broken"));
+        assert!(png.text_entries().last().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_bad_keywords() {
+        let long = "k".repeat(80);
+        for keyword in [
+            "",
+            long.as_str(),
+            " Title",
+            "Title ",
+            "Two  spaces",
+            "Tab\there",
+            "\u{3b1}",
+        ]
+        .iter()
+        {
+            assert!(
+                matches!(
+                    TextChunk::new(keyword, "x"),
+                    Err(PngError::InvalidField {
+                        field: "keyword",
+                        ..
+                    })
+                ),
+                "{:?}",
+                keyword
+            );
+        }
+        assert!(TextChunk::new(&"k".repeat(79), "x").is_ok());
+        assert!(TextChunk::new("Two spaces", "x").is_ok());
+    }
+
+    #[test]
+    fn test_bad_text() {
+        assert!(TextChunk::new("Comment", "\u{3b1}").is_err());
+        assert!(TextChunk::new("Comment", "a\0b").is_err());
+        assert!(TextChunk::decode(&raw_text(b"Comment\0a\0b")).is_err());
+        assert!(TextChunk::decode(&raw_text(b"no separator")).is_err());
+        assert!(TextChunk::decode(&raw_text(b" Comment\0x")).is_err());
+    }
+}
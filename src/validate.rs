@@ -0,0 +1,515 @@
+use std::fmt::{self, Display};
+
+use crate::{
+    apng::sequence_errors,
+    chunk_spec::{
+        ChunkSpec, Chunk_IDAT, Chunk_IEND, Chunk_IHDR, Chunk_PLTE, Chunk_hIST, Chunk_iCCP,
+        Chunk_sBIT, Chunk_sRGB, Chunk_tRNS, ColorType,
+    },
+    png::Png,
+    PngError,
+};
+
+/*
+ * Checks a PNG against the chunk ordering rules of the spec, collecting every
+ * violation rather than stopping at the first
+ */
+
+#[derive(Eq, PartialEq, Ord, PartialOrd, Debug, Clone, Copy)]
+pub enum Severity {
+    // Readers will usually cope, but the file is not conformant
+    Warning,
+    // Readers are expected to reject the file
+    Error,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // Index into `Png::chunks` of the offending chunk, if there is one
+    pub chunk: Option<usize>,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match self.chunk {
+            Some(i) => write!(f, "{} (chunk {}): {}", level, i, self.message),
+            None => write!(f, "{}: {}", level, self.message),
+        }
+    }
+}
+
+// Chunks the spec allows at most once per file
+const ONCE_ONLY: [&[u8; 4]; 18] = [
+    b"IHDR", b"PLTE", b"IEND", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"cICP", b"mDCV",
+    b"cLLI", b"bKGD", b"hIST", b"tRNS", b"pHYs", b"tIME", b"eXIf", b"acTL",
+];
+// Ancillary chunks that must come before both PLTE and IDAT
+const BEFORE_PLTE: [&[u8; 4]; 8] = [
+    b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"cICP", b"mDCV", b"cLLI",
+];
+// Ancillary chunks that must come after PLTE but before IDAT
+const AFTER_PLTE: [&[u8; 4]; 3] = [b"bKGD", b"hIST", b"tRNS"];
+// Ancillary chunks that must come before IDAT
+const BEFORE_IDAT: [&[u8; 4]; 4] = [b"pHYs", b"sPLT", b"eXIf", b"acTL"];
+// Critical chunks defined by the spec
+const KNOWN_CRITICAL: [&[u8; 4]; 4] = [b"IHDR", b"PLTE", b"IDAT", b"IEND"];
+
+struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    fn push(&mut self, severity: Severity, chunk: Option<usize>, message: String) {
+        self.0.push(Diagnostic {
+            severity,
+            chunk,
+            message,
+        });
+    }
+}
+
+impl Png {
+    // Report every way the chunk layout breaks the PNG spec; empty if none
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut out = Diagnostics(Vec::new());
+        let types: Vec<[u8; 4]> = self
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().bytes())
+            .collect();
+        let name = |i: usize| self.chunks()[i].chunk_type().to_string();
+        let first = |t: [u8; 4]| types.iter().position(|&c| c == t);
+
+        check_ends(&types, &mut out);
+
+        // Repeats of once-only chunks
+        for t in ONCE_ONLY.iter() {
+            let found: Vec<usize> = (0..types.len()).filter(|&i| &types[i] == *t).collect();
+            for &i in found.iter().skip(1) {
+                let severity = if is_critical(t) {
+                    Severity::Error
+                } else {
+                    Severity::Warning
+                };
+                out.push(
+                    severity,
+                    Some(i),
+                    format!(
+                        "{} may appear only once, first seen at chunk {}",
+                        name(i),
+                        found[0]
+                    ),
+                );
+            }
+        }
+
+        // IDAT: present, and in one unbroken run
+        let idats: Vec<usize> = (0..types.len())
+            .filter(|&i| types[i] == Chunk_IDAT::HEADER)
+            .collect();
+        if idats.is_empty() {
+            out.push(Severity::Error, None, String::from("no IDAT chunk"));
+        }
+        for w in idats.windows(2) {
+            if w[1] != w[0] + 1 {
+                out.push(
+                    Severity::Error,
+                    Some(w[1]),
+                    format!(
+                        "IDAT chunks are not consecutive (broken by chunk {})",
+                        w[0] + 1
+                    ),
+                );
+            }
+        }
+        let first_idat = idats.first().copied();
+        let plte = first(Chunk_PLTE::HEADER);
+
+        // PLTE: before IDAT, and present exactly when the colour type needs it
+        if let (Some(p), Some(d)) = (plte, first_idat) {
+            if p > d {
+                out.push(
+                    Severity::Error,
+                    Some(p),
+                    String::from("PLTE must precede IDAT"),
+                );
+            }
+        }
+        match self.ihdr() {
+            Ok(ihdr) => match (ihdr.color_type, plte) {
+                (ColorType::PLT, None) => out.push(
+                    Severity::Error,
+                    None,
+                    String::from("colour type 3 requires a PLTE chunk"),
+                ),
+                (ColorType::GRY, Some(p)) | (ColorType::GRYA, Some(p)) => out.push(
+                    Severity::Error,
+                    Some(p),
+                    String::from("PLTE must not appear in a greyscale image"),
+                ),
+                _ => {}
+            },
+            Err(PngError::ChunkNotFound(_)) => {}
+            Err(e) => out.push(Severity::Error, first(Chunk_IHDR::HEADER), e.to_string()),
+        }
+
+        // PLTE, tRNS, hIST and sBIT contents; a greyscale PLTE is reported above
+        if let Ok(ihdr) = self.ihdr() {
+            let chunk = |i: usize| &self.chunks()[i];
+            let mut palette: Option<Chunk_PLTE> = None;
+            if let Some(p) = plte.filter(|_| ihdr.color_type.color_used()) {
+                match Chunk_PLTE::decode(chunk(p)).and_then(|pal| pal.check(&ihdr).map(|_| pal)) {
+                    Ok(pal) => palette = Some(pal),
+                    Err(e) => out.push(Severity::Error, Some(p), e.to_string()),
+                }
+            }
+            if let Some(t) = first(Chunk_tRNS::HEADER) {
+                if let Err(e) = Chunk_tRNS::decode(chunk(t), &ihdr, palette.as_ref()) {
+                    out.push(Severity::Error, Some(t), e.to_string());
+                }
+            }
+            if let (Some(h), Some(pal)) = (first(Chunk_hIST::HEADER), &palette) {
+                if let Err(e) = Chunk_hIST::decode(chunk(h)).and_then(|hist| hist.check(pal)) {
+                    out.push(Severity::Error, Some(h), e.to_string());
+                }
+            }
+            if let Some(b) = first(Chunk_sBIT::HEADER) {
+                if let Err(e) = Chunk_sBIT::decode(chunk(b)).and_then(|sbit| sbit.check(&ihdr)) {
+                    out.push(Severity::Error, Some(b), e.to_string());
+                }
+            }
+        }
+
+        // Ancillary chunk placement
+        let after = |i: usize, other: Option<usize>| other.is_some_and(|o| i > o);
+        for (i, t) in types.iter().enumerate() {
+            if BEFORE_PLTE.contains(&t) && (after(i, plte) || after(i, first_idat)) {
+                out.push(
+                    Severity::Warning,
+                    Some(i),
+                    format!("{} must precede PLTE and IDAT", name(i)),
+                );
+            }
+            if AFTER_PLTE.contains(&t) {
+                if after(i, first_idat) {
+                    out.push(
+                        Severity::Warning,
+                        Some(i),
+                        format!("{} must precede IDAT", name(i)),
+                    );
+                }
+                if plte.is_some_and(|p| i < p) {
+                    out.push(
+                        Severity::Warning,
+                        Some(i),
+                        format!("{} must follow PLTE", name(i)),
+                    );
+                }
+                if t == b"hIST" && plte.is_none() {
+                    out.push(
+                        Severity::Error,
+                        Some(i),
+                        String::from("hIST requires a PLTE chunk"),
+                    );
+                }
+            }
+            if BEFORE_IDAT.contains(&t) && after(i, first_idat) {
+                out.push(
+                    Severity::Warning,
+                    Some(i),
+                    format!("{} must precede IDAT", name(i)),
+                );
+            }
+            if is_critical(t) && !KNOWN_CRITICAL.contains(&t) {
+                out.push(
+                    Severity::Error,
+                    Some(i),
+                    format!("unknown critical chunk {}", name(i)),
+                );
+            }
+        }
+
+        // An embedded profile and the sRGB profile cannot both apply
+        if let (Some(s), Some(i)) = (first(Chunk_sRGB::HEADER), first(Chunk_iCCP::HEADER)) {
+            out.push(
+                Severity::Warning,
+                Some(s.max(i)),
+                String::from("sRGB and iCCP must not both be present"),
+            );
+        }
+
+        // APNG frame sequence
+        for e in sequence_errors(self) {
+            out.push(
+                Severity::Error,
+                Some(e.chunk),
+                format!("{} {}: {}", name(e.chunk), e.field, e.reason),
+            );
+        }
+
+        out.0.sort_by_key(|d| d.chunk);
+        return out.0;
+    }
+}
+
+// IHDR must open the file and IEND must close it
+fn check_ends(types: &[[u8; 4]], out: &mut Diagnostics) {
+    if types.first() != Some(&Chunk_IHDR::HEADER) {
+        // With no chunks at all there is no first chunk to point at
+        let first = if types.is_empty() { None } else { Some(0) };
+        out.push(
+            Severity::Error,
+            first,
+            String::from("first chunk must be IHDR"),
+        );
+    }
+    match types.iter().position(|&t| t == Chunk_IEND::HEADER) {
+        None => out.push(Severity::Error, None, String::from("no IEND chunk")),
+        Some(end) if end + 1 != types.len() => out.push(
+            Severity::Error,
+            Some(end + 1),
+            format!("{} chunks follow IEND", types.len() - end - 1),
+        ),
+        Some(_) => {}
+    }
+}
+
+fn is_critical(t: &[u8; 4]) -> bool {
+    (t[0] >> 5) & 0b1 == 0b0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunk::Chunk,
+        chunk_spec::{CompressionMethod, FilterMethod, InterlaceMethod, SignificantBits},
+        chunk_type::ChunkType,
+        png_util::read_png,
+    };
+    use std::str::FromStr;
+
+    fn ihdr(color_type: ColorType) -> Chunk {
+        Chunk_IHDR {
+            width: 1,
+            height: 1,
+            bit_depth: 8,
+            color_type,
+            compression_method: CompressionMethod::DeflateInflate,
+            filter_method: FilterMethod::Adaptive,
+            interlace_method: InterlaceMethod::None,
+        }
+        .encode()
+    }
+
+    fn chunk(chunk_type: &str) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), vec![0; 3])
+    }
+
+    fn sized(chunk_type: &str, len: usize) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), vec![0; len])
+    }
+
+    fn png(chunks: Vec<Chunk>) -> Png {
+        Png::from_chunks(chunks)
+    }
+
+    fn messages(png: &Png) -> Vec<(Severity, Option<usize>)> {
+        png.validate()
+            .iter()
+            .map(|d| (d.severity, d.chunk))
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_files() {
+        let png = read_png("./data/png/rust_crab.png").unwrap();
+        assert!(png.validate().is_empty());
+        let png = read_png("./data/png/transparent.png").unwrap();
+        assert!(png.validate().is_empty());
+
+        let png = png_with(vec![ihdr(ColorType::PLT), chunk("PLTE"), sized("tRNS", 1)]);
+        assert!(png.validate().is_empty());
+    }
+
+    fn png_with(head: Vec<Chunk>) -> Png {
+        let mut chunks = head;
+        chunks.push(chunk("IDAT"));
+        chunks.push(chunk("tEXt"));
+        chunks.push(Chunk_IEND {}.encode());
+        png(chunks)
+    }
+
+    #[test]
+    fn test_missing_ihdr_and_iend() {
+        let png = png(vec![chunk("IDAT")]);
+        assert_eq!(
+            messages(&png),
+            vec![(Severity::Error, None), (Severity::Error, Some(0))]
+        );
+
+        let empty = Png::from_chunks(vec![]);
+        assert!(!messages(&empty).is_empty());
+        assert!(messages(&empty).iter().all(|&(_, chunk)| chunk.is_none()));
+    }
+
+    #[test]
+    fn test_chunks_after_iend() {
+        let mut p = png_with(vec![ihdr(ColorType::RGB)]);
+        p.append_chunk(chunk("ruSt"));
+        assert!(p.validate().is_empty());
+
+        let d = png(vec![
+            ihdr(ColorType::RGB),
+            chunk("IDAT"),
+            Chunk_IEND {}.encode(),
+            chunk("ruSt"),
+            Chunk_IEND {}.encode(),
+        ])
+        .validate();
+        assert!(d
+            .iter()
+            .any(|d| d.chunk == Some(3) && d.message.contains("follow IEND")));
+        assert!(d
+            .iter()
+            .any(|d| d.chunk == Some(4) && d.message.contains("only once")));
+    }
+
+    #[test]
+    fn test_split_idat() {
+        let p = png(vec![
+            ihdr(ColorType::RGB),
+            chunk("IDAT"),
+            chunk("tEXt"),
+            chunk("IDAT"),
+            Chunk_IEND {}.encode(),
+        ]);
+        assert_eq!(messages(&p), vec![(Severity::Error, Some(3))]);
+    }
+
+    #[test]
+    fn test_palette_rules() {
+        let p = png_with(vec![ihdr(ColorType::PLT)]);
+        assert_eq!(messages(&p), vec![(Severity::Error, None)]);
+
+        let p = png_with(vec![ihdr(ColorType::GRY), chunk("PLTE")]);
+        assert_eq!(messages(&p), vec![(Severity::Error, Some(1))]);
+
+        let p = png(vec![
+            ihdr(ColorType::PLT),
+            chunk("IDAT"),
+            chunk("PLTE"),
+            Chunk_IEND {}.encode(),
+        ]);
+        assert_eq!(messages(&p), vec![(Severity::Error, Some(2))]);
+    }
+
+    #[test]
+    fn test_ancillary_placement() {
+        let p = png_with(vec![
+            ihdr(ColorType::PLT),
+            sized("tRNS", 1),
+            chunk("PLTE"),
+            chunk("gAMA"),
+            sized("hIST", 2),
+        ]);
+        assert_eq!(
+            messages(&p),
+            vec![(Severity::Warning, Some(1)), (Severity::Warning, Some(3))]
+        );
+
+        let p = png(vec![
+            ihdr(ColorType::RGB),
+            chunk("IDAT"),
+            chunk("pHYs"),
+            Chunk_IEND {}.encode(),
+        ]);
+        assert_eq!(messages(&p), vec![(Severity::Warning, Some(2))]);
+    }
+
+    #[test]
+    fn test_repeated_ancillary_and_unknown_critical() {
+        let p = png_with(vec![
+            ihdr(ColorType::RGB),
+            chunk("gAMA"),
+            chunk("gAMA"),
+            chunk("RuSt"),
+        ]);
+        assert_eq!(
+            messages(&p),
+            vec![(Severity::Warning, Some(2)), (Severity::Error, Some(3))]
+        );
+    }
+
+    #[test]
+    fn test_malformed_ihdr() {
+        let p = png_with(vec![Chunk::new(Chunk_IHDR::chunk_type(), vec![0; 13])]);
+        let d = p.validate();
+        assert_eq!(d.len(), 1);
+        assert_eq!(d[0].chunk, Some(0));
+        assert!(d[0].message.contains("width"));
+    }
+
+    #[test]
+    fn test_hist_without_palette() {
+        let p = png_with(vec![ihdr(ColorType::RGB), sized("hIST", 2)]);
+        assert_eq!(messages(&p), vec![(Severity::Error, Some(1))]);
+    }
+
+    #[test]
+    fn test_palette_contents() {
+        // 1 bit indices reach only 2 entries
+        let mut one_bit = ihdr(ColorType::PLT).data().to_vec();
+        one_bit[8] = 1;
+        let one_bit = Chunk::new(Chunk_IHDR::chunk_type(), one_bit);
+        let p = png_with(vec![one_bit.clone(), sized("PLTE", 9)]);
+        assert_eq!(messages(&p), vec![(Severity::Error, Some(1))]);
+
+        // More alphas than palette entries, and a histogram of the wrong length
+        let p = png_with(vec![
+            one_bit,
+            sized("PLTE", 6),
+            sized("tRNS", 3),
+            sized("hIST", 2),
+        ]);
+        assert_eq!(
+            messages(&p),
+            vec![(Severity::Error, Some(2)), (Severity::Error, Some(3))]
+        );
+
+        // tRNS is meaningless with an alpha channel
+        let p = png_with(vec![ihdr(ColorType::RGBA), sized("tRNS", 6)]);
+        assert_eq!(messages(&p), vec![(Severity::Error, Some(1))]);
+        let p = png_with(vec![ihdr(ColorType::RGB), sized("tRNS", 6)]);
+        assert!(p.validate().is_empty());
+    }
+
+    #[test]
+    fn test_significant_bits() {
+        let sbit = |bits| Chunk_sBIT { bits }.encode();
+        let p = png_with(vec![
+            ihdr(ColorType::RGB),
+            sbit(SignificantBits::Rgb(5, 6, 5)),
+        ]);
+        assert!(p.validate().is_empty());
+        let p = png_with(vec![ihdr(ColorType::RGB), sbit(SignificantBits::Grey(5))]);
+        assert_eq!(messages(&p), vec![(Severity::Error, Some(1))]);
+        let p = png_with(vec![
+            ihdr(ColorType::RGB),
+            sbit(SignificantBits::Rgb(5, 9, 5)),
+        ]);
+        assert_eq!(messages(&p), vec![(Severity::Error, Some(1))]);
+    }
+
+    #[test]
+    fn test_srgb_with_iccp() {
+        let p = png_with(vec![ihdr(ColorType::RGB), chunk("iCCP"), chunk("sRGB")]);
+        assert_eq!(messages(&p), vec![(Severity::Warning, Some(2))]);
+        let p = png_with(vec![ihdr(ColorType::RGB), chunk("sRGB")]);
+        assert!(p.validate().is_empty());
+    }
+}
@@ -0,0 +1,388 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+use tempfile::TempDir;
+
+const CRAB: &str = "./data/png/rust_crab.png";
+
+fn pngme(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pngme"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+// Run pngme with PNGME_PASSPHRASE set
+fn pngme_with_passphrase(args: &[&str], passphrase: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pngme"))
+        .args(args)
+        .env("PNGME_PASSPHRASE", passphrase)
+        .output()
+        .unwrap()
+}
+
+fn scratch_copy(dir: &TempDir) -> PathBuf {
+    let path = dir.path().join("crab.png");
+    fs::copy(CRAB, &path).unwrap();
+    path
+}
+
+fn path_str(p: &Path) -> &str {
+    p.to_str().unwrap()
+}
+
+#[test]
+fn test_encode_then_decode() {
+    let dir = TempDir::new().unwrap();
+    let file = scratch_copy(&dir);
+
+    let out = pngme(&["encode", path_str(&file), "RuSt", "hidden in the crab"]);
+    assert!(out.status.success());
+
+    let out = pngme(&["decode", path_str(&file), "RuSt"]);
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "hidden in the crab\n"
+    );
+
+    // The message goes in front of IEND, which must stay the last chunk
+    let bytes = fs::read(&file).unwrap();
+    let (head, iend) = bytes.split_at(bytes.len() - 12);
+    assert_eq!(iend, b"\0\0\0\0IEND\xAE\x42\x60\x82");
+    // The chunk before it holds the message, followed by its 4 byte CRC
+    assert_eq!(
+        &head[head.len() - 22..head.len() - 4],
+        b"hidden in the crab"
+    );
+}
+
+#[test]
+fn test_encode_to_output_leaves_input_untouched() {
+    let dir = TempDir::new().unwrap();
+    let file = scratch_copy(&dir);
+    let output = dir.path().join("out.png");
+
+    let out = pngme(&[
+        "encode",
+        path_str(&file),
+        "RuSt",
+        "message",
+        path_str(&output),
+    ]);
+    assert!(out.status.success());
+    assert_eq!(fs::read(&file).unwrap(), fs::read(CRAB).unwrap());
+
+    let out = pngme(&["decode", path_str(&output), "RuSt"]);
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "message\n");
+}
+
+#[test]
+fn test_remove() {
+    let dir = TempDir::new().unwrap();
+    let file = scratch_copy(&dir);
+
+    assert!(pngme(&["encode", path_str(&file), "RuSt", "message"])
+        .status
+        .success());
+    assert!(pngme(&["remove", path_str(&file), "RuSt"]).status.success());
+    assert_eq!(fs::read(&file).unwrap(), fs::read(CRAB).unwrap());
+
+    let out = pngme(&["decode", path_str(&file), "RuSt"]);
+    assert_eq!(out.status.code(), Some(1));
+}
+
+#[test]
+fn test_remove_missing_chunk_fails() {
+    let dir = TempDir::new().unwrap();
+    let file = scratch_copy(&dir);
+
+    let out = pngme(&["remove", path_str(&file), "RuSt"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(!out.stderr.is_empty());
+}
+
+#[test]
+fn test_print() {
+    let out = pngme(&["print", CRAB]);
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("IHDR"));
+    assert!(stdout.contains("IDAT"));
+    assert!(stdout.contains("IEND"));
+}
+
+#[test]
+fn test_invalid_chunk_type_fails() {
+    let dir = TempDir::new().unwrap();
+    let file = scratch_copy(&dir);
+
+    let out = pngme(&["encode", path_str(&file), "Ru1t", "message"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(fs::read(&file).unwrap(), fs::read(CRAB).unwrap());
+}
+
+#[test]
+fn test_missing_file_fails() {
+    let out = pngme(&["print", "./data/png/does_not_exist.png"]);
+    assert_eq!(out.status.code(), Some(1));
+}
+
+#[test]
+fn test_bad_usage_fails() {
+    let out = pngme(&["encode", CRAB]);
+    assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn test_text_messages() {
+    let dir = TempDir::new().unwrap();
+    let file = scratch_copy(&dir);
+    let long = "over and over ".repeat(40);
+
+    let out = pngme(&["encode", path_str(&file), "tEXt", "short note"]);
+    assert!(out.status.success());
+    let out = pngme(&[
+        "encode",
+        path_str(&file),
+        "tEXt",
+        &long,
+        "--keyword",
+        "Description",
+    ]);
+    assert!(out.status.success());
+
+    // The long message was compressed on its own
+    let out = pngme(&["print", path_str(&file)]);
+    let listing = String::from_utf8(out.stdout).unwrap();
+    assert!(listing.contains("tEXt"));
+    assert!(listing.contains("zTXt"));
+
+    let out = pngme(&["decode", path_str(&file), "tEXt"]);
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "short note\n");
+    let out = pngme(&["decode", path_str(&file), "zTXt", "-k", "Description"]);
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        format!("{}\n", long)
+    );
+
+    let out = pngme(&["decode", path_str(&file), "tEXt", "-k", "Title"]);
+    assert_eq!(out.status.code(), Some(1));
+}
+
+#[test]
+fn test_non_latin_text_message() {
+    let dir = TempDir::new().unwrap();
+    let file = scratch_copy(&dir);
+
+    let out = pngme(&[
+        "encode",
+        path_str(&file),
+        "tEXt",
+        "\u{4f60}\u{597d}",
+        "-k",
+        "Title",
+    ]);
+    assert!(out.status.success());
+    let out = pngme(&["decode", path_str(&file), "iTXt", "-k", "Title"]);
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "\u{4f60}\u{597d}\n");
+}
+
+#[test]
+fn test_repair() {
+    let dir = TempDir::new().unwrap();
+    let file = scratch_copy(&dir);
+    let mut bytes = fs::read(&file).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0xff;
+    fs::write(&file, &bytes).unwrap();
+
+    let out = pngme(&["print", path_str(&file)]);
+    assert_eq!(out.status.code(), Some(1));
+
+    let out = pngme(&["repair", "--dry-run", path_str(&file)]);
+    assert!(out.status.success());
+    let listing = String::from_utf8(out.stdout).unwrap();
+    assert!(listing.contains("(IEND): stored CRC"));
+    assert_eq!(fs::read(&file).unwrap(), bytes);
+
+    let out = pngme(&["repair", path_str(&file)]);
+    assert!(out.status.success());
+    assert!(String::from_utf8(out.stdout)
+        .unwrap()
+        .contains("Repaired 1 of 3 chunks"));
+    assert_eq!(fs::read(&file).unwrap(), fs::read(CRAB).unwrap());
+
+    let out = pngme(&["repair", "-n", path_str(&file)]);
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "No problems found\n"
+    );
+}
+
+#[test]
+fn test_encrypted_message() {
+    let dir = TempDir::new().unwrap();
+    let file = scratch_copy(&dir);
+
+    let out = pngme_with_passphrase(
+        &["encode", path_str(&file), "ruSt", "attack at dawn", "-p"],
+        "pw",
+    );
+    assert!(out.status.success());
+    let bytes = fs::read(&file).unwrap();
+    assert!(!bytes.windows(6).any(|w| w == b"attack"));
+
+    let out = pngme_with_passphrase(&["decode", path_str(&file), "ruSt", "--passphrase"], "pw");
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "attack at dawn
+"
+    );
+
+    // Without the variable the passphrase is read from stdin
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pngme"))
+        .args(["decode", path_str(&file), "ruSt", "-p"])
+        .env_remove("PNGME_PASSPHRASE")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"pw\n").unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "attack at dawn\n");
+
+    let out = pngme_with_passphrase(&["decode", path_str(&file), "ruSt", "-p"], "wrong");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8(out.stderr)
+        .unwrap()
+        .contains("wrong passphrase"));
+
+    // The passphrase is never taken as an argument
+    let out = pngme(&["decode", path_str(&file), "ruSt", "-p", "pw"]);
+    assert_eq!(out.status.code(), Some(2));
+    let out = pngme(&["decode", path_str(&file), "ruSt", "-p"]);
+    assert_eq!(out.status.code(), Some(1));
+}
+
+#[test]
+fn test_embed_then_extract() {
+    let dir = TempDir::new().unwrap();
+    let file = scratch_copy(&dir);
+    let output = dir.path().join("out.png");
+
+    let out = pngme_with_passphrase(
+        &[
+            "embed",
+            path_str(&file),
+            "in the pixels",
+            path_str(&output),
+            "--bits",
+            "2",
+            "-p",
+        ],
+        "pw",
+    );
+    assert!(out.status.success());
+    // No chunk was added
+    let listing = String::from_utf8(pngme(&["print", path_str(&output)]).stdout).unwrap();
+    assert!(!listing.contains("ruSt"));
+
+    let out = pngme_with_passphrase(&["extract", path_str(&output), "-b", "2", "-p"], "pw");
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "in the pixels\n");
+}
+
+#[test]
+fn test_split_message() {
+    let dir = TempDir::new().unwrap();
+    let file = scratch_copy(&dir);
+
+    let out = pngme(&[
+        "encode",
+        path_str(&file),
+        "ruSt",
+        "in three pieces",
+        "--parts",
+        "3",
+    ]);
+    assert!(out.status.success());
+    let listing = String::from_utf8(pngme(&["print", path_str(&file)]).stdout).unwrap();
+    assert_eq!(listing.matches("ruSt").count(), 3);
+
+    let out = pngme(&["decode", path_str(&file), "ruSt", "--parts"]);
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "in three pieces\n");
+
+    let out = pngme(&["remove", path_str(&file), "ruSt"]);
+    assert!(out.status.success());
+    let out = pngme(&["decode", path_str(&file), "ruSt", "--parts"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8(out.stderr)
+        .unwrap()
+        .contains("missing parts 0 of 3"));
+}
+
+#[test]
+fn test_frames() {
+    let dir = TempDir::new().unwrap();
+    let frames = dir.path().join("frames");
+
+    let out = pngme(&["frames", "./data/png/animated.png", path_str(&frames)]);
+    assert!(out.status.success());
+    for n in 0..3 {
+        let frame = frames.join(format!("frame_{:03}.png", n));
+        let listing = String::from_utf8(pngme(&["print", path_str(&frame)]).stdout).unwrap();
+        assert!(listing.contains("IHDR"));
+        assert!(!listing.contains("acTL"));
+    }
+    assert!(!frames.join("frame_003.png").exists());
+
+    let out = pngme(&["frames", CRAB, path_str(&frames)]);
+    assert_eq!(out.status.code(), Some(1));
+}
+
+#[test]
+fn test_exif() {
+    let dir = TempDir::new().unwrap();
+    let file = dir.path().join("exif.png");
+    fs::copy("./data/png/exif.png", &file).unwrap();
+
+    let out = pngme(&["exif", path_str(&file)]);
+    assert!(out.status.success());
+    let listing = String::from_utf8(out.stdout).unwrap();
+    assert!(listing.contains("Exif BodySerialNumber: \"032021001234\""));
+    assert!(listing.contains("GPS position: 51.500000, -0.127500"));
+    assert!(listing.contains("Thumbnail: "));
+
+    let out = pngme(&[
+        "exif",
+        path_str(&file),
+        "-r",
+        "GPSLatitude",
+        "-r",
+        "GPSLongitude",
+        "-r",
+        "BodySerialNumber",
+        "--remove-thumbnail",
+    ]);
+    assert!(out.status.success());
+    let listing = String::from_utf8(pngme(&["exif", path_str(&file)]).stdout).unwrap();
+    assert!(listing.contains("Primary Make: \"Canon\""));
+    assert!(!listing.contains("BodySerialNumber"));
+    assert!(!listing.contains("GPS position"));
+    assert!(!listing.contains("Thumbnail"));
+
+    let out = pngme(&["exif", path_str(&file), "-r", "Nonsense"]);
+    assert_eq!(out.status.code(), Some(1));
+    let out = pngme(&["exif", CRAB]);
+    assert_eq!(out.status.code(), Some(1));
+}